@@ -7,7 +7,7 @@ use astro::time::{CalType, Date, julian_day, mn_sidr};
 
 use chrono::{Datelike, DateTime, Timelike, Utc};
 use std::f64::consts::PI;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 /// Returns the separation, in radians, between the given celestial coordinates
 /// (in radians).
@@ -76,6 +76,151 @@ pub fn equatorial_from_alt_az(alt: f64, az: f64, lat: f64, long: f64,
     (ra, dec)
 }
 
+/// Returns the rate (radians per second) at which the field of view rotates
+/// for an alt-az mounted telescope that is tracking.
+/// lat: observer latitude in radians.
+/// az: radians, clockwise from north.
+/// alt: elevation in radians.
+/// The sign of the result changes as the azimuth crosses east or west. The
+/// magnitude grows without bound as `alt` approaches the zenith.
+pub fn field_rotation_rate(lat: f64, az: f64, alt: f64) -> f64 {
+    // Earth's sidereal rotation rate, radians per second.
+    const EARTH_ROTATION_RATE: f64 = 7.2921159e-5;
+    EARTH_ROTATION_RATE * lat.cos() * az.cos() / alt.cos()
+}
+
+/// Standard conditions for refraction().
+pub const STANDARD_TEMPERATURE: f64 = 10.0;  // Celsius.
+pub const STANDARD_PRESSURE: f64 = 1010.0;  // Millibars.
+
+/// Returns the atmospheric refraction, in radians, for an object at the given
+/// true (airless) altitude; the apparent altitude is `alt` plus this amount.
+/// Uses Saemundsson's formula, scaled for temperature and pressure.
+/// alt: true altitude in radians.
+/// temperature: Celsius.
+/// pressure: millibars.
+pub fn refraction(alt: f64, temperature: f64, pressure: f64) -> f64 {
+    // The formula diverges a few degrees below the horizon.
+    let h = alt.to_degrees().max(-1.0);
+    let arcmin = 1.02 / (h + 10.3 / (h + 5.11)).to_radians().tan();
+    let scale = (pressure / STANDARD_PRESSURE) *
+        ((273.0 + STANDARD_TEMPERATURE) / (273.0 + temperature));
+    (arcmin * scale / 60.0).to_radians()
+}
+
+/// Returns (ra, dec) in radians, precessed from the J2000 equinox (as used by
+/// the star catalog and thus by plate solutions) to the equinox of `time`.
+/// Uses the IAU 1976 precession angles (Meeus chapter 21); nutation is ignored.
+/// ra: right ascension in radians.
+/// dec: declination in radians.
+pub fn precess_from_j2000(ra: f64, dec: f64, time: SystemTime) -> (f64, f64) {
+    // Julian centuries since J2000.
+    let t = (julian_day_from_system_time(time) - 2451545.0) / 36525.0;
+    let arcsec = |a: f64| (a / 3600.0).to_radians();
+    let zeta = arcsec((2306.2181 + (0.30188 + 0.017998 * t) * t) * t);
+    let z = arcsec((2306.2181 + (1.09468 + 0.018203 * t) * t) * t);
+    let theta = arcsec((2004.3109 - (0.42665 + 0.041833 * t) * t) * t);
+
+    let a = dec.cos() * (ra + zeta).sin();
+    let b = theta.cos() * dec.cos() * (ra + zeta).cos() - theta.sin() * dec.sin();
+    let c = theta.sin() * dec.cos() * (ra + zeta).cos() + theta.cos() * dec.sin();
+    (limit_to_two_PI(a.atan2(b) + z), c.asin())
+}
+
+/// Returns galactic (l, b) in radians, l in 0..2*PI.
+/// ra: J2000 right ascension in radians.
+/// dec: J2000 declination in radians.
+pub fn galactic_from_equatorial(ra: f64, dec: f64) -> (f64, f64) {
+    // J2000 position of the north galactic pole, and the galactic longitude
+    // of the north celestial pole.
+    let pole_ra = 192.85948_f64.to_radians();
+    let pole_dec = 27.12825_f64.to_radians();
+    let ncp_l = 122.93192_f64.to_radians();
+
+    let b = (dec.sin() * pole_dec.sin() +
+             dec.cos() * pole_dec.cos() * (ra - pole_ra).cos()).asin();
+    let l = ncp_l - (dec.cos() * (ra - pole_ra).sin()).atan2(
+        dec.sin() * pole_dec.cos() - dec.cos() * pole_dec.sin() * (ra - pole_ra).cos());
+    (limit_to_two_PI(l), b)
+}
+
+/// Returns ecliptic (lambda, beta) in radians, lambda in 0..2*PI, for the mean
+/// ecliptic and equinox of J2000.
+/// ra: J2000 right ascension in radians.
+/// dec: J2000 declination in radians.
+pub fn ecliptic_from_equatorial(ra: f64, dec: f64) -> (f64, f64) {
+    // Obliquity of the ecliptic at J2000.
+    let eps = 23.4392911_f64.to_radians();
+    let lambda = (ra.sin() * eps.cos() + dec.tan() * eps.sin()).atan2(ra.cos());
+    let beta = (dec.sin() * eps.cos() - dec.cos() * eps.sin() * ra.sin()).asin();
+    (limit_to_two_PI(lambda), beta)
+}
+
+/// Returns the gnomonic (tangent plane) projection of (ra, dec) about the
+/// tangent point (ra0, dec0), as standard coordinates (xi, eta); xi increases
+/// to the east and eta to the north. Returns None if (ra, dec) is 90 degrees
+/// or more from the tangent point.
+/// Args and return values in radians.
+pub fn tangent_plane_from_equatorial(ra: f64, dec: f64, ra0: f64, dec0: f64)
+                                     -> Option<(f64, f64)> {
+    let cos_c = dec0.sin() * dec.sin() + dec0.cos() * dec.cos() * (ra - ra0).cos();
+    if cos_c <= 0.0 {
+        return None;
+    }
+    let xi = dec.cos() * (ra - ra0).sin() / cos_c;
+    let eta = (dec0.cos() * dec.sin() - dec0.sin() * dec.cos() * (ra - ra0).cos()) / cos_c;
+    Some((xi, eta))
+}
+
+/// Returns the distance, in pixels from the image center, of a point at the
+/// given angular `radius` from the center, for a gnomonic (pinhole) projection
+/// with the given `pixel_angular_size` at the center.
+/// Args in radians.
+pub fn radius_to_pixels(radius: f64, pixel_angular_size: f64) -> f64 {
+    radius.tan() / pixel_angular_size.tan()
+}
+
+/// Returns `roll` (the image's rotation, e.g. SolveResult.roll) rotated
+/// counter-clockwise by `offset`, in the range 0..360.
+/// Args and result in degrees.
+pub fn add_roll_offset(roll: f32, offset: f32) -> f32 {
+    (roll + offset).rem_euclid(360.0)
+}
+
+/// Returns the effective observation time of an exposure that ended at
+/// `readout_time`: the middle of the exposure. For long exposures this
+/// differs meaningfully from `readout_time` when computing positions (e.g.
+/// alt/az) of the sky, which turns at 15 arcsec per second.
+pub fn exposure_midpoint(readout_time: SystemTime, exposure_duration: Duration)
+                         -> SystemTime {
+    readout_time - exposure_duration / 2
+}
+
+/// Returns the lowest usable altitude at azimuth `az`, given a horizon
+/// profile: altitudes at equally spaced azimuths starting at north (0) and
+/// proceeding through east. Altitudes between profile points are linearly
+/// interpolated; an empty profile yields 0.
+/// Args and result in degrees.
+pub fn horizon_altitude(profile: &[f32], az: f64) -> f64 {
+    if profile.is_empty() {
+        return 0.0;
+    }
+    let n = profile.len();
+    let step = 360.0 / n as f64;
+    let pos = az.rem_euclid(360.0) / step;
+    let index = (pos.floor() as usize).min(n - 1);
+    let frac = pos - index as f64;
+    let alt0 = profile[index] as f64;
+    let alt1 = profile[(index + 1) % n] as f64;
+    alt0 + (alt1 - alt0) * frac
+}
+
+fn julian_day_from_system_time(time: SystemTime) -> f64 {
+    let secs = DateTime::<Utc>::from(time).timestamp_millis() as f64 / 1000.0;
+    // The Unix epoch is JD 2440587.5.
+    2440587.5 + secs / 86400.0
+}
+
 fn greenwich_mean_sidereal_time_from_system_time(time: SystemTime) -> f64 {
     let dt_utc = DateTime::<Utc>::from(time);
     let date = Date{year: dt_utc.date_naive().year() as i16,
@@ -161,4 +306,194 @@ mod tests {
                             epsilon = 0.01);
     }
 
+    #[test]
+    fn test_field_rotation_rate() {
+        let lat = 40_f64.to_radians();
+
+        // Looking north at 45 degrees altitude.
+        assert_abs_diff_eq!(field_rotation_rate(lat, 0.0, 45_f64.to_radians()),
+                            7.8999e-5,
+                            epsilon = 1.0e-8);
+        // Looking south, same rate but opposite direction.
+        assert_abs_diff_eq!(field_rotation_rate(lat, PI, 45_f64.to_radians()),
+                            -7.8999e-5,
+                            epsilon = 1.0e-8);
+        // No field rotation due east.
+        assert_abs_diff_eq!(field_rotation_rate(lat, PI / 2.0, 45_f64.to_radians()),
+                            0.0,
+                            epsilon = 1.0e-12);
+        // Much faster close to the zenith.
+        assert!(field_rotation_rate(lat, 0.0, 89_f64.to_radians()).abs() > 1.0e-3);
+    }
+
+    #[test]
+    fn test_refraction() {
+        let arcmin = |alt_deg: f64, temperature: f64, pressure: f64| {
+            refraction(alt_deg.to_radians(), temperature, pressure).to_degrees() * 60.0
+        };
+        // Standard values, arcminutes.
+        assert_abs_diff_eq!(arcmin(10.0, STANDARD_TEMPERATURE, STANDARD_PRESSURE),
+                            5.3, epsilon = 0.15);
+        assert_abs_diff_eq!(arcmin(30.0, STANDARD_TEMPERATURE, STANDARD_PRESSURE),
+                            1.7, epsilon = 0.1);
+        assert_abs_diff_eq!(arcmin(80.0, STANDARD_TEMPERATURE, STANDARD_PRESSURE),
+                            0.17, epsilon = 0.02);
+        // An object whose true altitude is zero appears about half a degree up.
+        assert_abs_diff_eq!(arcmin(0.0, STANDARD_TEMPERATURE, STANDARD_PRESSURE),
+                            29.0, epsilon = 1.0);
+        // Zero at the zenith.
+        assert_abs_diff_eq!(arcmin(90.0, STANDARD_TEMPERATURE, STANDARD_PRESSURE),
+                            0.0, epsilon = 0.01);
+        // Less refraction in thin, warm air.
+        assert!(arcmin(10.0, 30.0, 800.0) <
+                arcmin(10.0, STANDARD_TEMPERATURE, STANDARD_PRESSURE));
+        // Finite below the horizon.
+        assert!(arcmin(-10.0, STANDARD_TEMPERATURE, STANDARD_PRESSURE).is_finite());
+    }
+
+    #[test]
+    fn test_precess_from_j2000() {
+        let time_from_jd = |jd: f64| {
+            SystemTime::UNIX_EPOCH +
+                Duration::from_secs_f64((jd - 2440587.5) * 86400.0)
+        };
+        // At J2000 there is no change.
+        let (ra, dec) = precess_from_j2000(1.0, 0.5, time_from_jd(2451545.0));
+        assert_abs_diff_eq!(ra, 1.0, epsilon = 1.0e-12);
+        assert_abs_diff_eq!(dec, 0.5, epsilon = 1.0e-12);
+
+        // A 25 year baseline, at RA = Dec = 0. The annual precession here is
+        // 3.075s in RA and 20.04" in Dec.
+        let (ra, dec) = precess_from_j2000(
+            0.0, 0.0, time_from_jd(2451545.0 + 25.0 * 365.25));
+        assert_abs_diff_eq!(ra.to_degrees(), 0.3203, epsilon = 2.0 / 3600.0);
+        assert_abs_diff_eq!(dec.to_degrees(), 0.1392, epsilon = 2.0 / 3600.0);
+
+        // Meeus example 21.b: theta Persei (with proper motion already
+        // applied) to 2028 November 13.19.
+        let (ra, dec) = precess_from_j2000(41.054063_f64.to_radians(),
+                                           49.227750_f64.to_radians(),
+                                           time_from_jd(2462088.69));
+        assert_abs_diff_eq!(ra.to_degrees(), 41.547214, epsilon = 1.0 / 3600.0);
+        assert_abs_diff_eq!(dec.to_degrees(), 49.348483, epsilon = 1.0 / 3600.0);
+    }
+
+    #[test]
+    fn test_galactic_from_equatorial() {
+        // Galactic center: RA 17h45m37.2s, Dec -28d56m10s.
+        let (l, b) = galactic_from_equatorial(deg_frm_hms(17, 45, 37.2).to_radians(),
+                                              (-deg_frm_dms(28, 56, 10.0)).to_radians());
+        let mut l = l.to_degrees();
+        if l > 180.0 {
+            l -= 360.0;
+        }
+        assert_abs_diff_eq!(l, 0.0, epsilon = 0.01);
+        assert_abs_diff_eq!(b.to_degrees(), 0.0, epsilon = 0.01);
+
+        // North galactic pole.
+        let (_l, b) = galactic_from_equatorial(192.85948_f64.to_radians(),
+                                               27.12825_f64.to_radians());
+        assert_abs_diff_eq!(b.to_degrees(), 90.0, epsilon = 1.0e-6);
+
+        // North celestial pole.
+        let (l, b) = galactic_from_equatorial(0.0, PI / 2.0);
+        assert_abs_diff_eq!(l.to_degrees(), 122.93192, epsilon = 1.0e-6);
+        assert_abs_diff_eq!(b.to_degrees(), 27.12825, epsilon = 1.0e-6);
+    }
+
+    #[test]
+    fn test_ecliptic_from_equatorial() {
+        // Vernal equinox.
+        let (lambda, beta) = ecliptic_from_equatorial(0.0, 0.0);
+        assert_abs_diff_eq!(lambda, 0.0, epsilon = 1.0e-9);
+        assert_abs_diff_eq!(beta, 0.0, epsilon = 1.0e-9);
+
+        // Summer solstice.
+        let (lambda, beta) = ecliptic_from_equatorial(PI / 2.0,
+                                                      23.4392911_f64.to_radians());
+        assert_abs_diff_eq!(lambda.to_degrees(), 90.0, epsilon = 1.0e-6);
+        assert_abs_diff_eq!(beta.to_degrees(), 0.0, epsilon = 1.0e-6);
+
+        // Meeus example 13.a: Pollux.
+        let (lambda, beta) = ecliptic_from_equatorial(116.328942_f64.to_radians(),
+                                                      28.026183_f64.to_radians());
+        assert_abs_diff_eq!(lambda.to_degrees(), 113.215630, epsilon = 1.0e-5);
+        assert_abs_diff_eq!(beta.to_degrees(), 6.684170, epsilon = 1.0e-5);
+    }
+
+    #[test]
+    fn test_tangent_plane_from_equatorial() {
+        let ra0 = 1.0;
+        let dec0 = 0.5;
+        let (xi, eta) = tangent_plane_from_equatorial(ra0, dec0, ra0, dec0).unwrap();
+        assert_abs_diff_eq!(xi, 0.0, epsilon = 1.0e-12);
+        assert_abs_diff_eq!(eta, 0.0, epsilon = 1.0e-12);
+
+        // Due north.
+        let (xi, eta) = tangent_plane_from_equatorial(ra0, dec0 + 0.1, ra0, dec0).unwrap();
+        assert_abs_diff_eq!(xi, 0.0, epsilon = 1.0e-12);
+        assert_abs_diff_eq!(eta, 0.1_f64.tan(), epsilon = 1.0e-12);
+
+        // Due east, on the equator.
+        let (xi, eta) = tangent_plane_from_equatorial(ra0 + 0.1, 0.0, ra0, 0.0).unwrap();
+        assert_abs_diff_eq!(xi, 0.1_f64.tan(), epsilon = 1.0e-12);
+        assert_abs_diff_eq!(eta, 0.0, epsilon = 1.0e-12);
+
+        // Opposite hemisphere.
+        assert!(tangent_plane_from_equatorial(ra0 + PI, 0.0, ra0, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_radius_to_pixels() {
+        let pixel_size = (10.0_f64 / 3600.0).to_radians();  // 10 arcsec.
+        // Small angles are nearly linear.
+        assert_abs_diff_eq!(radius_to_pixels(pixel_size * 100.0, pixel_size),
+                            100.0, epsilon = 1.0e-3);
+        // Wide angles are stretched by the projection.
+        assert_abs_diff_eq!(radius_to_pixels(0.5, 0.01),
+                            0.5_f64.tan() / 0.01_f64.tan(), epsilon = 1.0e-9);
+        assert!(radius_to_pixels(0.5, 0.01) > 50.0);
+    }
+
+    #[test]
+    fn test_add_roll_offset() {
+        assert_abs_diff_eq!(add_roll_offset(10.0, 0.0), 10.0);
+        assert_abs_diff_eq!(add_roll_offset(10.0, 45.0), 55.0);
+        assert_abs_diff_eq!(add_roll_offset(350.0, 20.0), 10.0, epsilon = 1.0e-4);
+        assert_abs_diff_eq!(add_roll_offset(-30.0, 10.0), 340.0, epsilon = 1.0e-4);
+        assert_abs_diff_eq!(add_roll_offset(0.0, 359.5), 359.5);
+    }
+
+    #[test]
+    fn test_exposure_midpoint() {
+        let readout_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let midpoint = exposure_midpoint(readout_time, Duration::from_secs(10));
+        assert_eq!(midpoint, readout_time - Duration::from_secs(5));
+        assert_eq!(exposure_midpoint(readout_time, Duration::ZERO), readout_time);
+
+        // An object's position computed at the midpoint trails the position
+        // at readout by the sky's rotation over half the exposure.
+        let lat = 40_f64.to_radians();
+        let long = -75_f64.to_radians();
+        let (_, _, ha_readout) = alt_az_from_equatorial(1.0, 0.3, lat, long, readout_time);
+        let (_, _, ha_midpoint) = alt_az_from_equatorial(1.0, 0.3, lat, long, midpoint);
+        // 5 sidereal seconds of hour angle.
+        assert_abs_diff_eq!((ha_readout - ha_midpoint).to_degrees(),
+                            5.0 * 15.0 * 1.00273790935 / 3600.0, epsilon = 1.0e-6);
+    }
+
+    #[test]
+    fn test_horizon_altitude() {
+        assert_eq!(horizon_altitude(&[], 123.0), 0.0);
+        assert_eq!(horizon_altitude(&[15.0], 200.0), 15.0);
+        // N, E, S, W.
+        let profile = [10.0, 30.0, 0.0, 20.0];
+        assert_abs_diff_eq!(horizon_altitude(&profile, 0.0), 10.0, epsilon = 1.0e-9);
+        assert_abs_diff_eq!(horizon_altitude(&profile, 45.0), 20.0, epsilon = 1.0e-9);
+        assert_abs_diff_eq!(horizon_altitude(&profile, 135.0), 15.0, epsilon = 1.0e-9);
+        // Wraps from west back to north.
+        assert_abs_diff_eq!(horizon_altitude(&profile, 315.0), 15.0, epsilon = 1.0e-9);
+        assert_abs_diff_eq!(horizon_altitude(&profile, -45.0), 15.0, epsilon = 1.0e-9);
+    }
+
 }  // mod tests.
@@ -3,53 +3,93 @@
 
 use std::fs;
 use std::io;
-use std::io::{Cursor, Read, Seek, SeekFrom};
-use std::net::SocketAddr;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr};
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::process::Command;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant, SystemTime};
 
 use cedar_camera::abstract_camera::{AbstractCamera, Offset, bin_2x2, sample_2x2};
 use cedar_camera::select_camera::{CameraInterface, select_camera};
 use cedar_camera::image_camera::ImageCamera;
-use canonical_error::{CanonicalError, CanonicalErrorCode};
+use canonical_error::{CanonicalError, CanonicalErrorCode, deadline_exceeded_error,
+                      failed_precondition_error};
 use chrono::offset::Local;
-use image::{GrayImage, ImageFormat};
-use image::io::Reader as ImageReader;
+use image::{GrayImage, Luma};
 
 use nix::time::{ClockId, clock_gettime, clock_settime};
 use nix::sys::time::TimeSpec;
 
 use clap::Parser;
 use axum::Router;
+use axum::http::StatusCode;
+use axum::routing::get;
 use log::{debug, error, info, warn};
 use prost::Message;
 use tower_http::{services::ServeDir, cors::CorsLayer, cors::Any};
 use tonic_web::GrpcWebLayer;
 
 use tracing_subscriber::prelude::*;
-use tracing_subscriber::{fmt, registry, EnvFilter};
+use tracing_subscriber::{fmt, registry, reload, EnvFilter, Registry};
 use tracing_appender::{non_blocking::NonBlockingBuilder};
 
-use futures::join;
+use futures::{join, Stream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::ReceiverStream;
 
-use cedar_server::astro_util::{alt_az_from_equatorial, equatorial_from_alt_az, position_angle};
+use cedar_server::astro_util::{add_roll_offset, alt_az_from_equatorial,
+                               angular_separation,
+                               ecliptic_from_equatorial, equatorial_from_alt_az,
+                               field_rotation_rate, galactic_from_equatorial,
+                               exposure_midpoint, horizon_altitude, position_angle,
+                               precess_from_j2000, radius_to_pixels,
+                               refraction, STANDARD_PRESSURE, STANDARD_TEMPERATURE};
 use cedar_server::cedar::cedar_server::{Cedar, CedarServer};
-use cedar_server::cedar::{Accuracy, ActionRequest, CalibrationData, CelestialCoordFormat,
-                          EmptyMessage, FixedSettings, FrameRequest, FrameResult,
-                          Image, ImageCoord, LatLong, LocationBasedInfo, MountType,
-                          OperatingMode, OperationSettings, ProcessingStats, Rectangle,
+use cedar_server::cedar::{Accuracy, ActionRequest, AlternateCoords,
+                          CalibrationData, CalibrationStep,
+                          CameraInformation, CedarEvent, CelestialCoordFormat,
+                          CelestialCoordSystem, DisplayImageFormat, EmptyMessage, EventType,
+                          FixedSettings, FrameRequest, FrameResult, GotoHistory, GotoTarget,
+                          Image, ImageCoord,
+                          LatLong, LocationBasedInfo, LogChunk, LogDownloadRequest,
+                          LogLevel, MountType, NearestStar,
+                          OperatingMode, OperationSettings, PierSide, PreferencesJson,
+                          ProcessingStats,
+                          Rectangle,
                           StarCentroid, Preferences, ServerInformationRequest,
-                          ServerInformationResult};
+                          SavedCalibration, ServerInformationResult, SyncPoint,
+                          SyncPointList,
+                          ThroughputTestRequest, ThroughputTestResult};
 use ::cedar_server::calibrator::Calibrator;
-use ::cedar_server::detect_engine::{DetectEngine, DetectResult};
-use ::cedar_server::scale_image::scale_image;
+use ::cedar_server::dark_frame::{DarkFrameLibrary, median_dark_frame};
+use ::cedar_server::detect_engine::{CameraOpener, DEFAULT_CENTER_REGION_FRACTION,
+                                    DetectEngine, DetectResult};
+use ::cedar_server::display_image::encode_display_image;
+use ::cedar_server::flat_field::{FlatField, save_flat_field};
+use ::cedar_server::format_util::{format_duration, format_gain};
+use ::cedar_server::hot_pixels::{HotPixelLibrary, find_hot_pixels};
+use ::cedar_server::image_file::load_gray_image;
+use ::cedar_server::frame_recorder::FrameRecorder;
+use ::cedar_server::gps::{NmeaReport, run_gps_reader};
+use ::cedar_server::scale_image::{coarse_histogram, scale_image};
 use ::cedar_server::solve_engine::{PlateSolution, SolveEngine};
 use ::cedar_server::position_reporter::{TelescopePosition, create_alpaca_server};
+use ::cedar_server::indi_server::run_indi_server;
+use ::cedar_server::metrics::{MetricsSnapshot, format_metrics};
+use ::cedar_server::preferences_json::{preferences_from_json, preferences_to_json};
+use ::cedar_server::spiral_search::SpiralSearchAdvisor;
+use ::cedar_server::star_names::star_name;
+use ::cedar_server::stellarium_server::run_stellarium_server;
+use ::cedar_server::throughput_test::throughput_test_response;
+use ::cedar_server::track_recorder::TrackRecorder;
 use ::cedar_server::motion_estimator::MotionEstimator;
 use ::cedar_server::polar_analyzer::PolarAnalyzer;
+use ::cedar_server::drift_align::DriftAlignAssistant;
 use ::cedar_server::tetra3_subprocess::Tetra3Subprocess;
 use ::cedar_server::value_stats::ValueStatsAccumulator;
 use ::cedar_server::tetra3_server;
@@ -57,6 +97,48 @@ use ::cedar_server::tetra3_server::{CelestialCoord, SolveResult as SolveResultPr
 
 use self::multiplex_service::MultiplexService;
 
+// Applies when OperationSettings.display_image_format is JPEG.
+const DISPLAY_JPEG_QUALITY: u8 = 95;
+
+// Spiral search guidance is given after this many consecutive failed solves
+// in OPERATE mode.
+const SPIRAL_SEARCH_FAILURE_THRESHOLD: usize = 5;
+const SPIRAL_SEARCH_WAYPOINTS: usize = 24;
+
+// Number of bins in FrameResult.histogram.
+const HISTOGRAM_BINS: usize = 64;
+
+// While thermal throttling, OPERATE mode updates at most this often.
+const THERMAL_THROTTLE_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// Throttling ends once the CPU temperature is this far below the limit.
+const THERMAL_THROTTLE_HYSTERESIS: f32 = 5.0;  // Degrees Celsius.
+const THERMAL_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// While the camera is reconnecting, get_next_frame() returns at this interval
+// rather than waiting for a new frame.
+const CAMERA_RECONNECTING_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// During calibration, get_next_frame() returns at once with the progress
+// rather than waiting for a new frame; StreamFrames sends such updates at this
+// interval.
+const STREAM_CALIBRATING_INTERVAL: Duration = Duration::from_millis(500);
+
+// On exit, pending preferences are written if the state lock can be had
+// within EXIT_FLUSH_ATTEMPTS tries at this interval.
+const EXIT_FLUSH_ATTEMPTS: u32 = 20;
+const EXIT_FLUSH_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+// Maps the --camera_interface argument to select_camera()'s parameter; None if
+// not recognized.
+fn parse_camera_interface(arg: &str) -> Option<Option<CameraInterface>> {
+    match arg {
+        "" => Some(None),
+        "asi" => Some(Some(CameraInterface::ASI)),
+        "rpi" => Some(Some(CameraInterface::Rpi)),
+        _ => None,
+    }
+}
+
 fn tonic_status(canonical_error: CanonicalError) -> tonic::Status {
     tonic::Status::new(
         match canonical_error.code {
@@ -88,12 +170,45 @@ struct MyCedar {
 
     preferences_file: PathBuf,
 
+    // Determined at startup.
+    preferences_read_only: bool,
+
+    // Preferences file writes are deferred by this much, so that a burst of
+    // updates results in a single write.
+    preferences_write_delay: Duration,
+
+    // Where `sync_points` (in CedarState) are persisted.
+    sync_points_file: PathBuf,
+
+    // Upper limit on ThroughputTestRequest.payload and response_size.
+    max_throughput_test_bytes: usize,
+
+    // Number of exposures combined by the `capture_dark_frame` action.
+    dark_frame_count: usize,
+
+    // Where the CPU temperature (millidegrees Celsius) is read from.
+    cpu_temp_path: PathBuf,
+
+    // Determined at startup; "unknown" if not available.
+    processor_model: String,
+    serial_number: String,
+
     // The path to our log file.
     log_file: PathBuf,
+
+    // Allows the logging filter to be changed at runtime.
+    log_filter_handle: reload::Handle<EnvFilter, Registry>,
 }
 
 struct CedarState {
     camera: Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>,
+    // Determined at startup. If false, we don't attempt to set or calibrate
+    // the camera's offset.
+    camera_supports_offset: bool,
+    // True if no camera was found and `camera` serves a blank image.
+    no_camera: bool,
+    // See DetectEngine::camera_reconnecting().
+    camera_reconnecting: Arc<AtomicBool>,
     fixed_settings: Arc<Mutex<FixedSettings>>,
     calibration_data: Arc<tokio::sync::Mutex<CalibrationData>>,
     operation_settings: OperationSettings,
@@ -103,6 +218,7 @@ struct CedarState {
     calibrator: Arc<tokio::sync::Mutex<Calibrator>>,
     telescope_position: Arc<Mutex<TelescopePosition>>,
     polar_analyzer: Arc<Mutex<PolarAnalyzer>>,
+    drift_align: Arc<Mutex<DriftAlignAssistant>>,
 
     // See "About Resolutions" below.
     // Whether (and how much, 2x2 or 4x4) the acquired image is binned prior to
@@ -116,6 +232,18 @@ struct CedarState {
     // operation; we reflect them out to all clients and persist them to a
     // server-side file.
     preferences: Preferences,
+    // The encoded preferences most recently written to the preferences file,
+    // used to skip writes of unchanged content.
+    written_preferences: Vec<u8>,
+    // Set while a deferred preferences file write is scheduled.
+    preferences_write_pending: bool,
+
+    // Stars fainter than this are not considered for
+    // FrameResult.nearest_bright_star.
+    bright_star_magnitude: f32,
+
+    // Accumulated by the `capture_sync_point` action.
+    sync_points: SyncPointList,
 
     // This is the most recent display image returned by get_frame().
     scaled_image: Option<Arc<GrayImage>>,
@@ -130,12 +258,174 @@ struct CedarState {
     // Relevant only if calibration is underway (`calibration_image` is present).
     calibration_start: Instant,
     calibration_duration_estimate: Duration,
+    // Upper limit on calibration time; see calibrate().
+    max_calibration_time: Duration,
+    // OPERATE mode solve timeout derived from the calibration's plate solve
+    // duration, before applying OperationSettings.max_solve_time. None if
+    // there was no calibration solve.
+    calibrated_solve_timeout: Option<Duration>,
+
+    // Successful calibrations are saved here.
+    calibration_file: PathBuf,
+    // Calibration loaded from `calibration_file` at startup, if it matches the
+    // attached camera. Consumed by the first calibrate() call in place of
+    // calibrating from scratch.
+    saved_calibration: Option<CalibrationData>,
+    // Set once we've warned about a plate solution disagreeing with
+    // FixedSettings.fov_override.
+    fov_override_warned: bool,
+
+    // Lower limit applied by set_update_interval(). Nonzero when a static
+    // test image is used in place of a camera, since there is no point in
+    // re-processing the identical image at a high rate.
+    min_update_interval: Duration,
 
     // For boresight capturing.
     center_peak_position: Arc<Mutex<Option<ImageCoord>>>,
 
     serve_latency_stats: ValueStatsAccumulator,
     overall_latency_stats: ValueStatsAccumulator,
+
+    // For the /metrics endpoint. Interval between successive frames, the
+    // frame_id and readout time of the most recently served frame, and the
+    // most recently served frame's ProcessingStats.
+    frame_interval_stats: ValueStatsAccumulator,
+    last_served_frame: Option<(i32, SystemTime)>,
+    latest_processing_stats: Option<ProcessingStats>,
+
+    // Events are published here; each GetEvents stream holds a receiver.
+    event_sender: broadcast::Sender<CedarEvent>,
+
+    // Shared with the solution callback.
+    reacquisition: Arc<Mutex<ReacquisitionDetector>>,
+    dwell_detector: Arc<Mutex<DwellDetector>>,
+    spiral_search: Arc<Mutex<SpiralSearchAdvisor>>,
+    track_recorder: Arc<Mutex<TrackRecorder>>,
+
+    // Shared with DetectEngine. None if the dark frame directory is unusable.
+    dark_frames: Option<Arc<Mutex<DarkFrameLibrary>>>,
+    hot_pixels: Option<Arc<Mutex<HotPixelLibrary>>>,
+
+    // Also holds flat fields.
+    dark_frame_dir: PathBuf,
+
+    // Whether OperationSettings.dwell_update_interval (rather than
+    // `update_interval`) is currently applied. See get_next_frame().
+    dwell_applied: bool,
+
+    // Whether the CPU is over its temperature limit. See start_thermal_monitor().
+    thermal_throttling: bool,
+}
+
+// Detects the first successful plate solution following a solve outage (e.g.
+// clouds), so that derived state can be refreshed and clients notified.
+struct ReacquisitionDetector {
+    // A gap between successful solves longer than this is an outage.
+    outage_threshold: Duration,
+
+    // Readout time of the most recent successful solve.
+    last_solve_time: Option<SystemTime>,
+
+    // The frame_id of the most recent reacquisition.
+    reacquired_frame_id: Option<i32>,
+}
+
+impl ReacquisitionDetector {
+    fn new(outage_threshold: Duration) -> Self {
+        ReacquisitionDetector{outage_threshold,
+                              last_solve_time: None,
+                              reacquired_frame_id: None}
+    }
+
+    // Forget solve history, e.g. when solving is stopped for SETUP mode. The
+    // next solve will not be treated as a reacquisition.
+    fn reset(&mut self) {
+        self.last_solve_time = None;
+    }
+
+    // Called for each successful solve. Returns true if this solve ends an
+    // outage.
+    fn solved(&mut self, readout_time: SystemTime, frame_id: i32) -> bool {
+        let prev_solve_time = self.last_solve_time.replace(readout_time);
+        let reacquired = match prev_solve_time {
+            Some(prev) => match readout_time.duration_since(prev) {
+                Ok(gap) => gap > self.outage_threshold,
+                Err(_) => false,
+            },
+            None => false,
+        };
+        if reacquired {
+            self.reacquired_frame_id = Some(frame_id);
+        }
+        reacquired
+    }
+}
+
+// Determines when the boresight is dwelling, i.e. has been stationary for a
+// while, as when a visual observer is parked on an object. Optionally logs the
+// position at the onset of each dwell.
+struct DwellDetector {
+    // How long the boresight must be stationary to be dwelling.
+    dwell_threshold: Duration,
+
+    // Readout time at which the boresight became stationary.
+    stationary_since: Option<SystemTime>,
+
+    dwelling: bool,
+
+    // Dwelled positions are appended here when `log_enabled`.
+    log_file: PathBuf,
+    log_enabled: bool,
+}
+
+impl DwellDetector {
+    fn new(dwell_threshold: Duration, log_file: PathBuf) -> Self {
+        DwellDetector{dwell_threshold,
+                      stationary_since: None,
+                      dwelling: false,
+                      log_file,
+                      log_enabled: false}
+    }
+
+    fn reset(&mut self) {
+        self.stationary_since = None;
+        self.dwelling = false;
+    }
+
+    // Called for each solve cycle. `stationary` indicates whether the motion
+    // estimator considers the boresight to be motionless. Returns true at the
+    // onset of a dwell.
+    fn update(&mut self, readout_time: SystemTime, stationary: bool) -> bool {
+        if !stationary {
+            self.reset();
+            return false;
+        }
+        let since = *self.stationary_since.get_or_insert(readout_time);
+        if self.dwelling {
+            return false;
+        }
+        match readout_time.duration_since(since) {
+            Ok(elapsed) if elapsed >= self.dwell_threshold => {
+                self.dwelling = true;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Appends a line "time,ra,dec,rmse" to `log_file`.
+    fn log_position(&self, readout_time: SystemTime, coords: &CelestialCoord,
+                    rmse: Option<f32>) {
+        let time: chrono::DateTime<Local> = readout_time.into();
+        let line = format!("{},{:.4},{:.4},{}\n", time.to_rfc3339(), coords.ra, coords.dec,
+                           rmse.map_or(String::new(), |r| format!("{:.2}", r)));
+        let result = fs::OpenOptions::new().create(true).append(true)
+            .open(&self.log_file)
+            .and_then(|mut f| f.write_all(line.as_bytes()));
+        if let Err(e) = result {
+            warn!("Could not log dwelled position to {:?}: {:?}", self.log_file, e);
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -155,6 +445,23 @@ impl Cedar for MyCedar {
             }
             response.log_content = Some(tail.unwrap());
         }
+        {
+            let locked_state = self.state.lock().await;
+            let locked_camera = locked_state.camera.lock().await;
+            response.camera = Some(CameraInformation{
+                model: locked_camera.model().to_string(),
+                width: locked_state.width as i32,
+                height: locked_state.height as i32,
+                supports_offset: locked_state.camera_supports_offset,
+                no_camera: locked_state.no_camera,
+            });
+            response.thermal_throttling = locked_state.thermal_throttling;
+        }
+        response.preferences_read_only = self.preferences_read_only;
+        response.processor_model = self.processor_model.clone();
+        response.serial_number = self.serial_number.clone();
+        response.cpu_temperature = Self::read_cpu_temperature(&self.cpu_temp_path);
+        response.preference_profiles = self.list_preference_profiles();
 
         Ok(tonic::Response::new(response))
     }
@@ -164,11 +471,13 @@ impl Cedar for MyCedar {
         -> Result<tonic::Response<FixedSettings>, tonic::Status>
     {
         let req: FixedSettings = request.into_inner();
-        let locked_state = self.state.lock().await;
+        let mut locked_state = self.state.lock().await;
         if let Some(observer_location) = req.observer_location {
             locked_state.fixed_settings.lock().unwrap().observer_location =
                 Some(observer_location.clone());
             info!("Updated observer location to {:?}", observer_location);
+            locked_state.preferences.observer_location = Some(observer_location);
+            self.write_preferences_file(&mut locked_state);
         }
         if let Some(current_time) = req.current_time {
             let current_time = TimeSpec::new(current_time.seconds, current_time.nanos as i64);
@@ -200,6 +509,28 @@ impl Cedar for MyCedar {
             return Err(tonic::Status::unimplemented(
                 "rpc UpdateFixedSettings cannot update max_exposure_time."));
         }
+        if let Some(_fov_override) = req.fov_override {
+            return Err(tonic::Status::invalid_argument(
+                "rpc UpdateFixedSettings cannot update fov_override."));
+        }
+        if let Some(temperature) = req.temperature {
+            if !(-60.0..=60.0).contains(&temperature) {
+                return Err(tonic::Status::invalid_argument(
+                    format!("Unreasonable temperature {}.", temperature)));
+            }
+            locked_state.fixed_settings.lock().unwrap().temperature = Some(temperature);
+        }
+        if let Some(pressure) = req.pressure {
+            if !(300.0..=1100.0).contains(&pressure) {
+                return Err(tonic::Status::invalid_argument(
+                    format!("Unreasonable pressure {}.", pressure)));
+            }
+            locked_state.fixed_settings.lock().unwrap().pressure = Some(pressure);
+        }
+        if let Some(refraction_correction) = req.refraction_correction {
+            locked_state.fixed_settings.lock().unwrap().refraction_correction =
+                Some(refraction_correction);
+        }
         let mut fixed_settings = locked_state.fixed_settings.lock().unwrap().clone();
         // Fill in our current time.
         Self::fill_in_time(&mut fixed_settings);
@@ -224,6 +555,7 @@ impl Cedar for MyCedar {
                     // Transition: OPERATE -> SETUP mode.
 
                     // In SETUP mode we run at full speed.
+                    locked_state.dwell_applied = false;
                     if let Err(x) = Self::set_update_interval(
                         &*locked_state, Duration::ZERO).await
                     {
@@ -238,6 +570,10 @@ impl Cedar for MyCedar {
                         true, locked_state.binning);
                     locked_state.operation_settings.operating_mode =
                         Some(OperatingMode::Setup as i32);
+                    Self::publish_event(&locked_state.event_sender, CedarEvent{
+                        event_type: EventType::ModeChanged.into(),
+                        operating_mode: Some(OperatingMode::Setup.into()),
+                        ..Default::default()});
                 }
             } else if new_operating_mode == OperatingMode::Operate as i32 {
                 let locked_state = self.state.lock().await;
@@ -275,6 +611,9 @@ impl Cedar for MyCedar {
                                 locked_state.calibration_data.lock().await.calibration_time =
                                     Some(prost_types::Timestamp::try_from(
                                         SystemTime::now()).unwrap());
+                                Self::publish_event(&locked_state.event_sender, CedarEvent{
+                                    event_type: EventType::CalibrationStarted.into(),
+                                    ..Default::default()});
                             }
                             // No locks held.
                             let cal_result = Self::calibrate(state.clone(), solve_timeout).await;
@@ -288,7 +627,13 @@ impl Cedar for MyCedar {
                             if *locked_state.cancel_calibration.lock().unwrap() {
                                 // Calibration was cancelled. Stay in Setup mode.
                                 *locked_state.cancel_calibration.lock().unwrap() = false;
+                                Self::publish_event(&locked_state.event_sender, CedarEvent{
+                                    event_type: EventType::CalibrationAborted.into(),
+                                    ..Default::default()});
                             } else {
+                                Self::publish_event(&locked_state.event_sender, CedarEvent{
+                                    event_type: EventType::CalibrationFinished.into(),
+                                    ..Default::default()});
                                 // Transition into Operate mode.
                                 locked_state.detect_engine.lock().await.set_focus_mode(
                                     false, locked_state.binning);
@@ -303,6 +648,10 @@ impl Cedar for MyCedar {
                                     locked_state.operation_settings.operating_mode =
                                         Some(OperatingMode::Operate as i32);
                                 }
+                                Self::publish_event(&locked_state.event_sender, CedarEvent{
+                                    event_type: EventType::ModeChanged.into(),
+                                    operating_mode: Some(OperatingMode::Operate.into()),
+                                    ..Default::default()});
                                 if let Err(x) = Self::set_update_interval(
                                     &*locked_state, std_duration).await
                                 {
@@ -344,26 +693,115 @@ impl Cedar for MyCedar {
                 return Err(tonic::Status::invalid_argument(
                     format!("Got negative update_interval: {}.", update_interval)));
             }
-            let std_duration = std::time::Duration::try_from(
-                update_interval.clone()).unwrap();
             let mut locked_state = self.state.lock().await;
+            locked_state.operation_settings.update_interval = Some(update_interval);
+        }
+        if let Some(dwell_update_interval) = req.dwell_update_interval {
+            if dwell_update_interval.seconds < 0 || dwell_update_interval.nanos < 0 {
+                return Err(tonic::Status::invalid_argument(
+                    format!("Got negative dwell_update_interval: {}.",
+                            dwell_update_interval)));
+            }
+            let mut locked_state = self.state.lock().await;
+            locked_state.operation_settings.dwell_update_interval =
+                Some(dwell_update_interval);
+        }
+        if req.update_interval.is_some() || req.dwell_update_interval.is_some() {
+            let locked_state = self.state.lock().await;
             if locked_state.operation_settings.operating_mode ==
                 Some(OperatingMode::Operate as i32)
             {
-                if let Err(x) = Self::set_update_interval(&*locked_state,
-                                                          std_duration).await {
+                if let Err(x) = Self::set_update_interval(
+                    &*locked_state, Self::operate_update_interval(&locked_state)).await
+                {
                     return Err(tonic_status(x));
                 }
             }
-            locked_state.operation_settings.update_interval = Some(update_interval);
         }
-        if let Some(_dwell_update_interval) = req.dwell_update_interval {
-            return Err(tonic::Status::unimplemented(
-                "rpc UpdateOperationSettings not implemented for dwell_update_interval."));
+        if let Some(max_solve_time) = req.max_solve_time {
+            if max_solve_time.seconds < 0 || max_solve_time.nanos < 0 ||
+                (max_solve_time.seconds == 0 && max_solve_time.nanos == 0)
+            {
+                return Err(tonic::Status::invalid_argument(
+                    format!("max_solve_time must be positive; got {}.", max_solve_time)));
+            }
+            let mut locked_state = self.state.lock().await;
+            locked_state.operation_settings.max_solve_time = Some(max_solve_time);
+            let solve_timeout = Self::operate_solve_timeout(&locked_state);
+            if let Err(x) = locked_state.solve_engine.lock().await.set_solve_timeout(
+                solve_timeout)
+            {
+                return Err(tonic_status(x));
+            }
         }
-        if let Some(_log_dwelled_positions) = req.log_dwelled_positions {
-            return Err(tonic::Status::unimplemented(
-                "rpc UpdateOperationSettings not implemented for log_dwelled_positions."));
+        if let Some(center_region_fraction) = req.center_region_fraction {
+            if !(0.1..=1.0).contains(&center_region_fraction) {
+                return Err(tonic::Status::invalid_argument(
+                    format!("center_region_fraction must be in [0.1, 1.0]; got {}.",
+                            center_region_fraction)));
+            }
+            let mut locked_state = self.state.lock().await;
+            locked_state.detect_engine.lock().await.set_center_region_fraction(
+                center_region_fraction);
+            locked_state.operation_settings.center_region_fraction =
+                Some(center_region_fraction);
+        }
+        if let Some(log_dwelled_positions) = req.log_dwelled_positions {
+            let mut locked_state = self.state.lock().await;
+            locked_state.dwell_detector.lock().unwrap().log_enabled = log_dwelled_positions;
+            locked_state.operation_settings.log_dwelled_positions =
+                Some(log_dwelled_positions);
+        }
+        if let Some(auto_save_images) = req.auto_save_images {
+            let mut locked_state = self.state.lock().await;
+            locked_state.solve_engine.lock().await.set_auto_save_images(auto_save_images);
+            locked_state.operation_settings.auto_save_images = Some(auto_save_images);
+        }
+        if let Some(auto_exposure) = req.auto_exposure {
+            let mut locked_state = self.state.lock().await;
+            locked_state.solve_engine.lock().await.set_exposure_rebase(auto_exposure);
+            locked_state.operation_settings.auto_exposure = Some(auto_exposure);
+        }
+        if let Some(binning) = req.binning {
+            if ![1, 2, 4].contains(&binning) {
+                return Err(tonic::Status::invalid_argument(
+                    format!("binning must be 1, 2, or 4; got {}.", binning)));
+            }
+            let mut locked_state = self.state.lock().await;
+            if binning as u32 != locked_state.binning {
+                if locked_state.operation_settings.operating_mode !=
+                    Some(OperatingMode::Setup as i32) || locked_state.calibrating
+                {
+                    return Err(tonic::Status::failed_precondition(
+                        "binning can only be changed in Setup mode."));
+                }
+                info!("Changing binning from {} to {}", locked_state.binning, binning);
+                locked_state.binning = binning as u32;
+                locked_state.detect_engine.lock().await.set_focus_mode(
+                    true, binning as u32);
+                locked_state.operation_settings.binning = Some(binning);
+                locked_state.scaled_image = None;
+                // Calibration depends on binning.
+                locked_state.saved_calibration = None;
+            }
+        }
+        if let Some(display_sampling) = req.display_sampling {
+            let mut locked_state = self.state.lock().await;
+            if display_sampling != locked_state.display_sampling {
+                locked_state.display_sampling = display_sampling;
+                locked_state.operation_settings.display_sampling = Some(display_sampling);
+                locked_state.scaled_image = None;
+            }
+        }
+        if let Some(display_image_format) = req.display_image_format {
+            if DisplayImageFormat::try_from(display_image_format).is_err() {
+                return Err(tonic::Status::invalid_argument(
+                    format!("Got invalid display_image_format: {}.",
+                            display_image_format)));
+            }
+            let mut locked_state = self.state.lock().await;
+            locked_state.operation_settings.display_image_format =
+                Some(display_image_format);
         }
 
         Ok(tonic::Response::new(self.state.lock().await.operation_settings.clone()))
@@ -392,39 +830,265 @@ impl Cedar for MyCedar {
         if let Some(mount_type) = req.mount_type {
             locked_state.preferences.mount_type = Some(mount_type);
         }
-
-        // Write updated preferences to file.
-        let prefs_path = Path::new(&self.preferences_file);
-        let scratch_path = prefs_path.with_extension("tmp");
-
-        let mut buf = vec![];
-        if let Err(e) = locked_state.preferences.encode(&mut buf) {
-            warn!("Could not encode preferences: {:?}", e);
-            return Ok(tonic::Response::new(locked_state.preferences.clone()));
+        if let Some(camera_mount_angle) = req.camera_mount_angle {
+            if !(0.0..360.0).contains(&camera_mount_angle) {
+                return Err(tonic::Status::invalid_argument(
+                    format!("camera_mount_angle must be in [0, 360); got {}.",
+                            camera_mount_angle)));
+            }
+            locked_state.preferences.camera_mount_angle = Some(camera_mount_angle);
         }
-        if let Err(e) = fs::write(&scratch_path, buf) {
-            warn!("Could not write file: {:?}", e);
-            return Ok(tonic::Response::new(locked_state.preferences.clone()));
+        if let Some(dark_frame_subtraction) = req.dark_frame_subtraction {
+            locked_state.detect_engine.lock().await.set_dark_frame_subtraction(
+                dark_frame_subtraction);
+            locked_state.preferences.dark_frame_subtraction = Some(dark_frame_subtraction);
         }
-        if let Err(e) = fs::rename(scratch_path, prefs_path) {
-            warn!("Could not rename file: {:?}", e);
+        if let Some(coord_system) = req.celestial_coord_system {
+            locked_state.preferences.celestial_coord_system = Some(coord_system);
+        }
+        if let Some(flat_field) = req.flat_field {
+            let prev_flat_field = locked_state.preferences.flat_field.replace(flat_field);
+            if let Err(x) = Self::apply_flat_field(&locked_state).await {
+                locked_state.preferences.flat_field = prev_flat_field;
+                return Err(tonic_status(x));
+            }
+        }
+        if let Some(mirror_horizontal) = req.mirror_horizontal {
+            locked_state.preferences.mirror_horizontal = Some(mirror_horizontal);
+        }
+        if let Some(mirror_vertical) = req.mirror_vertical {
+            locked_state.preferences.mirror_vertical = Some(mirror_vertical);
+        }
+        if let Some(min_slew_altitude) = req.min_slew_altitude {
+            if !(-90.0..=90.0).contains(&min_slew_altitude) {
+                return Err(tonic::Status::invalid_argument(
+                    format!("min_slew_altitude must be in [-90, 90]; got {}.",
+                            min_slew_altitude)));
+            }
+            locked_state.preferences.min_slew_altitude = Some(min_slew_altitude);
+        }
+        // An empty horizon_profile means "unchanged".
+        if !req.horizon_profile.is_empty() {
+            if req.horizon_profile.iter().any(|a| !(-90.0..=90.0).contains(a)) {
+                return Err(tonic::Status::invalid_argument(
+                    "horizon_profile altitudes must be in [-90, 90]."));
+            }
+            locked_state.preferences.horizon_profile = req.horizon_profile;
         }
 
+        self.write_preferences_file(&mut locked_state);
+
+        Ok(tonic::Response::new(locked_state.preferences.clone()))
+    }
+
+    async fn reset_preferences(&self, _request: tonic::Request<EmptyMessage>)
+                               -> Result<tonic::Response<Preferences>, tonic::Status> {
+        let mut locked_state = self.state.lock().await;
+        info!("Resetting preferences to defaults");
+        // The observer location is not a user preference; it is persisted
+        // here only so that it survives restarts.
+        let observer_location = locked_state.preferences.observer_location.take();
+        locked_state.preferences = Self::default_preferences();
+        locked_state.preferences.observer_location = observer_location;
+        Self::apply_preferences(&locked_state).await;
+        self.write_preferences_file(&mut locked_state);
+
         Ok(tonic::Response::new(locked_state.preferences.clone()))
     }
 
+    async fn set_log_level(&self, request: tonic::Request<LogLevel>)
+                           -> Result<tonic::Response<LogLevel>, tonic::Status> {
+        let req: LogLevel = request.into_inner();
+        if !req.level.is_empty() {
+            let filter = match EnvFilter::try_new(&req.level) {
+                Ok(f) => f,
+                Err(e) => {
+                    return Err(tonic::Status::invalid_argument(
+                        format!("Invalid log level {:?}: {}.", req.level, e)));
+                }
+            };
+            if let Err(e) = self.log_filter_handle.reload(filter) {
+                return Err(tonic::Status::internal(
+                    format!("Could not change log level: {:?}.", e)));
+            }
+            info!("Log level set to {:?}", req.level);
+        }
+        match self.log_filter_handle.with_current(|filter| filter.to_string()) {
+            Ok(level) => Ok(tonic::Response::new(LogLevel{level})),
+            Err(e) => Err(tonic::Status::internal(
+                format!("Could not read log level: {:?}.", e))),
+        }
+    }
+
+    async fn get_preferences_json(&self, _request: tonic::Request<EmptyMessage>)
+                                  -> Result<tonic::Response<PreferencesJson>, tonic::Status> {
+        let json = preferences_to_json(&self.state.lock().await.preferences);
+        Ok(tonic::Response::new(PreferencesJson{json}))
+    }
+
+    async fn set_preferences_json(&self, request: tonic::Request<PreferencesJson>)
+                                  -> Result<tonic::Response<PreferencesJson>, tonic::Status> {
+        let req: PreferencesJson = request.into_inner();
+        let imported = match preferences_from_json(&req.json) {
+            Ok(p) => p,
+            Err(x) => { return Err(tonic_status(x)); }
+        };
+        // Start from defaults, so that omitted preferences are reset.
+        let mut preferences = Self::default_preferences();
+        preferences.merge(imported.encode_to_vec().as_slice()).unwrap();
+        Self::sanitize_preferences(&mut preferences);
+
+        let mut locked_state = self.state.lock().await;
+        // Not part of the JSON form; keep this device's location.
+        preferences.observer_location = locked_state.preferences.observer_location.clone();
+        info!("Importing preferences {:?}", preferences);
+        locked_state.preferences = preferences;
+        Self::apply_preferences(&locked_state).await;
+        self.write_preferences_file(&mut locked_state);
+
+        let json = preferences_to_json(&locked_state.preferences);
+        Ok(tonic::Response::new(PreferencesJson{json}))
+    }
+
     async fn get_frame(&self, request: tonic::Request<FrameRequest>)
                        -> Result<tonic::Response<FrameResult>, tonic::Status> {
         let req: FrameRequest = request.into_inner();
         let frame_result = Self::get_next_frame(
-            self.state.clone(), req.prev_frame_id).await;
+            self.state.clone(), req.prev_frame_id,
+            req.include_processing_stats.unwrap_or(true)).await;
         Ok(tonic::Response::new(frame_result))
     }
 
+    type StreamFramesStream =
+        Pin<Box<dyn Stream<Item = Result<FrameResult, tonic::Status>> + Send>>;
+
+    async fn stream_frames(&self, request: tonic::Request<FrameRequest>)
+                           -> Result<tonic::Response<Self::StreamFramesStream>,
+                                     tonic::Status> {
+        let req: FrameRequest = request.into_inner();
+        let include_processing_stats = req.include_processing_stats.unwrap_or(true);
+        // Only the state handle is carried between frames; get_next_frame()
+        // takes and releases the lock itself. When the client disconnects,
+        // tonic drops the stream, cancelling any pending get_next_frame().
+        let stream = futures::stream::unfold(
+            (self.state.clone(), req.prev_frame_id, /*calibrating=*/false),
+            move |(state, prev_frame_id, calibrating)| async move {
+                // During calibration get_next_frame() doesn't wait for a new
+                // frame; pace the progress updates.
+                if calibrating {
+                    tokio::time::sleep(STREAM_CALIBRATING_INTERVAL).await;
+                }
+                let frame_result = Self::get_next_frame(
+                    state.clone(), prev_frame_id, include_processing_stats).await;
+                // Status-only results (calibrating, camera reconnecting) carry
+                // no new frame, so keep waiting for the frame after the last
+                // one sent.
+                let next_prev_frame_id =
+                    if frame_result.calibrating || frame_result.camera_reconnecting {
+                        prev_frame_id
+                    } else {
+                        Some(frame_result.frame_id)
+                    };
+                let calibrating = frame_result.calibrating;
+                Some((Ok(frame_result), (state, next_prev_frame_id, calibrating)))
+            });
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+
     async fn initiate_action(&self, request: tonic::Request<ActionRequest>)
                              -> Result<tonic::Response<EmptyMessage>, tonic::Status> {
         let req: ActionRequest = request.into_inner();
-        let locked_state = self.state.lock().await;
+        if req.capture_dark_frame.unwrap_or(false) {
+            let camera;
+            let dark_frames;
+            {
+                let locked_state = self.state.lock().await;
+                camera = locked_state.camera.clone();
+                dark_frames = locked_state.dark_frames.clone();
+            }
+            let Some(dark_frames) = dark_frames else {
+                return Err(tonic::Status::failed_precondition(
+                    "Dark frame directory is not available."));
+            };
+            // No locks held while capturing.
+            if let Err(x) = Self::capture_dark_frame(
+                camera, dark_frames, self.dark_frame_count).await
+            {
+                return Err(tonic_status(x));
+            }
+        }
+        if let Some(name) = req.capture_flat {
+            let camera;
+            let dark_frame_dir;
+            {
+                let locked_state = self.state.lock().await;
+                camera = locked_state.camera.clone();
+                dark_frame_dir = locked_state.dark_frame_dir.clone();
+            }
+            // No locks held while capturing.
+            let flat = match Self::capture_median_frame(camera, self.dark_frame_count).await {
+                Ok((_exposure_duration, _gain, flat)) => flat,
+                Err(x) => { return Err(tonic_status(x)); }
+            };
+            if let Err(x) = save_flat_field(&dark_frame_dir, &name, &flat) {
+                return Err(tonic_status(x));
+            }
+            // Pick up the new flat if it is the one in use.
+            let locked_state = self.state.lock().await;
+            if locked_state.preferences.flat_field.as_deref() == Some(name.as_str()) {
+                if let Err(x) = Self::apply_flat_field(&locked_state).await {
+                    return Err(tonic_status(x));
+                }
+            }
+        }
+        if req.map_hot_pixels.unwrap_or(false) {
+            let camera;
+            let hot_pixels;
+            {
+                let locked_state = self.state.lock().await;
+                camera = locked_state.camera.clone();
+                hot_pixels = locked_state.hot_pixels.clone();
+            }
+            let Some(hot_pixels) = hot_pixels else {
+                return Err(tonic::Status::failed_precondition(
+                    "Dark frame directory is not available."));
+            };
+            // No locks held while capturing.
+            if let Err(x) = Self::map_hot_pixels(
+                camera, hot_pixels, self.dark_frame_count).await
+            {
+                return Err(tonic_status(x));
+            }
+        }
+        if req.save_fits.unwrap_or(false) {
+            let operating_mode;
+            let detect_engine;
+            let solve_engine;
+            {
+                let locked_state = self.state.lock().await;
+                operating_mode = locked_state.operation_settings.operating_mode.or(
+                    Some(OperatingMode::Setup as i32)).unwrap();
+                detect_engine = locked_state.detect_engine.clone();
+                solve_engine = locked_state.solve_engine.clone();
+            }
+            // No state lock held while waiting for the next frame.
+            let (captured_image, solve_result) =
+                if operating_mode == OperatingMode::Operate as i32 {
+                    let plate_solution = solve_engine.lock().await.
+                        get_next_result(None).await;
+                    (plate_solution.detect_result.captured_image,
+                     plate_solution.tetra3_solve_result)
+                } else {
+                    let detect_result = detect_engine.lock().await.
+                        get_next_result(None).await;
+                    (detect_result.captured_image, None)
+                };
+            if let Err(x) = SolveEngine::write_fits_file(&captured_image,
+                                                         solve_result.as_ref()) {
+                return Err(tonic_status(x));
+            }
+        }
+        let mut locked_state = self.state.lock().await;
         if req.capture_boresight.unwrap_or(false) {
             let operating_mode = locked_state.operation_settings.operating_mode.or(
                     Some(OperatingMode::Setup as i32)).unwrap();
@@ -469,17 +1133,46 @@ impl Cedar for MyCedar {
         }
         if req.shutdown_server.unwrap_or(false) {
             info!("Shutting down host system");
-            std::thread::sleep(Duration::from_secs(2));
-            let output = Command::new("sudo")
-                .arg("shutdown")
-                .arg("now")
-                .output()
-                .expect("Failed to execute 'sudo shutdown now' command");
-            if !output.status.success() {
-                let error_str = String::from_utf8_lossy(&output.stderr);
+            // Check that we are allowed to shut down before tearing anything
+            // down; otherwise the server would be left without a solver.
+            match Command::new("sudo").args(["-n", "-l", "shutdown"]).output() {
+                Ok(output) => if !output.status.success() {
                     return Err(tonic::Status::failed_precondition(
-                        format!("sudo shutdown error: {:?}.", error_str)));
+                        format!("sudo shutdown not permitted: {:?}.",
+                                String::from_utf8_lossy(&output.stderr))));
+                },
+                Err(e) => {
+                    return Err(tonic::Status::failed_precondition(
+                        format!("Failed to execute sudo: {:?}.", e)));
+                }
             }
+            // Quiesce the camera and solver, and write out pending state, so
+            // that nothing is mid-write when the power goes away.
+            locked_state.solve_engine.lock().await.stop().await;
+            locked_state.detect_engine.lock().await.stop().await;
+            locked_state.tetra3_subprocess.lock().unwrap().stop();
+            locked_state.track_recorder.lock().unwrap().stop();
+            Self::flush_preferences_file(&mut locked_state, &self.preferences_file);
+            // Give our response time to reach the client before the network
+            // goes down. Having passed the check above, this should not fail;
+            // if it does, tell connected clients.
+            let event_sender = locked_state.event_sender.clone();
+            tokio::task::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                let error = match Command::new("sudo").args(["-n", "shutdown", "now"])
+                    .output()
+                {
+                    Ok(output) if output.status.success() => return,
+                    Ok(output) => format!("sudo shutdown error: {:?}",
+                                          String::from_utf8_lossy(&output.stderr)),
+                    Err(e) => format!("Failed to execute 'sudo shutdown now': {:?}", e),
+                };
+                error!("{}", error);
+                Self::publish_event(&event_sender, CedarEvent{
+                    event_type: EventType::Warning.into(),
+                    message: Some(format!("Shutdown failed: {}. Restart the server.", error)),
+                    ..Default::default()});
+            });
         }
         if req.stop_slew.unwrap_or(false) {
             locked_state.telescope_position.lock().unwrap().slew_active = false;
@@ -490,11 +1183,665 @@ impl Cedar for MyCedar {
                 return Err(tonic_status(x));
             }
         }
+        if let Some(sync_point_request) = req.capture_sync_point {
+            let operating_mode = locked_state.operation_settings.operating_mode.or(
+                    Some(OperatingMode::Setup as i32)).unwrap();
+            if operating_mode != OperatingMode::Operate as i32 {
+                return Err(tonic::Status::failed_precondition(
+                    format!("Not in Operate mode: {:?}.", operating_mode)));
+            }
+            let plate_solution = locked_state.solve_engine.lock().await.
+                get_next_result(None).await;
+            let tsr = match &plate_solution.tetra3_solve_result {
+                Some(tsr) if tsr.status == Some(SolveStatus::MatchFound.into()) => tsr,
+                _ => {
+                    return Err(tonic::Status::failed_precondition(
+                        "No current plate solution."));
+                }
+            };
+            let solved_position = if tsr.target_coords.len() > 0 {
+                tsr.target_coords[0].clone()
+            } else {
+                tsr.image_center_coords.as_ref().unwrap().clone()
+            };
+            let readout_time = plate_solution.detect_result.captured_image.readout_time;
+            let mut sync_point = SyncPoint{
+                time: Some(prost_types::Timestamp::try_from(readout_time).unwrap()),
+                solved_position: Some(solved_position.clone()),
+                mount_position: sync_point_request.mount_position,
+                altitude: None,
+                azimuth: None,
+            };
+            let observer_location =
+                locked_state.fixed_settings.lock().unwrap().observer_location.clone();
+            if let Some(geo_location) = observer_location {
+                let (alt, az, _ha) = alt_az_from_equatorial(
+                    solved_position.ra.to_radians() as f64,
+                    solved_position.dec.to_radians() as f64,
+                    geo_location.latitude.to_radians() as f64,
+                    geo_location.longitude.to_radians() as f64,
+                    readout_time);
+                sync_point.altitude = Some(alt.to_degrees() as f32);
+                sync_point.azimuth = Some(az.to_degrees() as f32);
+            }
+            info!("Captured sync point {:?}", sync_point);
+            locked_state.sync_points.sync_points.push(sync_point);
+            self.write_sync_points_file(&locked_state.sync_points);
+        }
+        if req.clear_sync_points.unwrap_or(false) {
+            locked_state.sync_points.sync_points.clear();
+            self.write_sync_points_file(&locked_state.sync_points);
+        }
+        if req.polar_align_begin.unwrap_or(false) {
+            if locked_state.fixed_settings.lock().unwrap().observer_location.is_none() {
+                return Err(tonic::Status::failed_precondition(
+                    "Three-point polar alignment requires the observer location."));
+            }
+            locked_state.polar_analyzer.lock().unwrap().three_point_begin();
+        }
+        if req.polar_align_capture.unwrap_or(false) {
+            let observer_location =
+                locked_state.fixed_settings.lock().unwrap().observer_location.clone();
+            let Some(observer_location) = observer_location else {
+                return Err(tonic::Status::failed_precondition(
+                    "Three-point polar alignment requires the observer location."));
+            };
+            if let Err(x) = locked_state.polar_analyzer.lock().unwrap().three_point_capture(
+                SystemTime::now(), observer_location.latitude, observer_location.longitude)
+            {
+                return Err(tonic_status(x));
+            }
+        }
+        if req.drift_align_begin.unwrap_or(false) {
+            if locked_state.fixed_settings.lock().unwrap().observer_location.is_none() {
+                return Err(tonic::Status::failed_precondition(
+                    "Drift alignment requires the observer location."));
+            }
+            locked_state.drift_align.lock().unwrap().begin();
+        }
+        if req.drift_align_switch_target.unwrap_or(false) {
+            if let Err(x) = locked_state.drift_align.lock().unwrap().switch_target() {
+                return Err(tonic_status(x));
+            }
+        }
+        if req.goto_previous.unwrap_or(false) &&
+            !locked_state.telescope_position.lock().unwrap().goto_previous()
+        {
+            return Err(tonic::Status::failed_precondition("No previous goto target."));
+        }
+        if req.goto_next.unwrap_or(false) &&
+            !locked_state.telescope_position.lock().unwrap().goto_next()
+        {
+            return Err(tonic::Status::failed_precondition("No next goto target."));
+        }
+        if let Some(pier_side) = req.set_pier_side {
+            let pier_side = PierSide::try_from(pier_side).unwrap_or(PierSide::Unspecified);
+            let mut locked_position = locked_state.telescope_position.lock().unwrap();
+            locked_position.pier_side = pier_side;
+            locked_position.pier_side_manual = pier_side != PierSide::Unspecified;
+        }
+        if req.start_track_recording.unwrap_or(false) {
+            if let Err(x) = locked_state.track_recorder.lock().unwrap().start() {
+                return Err(tonic_status(x));
+            }
+        }
+        if req.stop_track_recording.unwrap_or(false) {
+            locked_state.track_recorder.lock().unwrap().stop();
+        }
+        if let Some(name) = req.save_preference_profile {
+            self.save_preference_profile(&locked_state, &name)?;
+        }
+        if let Some(name) = req.load_preference_profile {
+            self.load_preference_profile(&mut locked_state, &name).await?;
+        }
+        if req.recalibrate.unwrap_or(false) {
+            locked_state.fixed_settings.lock().unwrap().fov_override = None;
+            locked_state.saved_calibration = None;
+            locked_state.fov_override_warned = false;
+        }
         Ok(tonic::Response::new(EmptyMessage{}))
     }
+
+    type DownloadLogStream =
+        Pin<Box<dyn Stream<Item = Result<LogChunk, tonic::Status>> + Send>>;
+
+    async fn download_log(&self, request: tonic::Request<LogDownloadRequest>)
+                          -> Result<tonic::Response<Self::DownloadLogStream>, tonic::Status> {
+        let req: LogDownloadRequest = request.into_inner();
+        if req.rotated_files < 0 {
+            return Err(tonic::Status::invalid_argument(
+                format!("rotated_files must be non-negative; got {}.", req.rotated_files)));
+        }
+        let mut log_files = match Self::find_log_files(&self.log_file) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(tonic::Status::failed_precondition(
+                    format!("Error listing log files {:?}: {:?}.", self.log_file, e)));
+            }
+        };
+        let skip = log_files.len().saturating_sub(req.rotated_files as usize + 1);
+        log_files.drain(..skip);
+
+        // The files are read incrementally, so that at most a few chunks are
+        // in memory at a time.
+        let (sender, receiver) = tokio::sync::mpsc::channel(4);
+        tokio::task::spawn_blocking(move || {
+            for path in log_files {
+                if sender.is_closed() {
+                    return;  // Client went away.
+                }
+                if let Err(e) = Self::send_log_file(&path, &sender) {
+                    let _ = sender.blocking_send(Err(tonic::Status::failed_precondition(
+                        format!("Error reading log file {:?}: {:?}.", path, e))));
+                    return;
+                }
+            }
+        });
+        Ok(tonic::Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    type GetEventsStream =
+        Pin<Box<dyn Stream<Item = Result<CedarEvent, tonic::Status>> + Send>>;
+
+    async fn get_events(&self, _request: tonic::Request<EmptyMessage>)
+                        -> Result<tonic::Response<Self::GetEventsStream>, tonic::Status> {
+        let receiver = self.state.lock().await.event_sender.subscribe();
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((Ok(event), receiver)),
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Event stream client lagged; dropped {} events", count);
+                        continue;
+                    },
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+
+    async fn get_sync_points(&self, _request: tonic::Request<EmptyMessage>)
+                             -> Result<tonic::Response<SyncPointList>, tonic::Status> {
+        Ok(tonic::Response::new(self.state.lock().await.sync_points.clone()))
+    }
+
+    async fn get_goto_history(&self, _request: tonic::Request<EmptyMessage>)
+                              -> Result<tonic::Response<GotoHistory>, tonic::Status> {
+        let locked_state = self.state.lock().await;
+        let locked_position = locked_state.telescope_position.lock().unwrap();
+        let targets = locked_position.goto_history.iter().map(|t| GotoTarget{
+            target: Some(CelestialCoord{ra: t.ra as f32, dec: t.dec as f32}),
+            time: Some(prost_types::Timestamp::from(t.time)),
+        }).collect();
+        Ok(tonic::Response::new(GotoHistory{
+            targets,
+            current_index: locked_position.goto_history_index.map(|i| i as i32),
+        }))
+    }
+
+    async fn throughput_test(&self, request: tonic::Request<ThroughputTestRequest>)
+                             -> Result<tonic::Response<ThroughputTestResult>, tonic::Status> {
+        let req: ThroughputTestRequest = request.into_inner();
+        match throughput_test_response(&req, self.max_throughput_test_bytes) {
+            Ok(response) => Ok(tonic::Response::new(response)),
+            Err(x) => Err(tonic_status(x)),
+        }
+    }
 }
 
 impl MyCedar {
+    // Delivers `event` to all current GetEvents subscribers (if any), stamping
+    // it with the current time.
+    fn publish_event(event_sender: &broadcast::Sender<CedarEvent>, mut event: CedarEvent) {
+        event.event_time = Some(prost_types::Timestamp::try_from(
+            SystemTime::now()).unwrap());
+        // An error just means there are no subscribers.
+        let _ = event_sender.send(event);
+    }
+
+    // Arranges for the current preferences to be written to the preferences
+    // file. To limit SD card wear, the write is deferred by
+    // `preferences_write_delay` so that a burst of updates results in a single
+    // write, and is skipped if the content is unchanged. A pending write is
+    // flushed early on shutdown or exit.
+    fn write_preferences_file(&self, state: &mut CedarState) {
+        if state.preferences_write_pending {
+            return;  // Pending write will pick up the change.
+        }
+        state.preferences_write_pending = true;
+        let cloned_state = self.state.clone();
+        let preferences_file = self.preferences_file.clone();
+        let delay = self.preferences_write_delay;
+        tokio::task::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let mut locked_state = cloned_state.lock().await;
+            Self::flush_preferences_file(&mut locked_state, &preferences_file);
+        });
+    }
+
+    // Periodically checks the CPU temperature against `limit` (degrees
+    // Celsius), slowing the OPERATE mode update rate while it is exceeded.
+    fn start_thermal_monitor(&self, limit: f32) {
+        let state = self.state.clone();
+        let cpu_temp_path = self.cpu_temp_path.clone();
+        tokio::task::spawn(async move {
+            loop {
+                tokio::time::sleep(THERMAL_CHECK_INTERVAL).await;
+                let Some(temperature) = Self::read_cpu_temperature(&cpu_temp_path) else {
+                    continue;
+                };
+                let mut locked_state = state.lock().await;
+                let throttling = if locked_state.thermal_throttling {
+                    temperature > limit - THERMAL_THROTTLE_HYSTERESIS
+                } else {
+                    temperature > limit
+                };
+                if throttling == locked_state.thermal_throttling {
+                    continue;
+                }
+                if throttling {
+                    warn!("CPU temperature {:.1}C exceeds {:.1}C; slowing updates",
+                          temperature, limit);
+                } else {
+                    info!("CPU temperature {:.1}C; resuming normal updates", temperature);
+                }
+                locked_state.thermal_throttling = throttling;
+                if locked_state.operation_settings.operating_mode ==
+                    Some(OperatingMode::Operate as i32) && !locked_state.calibrating
+                {
+                    let update_interval = Self::operate_update_interval(&locked_state);
+                    if let Err(x) = Self::set_update_interval(
+                        &locked_state, update_interval).await
+                    {
+                        warn!("Could not set update interval {:?}", x);
+                    }
+                }
+            }
+        });
+    }
+
+    // Reads NMEA sentences from the GPS receiver at `device` in the
+    // background. Each fix updates the observer location (keeping the last
+    // known location if the fix is lost) and, if `set_clock`, the system
+    // clock.
+    fn start_gps(&self, device: PathBuf, set_clock: bool) {
+        // The system clock is only corrected when off by more than this.
+        const MAX_CLOCK_ERROR: Duration = Duration::from_secs(2);
+        // Smaller location changes are GPS jitter; ignore them.
+        const MIN_LOCATION_CHANGE: f32 = 0.001;  // Degrees.
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<NmeaReport>(8);
+        std::thread::spawn(move || run_gps_reader(device, sender));
+        let state = self.state.clone();
+        let preferences_file = self.preferences_file.clone();
+        tokio::task::spawn(async move {
+            let mut have_fix = false;
+            let mut clock_error_logged = false;
+            while let Some(report) = receiver.recv().await {
+                let NmeaReport::Fix(fix) = report else {
+                    if have_fix {
+                        info!("GPS fix lost; keeping last known location");
+                        have_fix = false;
+                    }
+                    continue;
+                };
+                if !have_fix {
+                    info!("GPS fix acquired at {:.4}, {:.4}", fix.latitude, fix.longitude);
+                    have_fix = true;
+                }
+                if let (true, Some(gps_time)) = (set_clock, fix.time) {
+                    let now = SystemTime::now();
+                    let error = gps_time.duration_since(now).unwrap_or_else(
+                        |e| e.duration());
+                    if error > MAX_CLOCK_ERROR {
+                        let since_epoch = gps_time.duration_since(
+                            SystemTime::UNIX_EPOCH).unwrap();
+                        let time_spec = TimeSpec::new(since_epoch.as_secs() as i64,
+                                                      since_epoch.subsec_nanos() as i64);
+                        match clock_settime(ClockId::CLOCK_REALTIME, time_spec) {
+                            Ok(()) => info!("Updated server time from GPS to {:?}",
+                                            Local::now()),
+                            Err(e) => if !clock_error_logged {
+                                warn!("Could not update server time from GPS: {:?}", e);
+                                clock_error_logged = true;
+                            },
+                        }
+                    }
+                }
+                let location = LatLong{latitude: fix.latitude as f32,
+                                       longitude: fix.longitude as f32};
+                let mut locked_state = state.lock().await;
+                let changed = match &locked_state.fixed_settings.lock().unwrap()
+                    .observer_location
+                {
+                    Some(prev) => (prev.latitude - location.latitude).abs() >
+                        MIN_LOCATION_CHANGE ||
+                        (prev.longitude - location.longitude).abs() > MIN_LOCATION_CHANGE,
+                    None => true,
+                };
+                if changed {
+                    info!("Updated observer location from GPS to {:?}", location);
+                    locked_state.fixed_settings.lock().unwrap().observer_location =
+                        Some(location.clone());
+                    locked_state.preferences.observer_location = Some(location);
+                    // Location changes are infrequent; write now.
+                    Self::flush_preferences_file(&mut locked_state, &preferences_file);
+                }
+            }
+        });
+    }
+
+    // Writes any pending preferences change as the server exits. The state
+    // lock can be held for a long time (e.g. by a calibration awaiting a solve
+    // that will now never finish), so give up rather than block exit.
+    async fn flush_preferences_on_exit(state: &Arc<tokio::sync::Mutex<CedarState>>,
+                                       preferences_file: &Path) {
+        for _ in 0..EXIT_FLUSH_ATTEMPTS {
+            if let Ok(mut locked_state) = state.try_lock() {
+                Self::flush_preferences_file(&mut locked_state, preferences_file);
+                return;
+            }
+            tokio::time::sleep(EXIT_FLUSH_RETRY_INTERVAL).await;
+        }
+        warn!("State busy; exiting without writing pending preferences");
+    }
+
+    // Immediately writes the current preferences, if changed since the last
+    // write.
+    fn flush_preferences_file(state: &mut CedarState, preferences_file: &Path) {
+        state.preferences_write_pending = false;
+        let mut buf = vec![];
+        if let Err(e) = state.preferences.encode(&mut buf) {
+            warn!("Could not encode preferences: {:?}", e);
+            return;
+        }
+        if buf == state.written_preferences {
+            return;
+        }
+        let scratch_path = preferences_file.with_extension("tmp");
+        if let Err(e) = fs::write(&scratch_path, &buf) {
+            warn!("Could not write file: {:?}", e);
+            return;
+        }
+        if let Err(e) = fs::rename(scratch_path, preferences_file) {
+            warn!("Could not rename file: {:?}", e);
+            return;
+        }
+        state.written_preferences = buf;
+    }
+
+    fn default_preferences() -> Preferences {
+        Preferences{
+            celestial_coord_format: Some(CelestialCoordFormat::HmsDms.into()),
+            eyepiece_fov: Some(1.0),
+            night_vision_theme: Some(false),
+            show_perf_stats: Some(false),
+            hide_app_bar: Some(false),
+            mount_type: Some(MountType::Equatorial.into()),
+            camera_mount_angle: Some(0.0),
+            dark_frame_subtraction: Some(true),
+            celestial_coord_system: Some(CelestialCoordSystem::SystemEquatorial.into()),
+            flat_field: None,
+            observer_location: None,
+            mirror_horizontal: Some(false),
+            mirror_vertical: Some(false),
+            min_slew_altitude: Some(0.0),
+            horizon_profile: Vec::new(),
+        }
+    }
+
+    // Fixes up out-of-range or missing values in preferences read from a file.
+    fn sanitize_preferences(p: &mut Preferences) {
+        let defaults = Self::default_preferences();
+        let eyepiece_fov = p.eyepiece_fov.unwrap_or(defaults.eyepiece_fov.unwrap());
+        p.eyepiece_fov = Some(eyepiece_fov.clamp(0.1, 2.0));
+        match p.camera_mount_angle {
+            Some(angle) if (0.0..360.0).contains(&angle) => (),
+            _ => p.camera_mount_angle = defaults.camera_mount_angle,
+        }
+        if p.dark_frame_subtraction.is_none() {
+            p.dark_frame_subtraction = defaults.dark_frame_subtraction;
+        }
+        match p.min_slew_altitude {
+            Some(alt) if (-90.0..=90.0).contains(&alt) => (),
+            _ => p.min_slew_altitude = defaults.min_slew_altitude,
+        }
+        // Dropping single entries would shift the azimuths of the rest.
+        if p.horizon_profile.iter().any(|alt| !(-90.0..=90.0).contains(alt)) {
+            p.horizon_profile.clear();
+        }
+    }
+
+    // Propagates preferences that affect server processing.
+    async fn apply_preferences(state: &CedarState) {
+        state.detect_engine.lock().await.set_dark_frame_subtraction(
+            state.preferences.dark_frame_subtraction.unwrap_or(true));
+        if let Err(x) = Self::apply_flat_field(state).await {
+            warn!("Flat field disabled: {:?}", x);
+            state.detect_engine.lock().await.set_flat_field(None);
+        }
+    }
+
+    // Loads the flat field named in preferences (if any) into the detect
+    // engine.
+    async fn apply_flat_field(state: &CedarState) -> Result<(), CanonicalError> {
+        let flat_field = match state.preferences.flat_field.as_deref() {
+            None | Some("") => None,
+            Some(name) => Some(Arc::new(FlatField::load(&state.dark_frame_dir, name)?)),
+        };
+        state.detect_engine.lock().await.set_flat_field(flat_field);
+        Ok(())
+    }
+
+    // Preference profiles are stored alongside the preferences file.
+    fn preference_profiles_dir(&self) -> PathBuf {
+        self.preferences_file.with_file_name("preference_profiles")
+    }
+
+    fn preference_profile_path(&self, name: &str) -> Result<PathBuf, tonic::Status> {
+        if name.is_empty() || name.len() > 64 || !name.chars().all(
+            |c| c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_')
+        {
+            return Err(tonic::Status::invalid_argument(
+                format!("Invalid preference profile name {:?}.", name)));
+        }
+        Ok(self.preference_profiles_dir().join(format!("{}.binpb", name)))
+    }
+
+    // Returns the names of the saved preference profiles, sorted.
+    fn list_preference_profiles(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(self.preference_profiles_dir()) else {
+            return vec![];
+        };
+        let mut names: Vec<String> = entries.filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "binpb" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().to_string())
+        }).collect();
+        names.sort();
+        names
+    }
+
+    fn save_preference_profile(&self, state: &CedarState, name: &str)
+                               -> Result<(), tonic::Status> {
+        let path = self.preference_profile_path(name)?;
+        let mut buf = vec![];
+        state.preferences.encode(&mut buf).unwrap();
+        if let Err(e) = fs::create_dir_all(self.preference_profiles_dir()).and_then(
+            |_| fs::write(&path, &buf))
+        {
+            return Err(tonic::Status::failed_precondition(
+                format!("Error writing preference profile {:?}: {:?}.", path, e)));
+        }
+        info!("Saved preference profile {:?}", path);
+        Ok(())
+    }
+
+    async fn load_preference_profile(&self, state: &mut CedarState, name: &str)
+                                     -> Result<(), tonic::Status> {
+        let path = self.preference_profile_path(name)?;
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return Err(tonic::Status::not_found(
+                    format!("Error reading preference profile {:?}: {:?}.", path, e)));
+            }
+        };
+        let mut preferences = match Preferences::decode(bytes.as_slice()) {
+            Ok(p) => p,
+            Err(e) => {
+                return Err(tonic::Status::failed_precondition(
+                    format!("Could not decode preference profile {:?}: {:?}.", path, e)));
+            }
+        };
+        Self::sanitize_preferences(&mut preferences);
+        state.preferences = preferences;
+        Self::apply_preferences(state).await;
+        self.write_preferences_file(state);
+        info!("Loaded preference profile {:?}", path);
+        Ok(())
+    }
+
+    // Determines whether we can create or replace `path`. Because files are
+    // replaced by writing a scratch file and renaming, it's the writability of
+    // the directory that matters.
+    fn is_writable(path: &Path) -> bool {
+        let scratch_path = path.with_extension("tmp");
+        match fs::write(&scratch_path, b"") {
+            Ok(()) => {
+                let _ = fs::remove_file(&scratch_path);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn write_sync_points_file(&self, sync_points: &SyncPointList) {
+        let sync_points_path = Path::new(&self.sync_points_file);
+        let scratch_path = sync_points_path.with_extension("tmp");
+
+        let mut buf = vec![];
+        if let Err(e) = sync_points.encode(&mut buf) {
+            warn!("Could not encode sync points: {:?}", e);
+            return;
+        }
+        if let Err(e) = fs::write(&scratch_path, buf) {
+            warn!("Could not write file: {:?}", e);
+            return;
+        }
+        if let Err(e) = fs::rename(scratch_path, sync_points_path) {
+            warn!("Could not rename file: {:?}", e);
+        }
+    }
+
+    // Saves `calibration_data` for use after a restart.
+    fn write_calibration_file(calibration_file: &Path, calibration_data: &CalibrationData,
+                              camera_model: &str, dimensions: (i32, i32)) {
+        let scratch_path = calibration_file.with_extension("tmp");
+        let saved_calibration = SavedCalibration{
+            camera_model: camera_model.to_string(),
+            width: dimensions.0,
+            height: dimensions.1,
+            calibration_data: Some(calibration_data.clone()),
+        };
+        let mut buf = vec![];
+        if let Err(e) = saved_calibration.encode(&mut buf) {
+            warn!("Could not encode calibration: {:?}", e);
+            return;
+        }
+        if let Err(e) = fs::write(&scratch_path, buf) {
+            warn!("Could not write file: {:?}", e);
+            return;
+        }
+        if let Err(e) = fs::rename(scratch_path, calibration_file) {
+            warn!("Could not rename file: {:?}", e);
+        }
+    }
+
+    // Returns the calibration saved in `calibration_file`, if present and
+    // applicable to the given camera.
+    fn read_calibration_file(calibration_file: &Path, camera_model: &str,
+                             dimensions: (i32, i32)) -> Option<CalibrationData> {
+        let bytes = match fs::read(calibration_file) {
+            Ok(b) => b,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Could not read file {:?}: {:?}", calibration_file, e);
+                }
+                return None;
+            }
+        };
+        let saved_calibration = match SavedCalibration::decode(bytes.as_slice()) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Could not decode calibration {:?}", e);
+                return None;
+            }
+        };
+        if saved_calibration.camera_model != camera_model ||
+            saved_calibration.width != dimensions.0 ||
+            saved_calibration.height != dimensions.1
+        {
+            info!("Ignoring saved calibration for camera {} {}x{}",
+                  saved_calibration.camera_model,
+                  saved_calibration.width, saved_calibration.height);
+            return None;
+        }
+        saved_calibration.calibration_data
+    }
+
+    // Applies a calibration previously saved by calibrate().
+    async fn apply_saved_calibration(state: &CedarState,
+                                     saved_calibration: CalibrationData)
+                                     -> Result<(), CanonicalError> {
+        let mut locked_camera = state.camera.lock().await;
+        if let Some(offset) = saved_calibration.camera_offset {
+            if state.camera_supports_offset {
+                locked_camera.set_offset(Offset::new(offset))?;
+            }
+        }
+        if let Some(exp_time) = &saved_calibration.target_exposure_time {
+            let exp_duration = std::time::Duration::try_from(exp_time.clone()).unwrap();
+            locked_camera.set_exposure_duration(exp_duration)?;
+            drop(locked_camera);
+            state.detect_engine.lock().await.set_calibrated_exposure_duration(
+                exp_duration);
+        }
+        let mut locked_solve_engine = state.solve_engine.lock().await;
+        locked_solve_engine.set_fov_estimate(saved_calibration.fov_horizontal)?;
+        locked_solve_engine.set_distortion(
+            saved_calibration.lens_distortion.unwrap_or(0.0))?;
+        locked_solve_engine.set_solve_timeout(Self::operate_solve_timeout(state))?;
+        info!("Using saved calibration: {:?}", saved_calibration);
+        *state.calibration_data.lock().await = saved_calibration;
+        Ok(())
+    }
+
+    // Sets the FOV-derived fields of `calibration_data`.
+    async fn set_calibrated_fov(
+        calibration_data: &mut CalibrationData, fov: f32,
+        camera: &Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>) {
+        calibration_data.fov_horizontal = Some(fov);
+        let locked_camera = camera.lock().await;
+        let sensor_width_mm = locked_camera.sensor_size().0;
+        let lens_fl_mm =
+            sensor_width_mm / (2.0 * (fov/2.0).to_radians()).tan();
+        calibration_data.lens_fl_mm = Some(lens_fl_mm);
+        let pixel_width_mm = sensor_width_mm / locked_camera.dimensions().0 as f32;
+        calibration_data.pixel_angular_size =
+            Some((pixel_width_mm / lens_fl_mm).atan().to_degrees());
+    }
+
+    fn calibration_fraction(calibration_start: Instant,
+                            calibration_duration_estimate: Duration) -> f32 {
+        let fraction = calibration_start.elapsed().as_secs_f32() /
+            calibration_duration_estimate.as_secs_f32();
+        if fraction > 1.0 { 1.0 } else { fraction }
+    }
+
     fn fill_in_time(fixed_settings: &mut FixedSettings) {
         if let Ok(cur_time) = clock_gettime(ClockId::CLOCK_REALTIME) {
             let mut pst = prost_types::Timestamp::default();
@@ -511,16 +1858,89 @@ impl MyCedar {
 
     async fn set_update_interval(state: &CedarState, update_interval: std::time::Duration)
                                  -> Result<(), CanonicalError> {
+        let update_interval = std::cmp::max(update_interval, state.min_update_interval);
         state.camera.lock().await.set_update_interval(update_interval)?;
         state.detect_engine.lock().await.set_update_interval(update_interval)?;
         state.solve_engine.lock().await.set_update_interval(update_interval)
     }
 
+    // Captures `count` exposures and saves their median to `dark_frames`.
+    async fn capture_dark_frame(
+        camera: Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>,
+        dark_frames: Arc<Mutex<DarkFrameLibrary>>,
+        count: usize) -> Result<(), CanonicalError> {
+        let (exposure_duration, gain, dark) = Self::capture_median_frame(camera, count).await?;
+        info!("Captured dark frame for exposure {:?}, gain {}", exposure_duration, gain);
+        dark_frames.lock().unwrap().add(exposure_duration, gain, dark)
+    }
+
+    // Captures `count` exposures and saves the hot pixels found in their median
+    // to `hot_pixels`.
+    async fn map_hot_pixels(
+        camera: Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>,
+        hot_pixels: Arc<Mutex<HotPixelLibrary>>,
+        count: usize) -> Result<(), CanonicalError> {
+        let (_exposure_duration, gain, dark) =
+            Self::capture_median_frame(camera, count).await?;
+        hot_pixels.lock().unwrap().add(gain, find_hot_pixels(&dark))
+    }
+
+    // Captures `count` exposures, returning their exposure duration, gain, and
+    // per-pixel median.
+    async fn capture_median_frame(
+        camera: Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>,
+        count: usize) -> Result<(Duration, i32, GrayImage), CanonicalError> {
+        // Discard the current frame; it might predate the lens being covered.
+        let (_, mut frame_id) = camera.lock().await.capture_image(None).await?;
+        let mut frames = Vec::<Arc<GrayImage>>::with_capacity(count);
+        let mut capture_params = None;
+        while frames.len() < count.max(1) {
+            let (captured_image, id) =
+                camera.lock().await.capture_image(Some(frame_id)).await?;
+            frame_id = id;
+            let params = (captured_image.capture_params.exposure_duration,
+                          captured_image.capture_params.gain.value());
+            if *capture_params.get_or_insert(params) != params {
+                return Err(failed_precondition_error(
+                    "Exposure changed during capture; use manual exposure"));
+            }
+            frames.push(captured_image.image.clone());
+        }
+        let (exposure_duration, gain) = capture_params.unwrap();
+        Ok((exposure_duration, gain, median_dark_frame(&frames)))
+    }
+
+    // The update interval to use in OPERATE mode, depending on whether we're
+    // dwelling.
+    fn operate_update_interval(state: &CedarState) -> Duration {
+        let update_interval = if state.dwell_applied {
+            state.operation_settings.dwell_update_interval.clone().unwrap()
+        } else {
+            state.operation_settings.update_interval.clone().unwrap()
+        };
+        let update_interval = std::time::Duration::try_from(update_interval).unwrap();
+        if state.thermal_throttling {
+            std::cmp::max(update_interval, THERMAL_THROTTLE_UPDATE_INTERVAL)
+        } else {
+            update_interval
+        }
+    }
+
+    fn operate_solve_timeout(state: &CedarState) -> Duration {
+        let max_solve_time = std::time::Duration::try_from(
+            state.operation_settings.max_solve_time.clone().unwrap()).unwrap();
+        match state.calibrated_solve_timeout {
+            Some(timeout) => std::cmp::min(timeout, max_solve_time),
+            None => max_solve_time,
+        }
+    }
+
     async fn reset_session_stats(state: &mut CedarState) {
         state.detect_engine.lock().await.reset_session_stats();
         state.solve_engine.lock().await.reset_session_stats();
         state.serve_latency_stats.reset_session();
         state.overall_latency_stats.reset_session();
+        state.frame_interval_stats.reset_session();
     }
 
     // Called when entering SETUP mode.
@@ -528,24 +1948,42 @@ impl MyCedar {
         let mut locked_camera = state.camera.lock().await;
         let gain = locked_camera.optimal_gain();
         locked_camera.set_gain(gain)?;
-        if let Err(e) = locked_camera.set_offset(Offset::new(3)) {
-            debug!("Could not set offset: {:?}", e);
+        if state.camera_supports_offset {
+            if let Err(e) = locked_camera.set_offset(Offset::new(3)) {
+                warn!("Could not set offset: {:?}", e);
+            }
         }
         let mut locked_solve_engine = state.solve_engine.lock().await;
         locked_solve_engine.set_fov_estimate(/*fov_estimate=*/None)?;
         locked_solve_engine.set_distortion(0.0)?;
-        locked_solve_engine.set_solve_timeout(Duration::from_secs(1))?;
+        locked_solve_engine.set_solve_timeout(Self::operate_solve_timeout(state))?;
         *state.calibration_data.lock().await = CalibrationData{..Default::default()};
         Ok(())
     }
 
+    // Logs a calibration step failure, publishes it as a WARNING event, and
+    // records it in CalibrationData.failure_reason.
+    async fn calibration_warning(event_sender: &broadcast::Sender<CedarEvent>,
+                                 calibration_data: &tokio::sync::Mutex<CalibrationData>,
+                                 msg: String) {
+        warn!("{}", msg);
+        Self::publish_event(event_sender, CedarEvent{
+            event_type: EventType::Warning.into(),
+            message: Some(msg.clone()), ..Default::default()});
+        calibration_data.lock().await.failure_reason = Some(msg);
+    }
+
     // Called when entering OPERATE mode. This always succeeds (even if
-    // calibration fails), unless the callibration was cancelled in which
-    // case an ABORTED error is returned.
+    // calibration fails or exceeds `max_calibration_time`, in which case
+    // fallback values are used for the incomplete steps), unless the
+    // callibration was cancelled in which case an ABORTED error is returned.
+    // The first call after startup uses the saved calibration, if any, instead
+    // of calibrating.
     async fn calibrate(state: Arc<tokio::sync::Mutex<CedarState>>,
                        solve_timeout: Duration)
                        -> Result<(), CanonicalError> {
         let setup_exposure_duration;
+        let camera_supports_offset;
         let binning;
         let detection_sigma;
         let star_count_goal;
@@ -555,14 +1993,32 @@ impl MyCedar {
         let calibration_data;
         let detect_engine;
         let solve_engine;
+        let event_sender;
+        let calibration_start;
+        let calibration_duration_estimate;
+        let max_calibration_time;
+        let tetra3_subprocess;
+        let calibration_file;
+        let fov_override;
         {
-            let locked_state = state.lock().await;
+            let mut locked_state = state.lock().await;
+            if let Some(saved_calibration) = locked_state.saved_calibration.take() {
+                return Self::apply_saved_calibration(&locked_state, saved_calibration).await;
+            }
             camera = locked_state.camera.clone();
+            camera_supports_offset = locked_state.camera_supports_offset;
             calibrator = locked_state.calibrator.clone();
             cancel_calibration = locked_state.cancel_calibration.clone();
             calibration_data = locked_state.calibration_data.clone();
             detect_engine = locked_state.detect_engine.clone();
             solve_engine = locked_state.solve_engine.clone();
+            event_sender = locked_state.event_sender.clone();
+            calibration_start = locked_state.calibration_start;
+            calibration_duration_estimate = locked_state.calibration_duration_estimate;
+            max_calibration_time = locked_state.max_calibration_time;
+            tetra3_subprocess = locked_state.tetra3_subprocess.clone();
+            calibration_file = locked_state.calibration_file.clone();
+            fov_override = locked_state.fixed_settings.lock().unwrap().fov_override;
 
             // What was the final exposure duration coming out of SETUP mode?
             setup_exposure_duration = camera.lock().await.get_exposure_duration();
@@ -573,80 +2029,160 @@ impl MyCedar {
             detection_sigma = locked_detect_engine.get_detection_sigma();
             star_count_goal = locked_detect_engine.get_star_count_goal();
         }
-        let offset = match calibrator.lock().await.calibrate_offset(
-            cancel_calibration.clone()).await
-        {
-            Ok(o) => o,
-            Err(e) => {
-                if e.code == CanonicalErrorCode::Aborted {
-                    return Err(e);
-                }
-                warn!{"Error while calibrating offset: {:?}, using 3", e};
-                Offset::new(3)  // Sane fallback value.
-            }
-        };
-        _ = camera.lock().await.set_offset(offset);  // Ignore unsupported offset.
-        calibration_data.lock().await.camera_offset = Some(offset.value());
-
-        let exp_duration = match calibrator.lock().await.calibrate_exposure_duration(
-            setup_exposure_duration, star_count_goal,
-            binning, detection_sigma,
-            cancel_calibration.clone()).await {
-            Ok(ed) => ed,
-            Err(e) => {
-                if e.code == CanonicalErrorCode::Aborted {
-                    return Err(e);
-                }
-                warn!{"Error while calibrating exposure duration: {:?}, using {:?}",
-                      e, setup_exposure_duration};
-                setup_exposure_duration  // Sane fallback value.
+        // Steps that would start after this deadline are skipped; a step in
+        // progress at the deadline is abandoned.
+        let deadline = tokio::time::Instant::from_std(
+            calibration_start + max_calibration_time);
+        let deadline_msg = format!("Calibration exceeded {:?}", max_calibration_time);
+        let mut deadline_exceeded = false;
+
+        if camera_supports_offset {
+            let offset = match tokio::time::timeout_at(
+                deadline,
+                calibrator.lock().await.calibrate_offset(cancel_calibration.clone())).await
+            {
+                Ok(Ok(o)) => {
+                    calibration_data.lock().await.completed_steps.push(
+                        CalibrationStep::Offset.into());
+                    o
+                },
+                Ok(Err(e)) => {
+                    if e.code == CanonicalErrorCode::Aborted {
+                        return Err(e);
+                    }
+                    let msg = format!("Error while calibrating offset: {:?}, using 3", e);
+                    Self::calibration_warning(&event_sender, &calibration_data, msg).await;
+                    Offset::new(3)  // Sane fallback value.
+                },
+                Err(_) => {
+                    deadline_exceeded = true;
+                    Offset::new(3)
+                },
+            };
+            camera.lock().await.set_offset(offset)?;
+            calibration_data.lock().await.camera_offset = Some(offset.value());
+            Self::publish_event(&event_sender, CedarEvent{
+                event_type: EventType::CalibrationProgress.into(),
+                calibration_progress: Some(Self::calibration_fraction(
+                    calibration_start, calibration_duration_estimate)),
+                ..Default::default()});
+        }
+
+        let exp_duration = if deadline_exceeded {
+            setup_exposure_duration
+        } else {
+            match tokio::time::timeout_at(
+                deadline,
+                calibrator.lock().await.calibrate_exposure_duration(
+                    setup_exposure_duration, star_count_goal,
+                    binning, detection_sigma,
+                    cancel_calibration.clone())).await
+            {
+                Ok(Ok(ed)) => {
+                    calibration_data.lock().await.completed_steps.push(
+                        CalibrationStep::ExposureDuration.into());
+                    ed
+                },
+                Ok(Err(e)) => {
+                    if e.code == CanonicalErrorCode::Aborted {
+                        return Err(e);
+                    }
+                    let msg = format!(
+                        "Error while calibrating exposure duration: {:?}, using {:?}",
+                        e, setup_exposure_duration);
+                    Self::calibration_warning(&event_sender, &calibration_data, msg).await;
+                    setup_exposure_duration  // Sane fallback value.
+                },
+                Err(_) => {
+                    deadline_exceeded = true;
+                    setup_exposure_duration
+                },
             }
         };
         camera.lock().await.set_exposure_duration(exp_duration)?;
         calibration_data.lock().await.target_exposure_time =
             Some(prost_types::Duration::try_from(exp_duration).unwrap());
         detect_engine.lock().await.set_calibrated_exposure_duration(exp_duration);
+        Self::publish_event(&event_sender, CedarEvent{
+            event_type: EventType::CalibrationProgress.into(),
+            calibration_progress: Some(Self::calibration_fraction(
+                calibration_start, calibration_duration_estimate)),
+            ..Default::default()});
 
-        match calibrator.lock().await.calibrate_optical(
-            solve_engine.clone(), exp_duration, solve_timeout,
-            binning, detection_sigma).await
-        {
+        let optical_result = if let Some(fov) = fov_override {
+            // Skip the optical step. The nominal solve duration yields the
+            // default operation solve timeout.
+            Ok((fov, /*distortion=*/0.0, Duration::from_millis(100)))
+        } else if deadline_exceeded {
+            Err(deadline_exceeded_error(&deadline_msg))
+        } else {
+            match tokio::time::timeout_at(
+                deadline,
+                calibrator.lock().await.calibrate_optical(
+                    solve_engine.clone(), exp_duration, solve_timeout,
+                    binning, detection_sigma)).await
+            {
+                Ok(result) => result,
+                Err(_) => {
+                    // Cancel the solve that we abandoned.
+                    tetra3_subprocess.lock().unwrap().send_interrupt_signal();
+                    Err(deadline_exceeded_error(&deadline_msg))
+                },
+            }
+        };
+        match optical_result {
             Ok((fov, distortion, solve_duration)) => {
+                let operation_solve_timeout = {
+                    let mut locked_state = state.lock().await;
+                    locked_state.calibrated_solve_timeout = Some(std::cmp::max(
+                        solve_duration * 10, Duration::from_millis(500)));
+                    Self::operate_solve_timeout(&locked_state)
+                };
                 let mut locked_calibration_data = calibration_data.lock().await;
-                locked_calibration_data.fov_horizontal = Some(fov);
+                if fov_override.is_none() {
+                    locked_calibration_data.completed_steps.push(
+                        CalibrationStep::Optical.into());
+                }
+                Self::set_calibrated_fov(&mut locked_calibration_data, fov, &camera).await;
                 locked_calibration_data.lens_distortion = Some(distortion);
-                let sensor_width_mm = camera.lock().await.sensor_size().0;
-                let lens_fl_mm =
-                    sensor_width_mm / (2.0 * (fov/2.0).to_radians()).tan();
-                locked_calibration_data.lens_fl_mm = Some(lens_fl_mm);
-                let pixel_width_mm =
-                    sensor_width_mm / camera.lock().await.dimensions().0 as f32;
-                locked_calibration_data.pixel_angular_size =
-                    Some((pixel_width_mm / lens_fl_mm).atan().to_degrees());
-
-                let operation_solve_timeout =
-                    std::cmp::min(
-                        std::cmp::max(solve_duration * 10, Duration::from_millis(500)),
-                        Duration::from_secs(1));  // TODO: max solve time cmd line arg
+
                 let mut locked_solve_engine = solve_engine.lock().await;
                 locked_solve_engine.set_fov_estimate(Some(fov))?;
                 locked_solve_engine.set_distortion(distortion)?;
                 locked_solve_engine.set_solve_timeout(operation_solve_timeout)?;
+
+                // Don't persist a calibration based on the override.
+                if fov_override.is_none() {
+                    let locked_camera = camera.lock().await;
+                    let dimensions = locked_camera.dimensions();
+                    Self::write_calibration_file(
+                        &calibration_file, &locked_calibration_data,
+                        &locked_camera.model(), dimensions);
+                }
             }
             Err(e) => {
+                let operation_solve_timeout = {
+                    let mut locked_state = state.lock().await;
+                    locked_state.calibrated_solve_timeout = None;
+                    Self::operate_solve_timeout(&locked_state)
+                };
                 let mut locked_calibration_data = calibration_data.lock().await;
                 locked_calibration_data.fov_horizontal = None;
                 locked_calibration_data.lens_distortion = None;
                 let mut locked_solve_engine = solve_engine.lock().await;
                 locked_solve_engine.set_fov_estimate(None)?;
                 locked_solve_engine.set_distortion(0.0)?;
-                // TODO: pass this in? Should come from command line, maybe is
-                // max solve time.
-                locked_solve_engine.set_solve_timeout(Duration::from_secs(1))?;
+                locked_solve_engine.set_solve_timeout(operation_solve_timeout)?;
                 if e.code == CanonicalErrorCode::Aborted {
                     return Err(e);
                 }
-                warn!{"Error while calibrating optics: {:?}", e};
+                drop(locked_calibration_data);
+                let msg = if e.code == CanonicalErrorCode::DeadlineExceeded {
+                    e.message
+                } else {
+                    format!("Error while calibrating optics: {:?}", e)
+                };
+                Self::calibration_warning(&event_sender, &calibration_data, msg).await;
             }
         };
         debug!("Calibration result: {:?}", calibration_data.lock().await);
@@ -654,13 +2190,18 @@ impl MyCedar {
     }
 
     async fn get_next_frame(state: Arc<tokio::sync::Mutex<CedarState>>,
-                            prev_frame_id: Option<i32>)
+                            prev_frame_id: Option<i32>,
+                            include_processing_stats: bool)
                             -> FrameResult {
         let overall_start_time = Instant::now();
 
         let mut frame_result = FrameResult {..Default::default()};
         let mut fixed_settings;
         let image_rectangle;
+        let display_image_format;
+        let setup_mode;
+        let detect_engine;
+        let solve_engine;
         {
             let locked_state = state.lock().await;
             image_rectangle = Rectangle{
@@ -676,47 +2217,64 @@ impl MyCedar {
             frame_result.preferences = Some(locked_state.preferences.clone());
             frame_result.operation_settings =
                 Some(locked_state.operation_settings.clone());
+            display_image_format = locked_state.operation_settings.display_image_format();
+            frame_result.no_camera = locked_state.no_camera;
+            setup_mode = locked_state.operation_settings.operating_mode.unwrap() ==
+                OperatingMode::Setup as i32;
+            detect_engine = locked_state.detect_engine.clone();
+            solve_engine = locked_state.solve_engine.clone();
 
             if locked_state.calibrating {
                 frame_result.calibrating = true;
-                let time_spent_calibrating = locked_state.calibration_start.elapsed();
-                let mut fraction =
-                    time_spent_calibrating.as_secs_f32() /
-                    locked_state.calibration_duration_estimate.as_secs_f32();
-                if fraction > 1.0 {
-                    fraction = 1.0;
-                }
-                frame_result.calibration_progress = Some(fraction);
+                frame_result.calibration_progress = Some(Self::calibration_fraction(
+                    locked_state.calibration_start,
+                    locked_state.calibration_duration_estimate));
 
                 if let Some(img) = &locked_state.scaled_image {
-                    let (scaled_width, scaled_height) = img.dimensions();
-                    let mut bmp_buf = Vec::<u8>::new();
-                    bmp_buf.reserve((scaled_width * scaled_height) as usize);
-                    img.write_to(&mut Cursor::new(&mut bmp_buf),
-                                 ImageFormat::Bmp).unwrap();
                     frame_result.image = Some(Image{
                         binning_factor: locked_state.scaled_image_binning_factor as i32,
                         // Rectangle is always in full resolution coordinates.
                         rectangle: Some(image_rectangle),
-                        image_data: bmp_buf,
+                        image_data: encode_display_image(
+                            img, display_image_format, DISPLAY_JPEG_QUALITY),
+                        format: display_image_format.into(),
                     });
                 }
                 return frame_result;
             }
+
+            // Don't wait for a frame while the camera is failing; serve the
+            // last image with the reconnecting status instead.
+            if locked_state.camera_reconnecting.load(Ordering::Relaxed) {
+                frame_result.camera_reconnecting = true;
+                if let Some(img) = &locked_state.scaled_image {
+                    frame_result.image = Some(Image{
+                        binning_factor: locked_state.scaled_image_binning_factor as i32,
+                        rectangle: Some(image_rectangle),
+                        image_data: encode_display_image(
+                            img, display_image_format, DISPLAY_JPEG_QUALITY),
+                        format: display_image_format.into(),
+                    });
+                }
+            }
         }  // locked_state.
+        if frame_result.camera_reconnecting {
+            tokio::time::sleep(CAMERA_RECONNECTING_POLL_INTERVAL).await;
+            return frame_result;
+        }
 
         // Populated only in OperatingMode::Operate mode.
         let mut tetra3_solve_result: Option<SolveResultProto> = None;
         let mut plate_solution: Option<PlateSolution> = None;
 
+        // The state lock must not be held while waiting for the next result,
+        // which can take a full exposure cycle; other requests (and other
+        // get_frame() calls) would stall behind it.
         let detect_result;
-        if state.lock().await.operation_settings.operating_mode.unwrap() ==
-            OperatingMode::Setup as i32
-        {
-            detect_result = state.lock().await.detect_engine.lock().await.
-                get_next_result(prev_frame_id).await;
+        if setup_mode {
+            detect_result = detect_engine.lock().await.get_next_result(prev_frame_id).await;
         } else {
-            plate_solution = Some(state.lock().await.solve_engine.lock().await.
+            plate_solution = Some(solve_engine.lock().await.
                                   get_next_result(prev_frame_id).await);
             let psr = plate_solution.as_ref().unwrap();
             tetra3_solve_result = psr.tetra3_solve_result.clone();
@@ -726,14 +2284,60 @@ impl MyCedar {
         let mut locked_state = state.lock().await;
 
         frame_result.frame_id = detect_result.frame_id;
+        let readout_time = detect_result.captured_image.readout_time;
+        match locked_state.last_served_frame {
+            Some((frame_id, _)) if frame_id == detect_result.frame_id => (),
+            prev_frame => {
+                if let Some((_, prev_readout_time)) = prev_frame {
+                    if let Ok(interval) = readout_time.duration_since(prev_readout_time) {
+                        locked_state.frame_interval_stats.add_value(interval.as_secs_f64());
+                    }
+                }
+                locked_state.last_served_frame = Some((detect_result.frame_id, readout_time));
+            }
+        }
+        if let Some(psr) = &plate_solution {
+            frame_result.frames_since_solve = psr.frames_since_solve;
+            frame_result.solve_coverage_fraction = psr.solve_coverage_fraction;
+            frame_result.solve_diagnostics = psr.solve_diagnostics.clone();
+            frame_result.solve_failure_reason = psr.solve_failure_reason.map(|r| r.into());
+            frame_result.reacquired =
+                locked_state.reacquisition.lock().unwrap().reacquired_frame_id ==
+                Some(detect_result.frame_id);
+
+            // The solution callback determines dwelling; we apply the
+            // corresponding update interval here because the callback cannot
+            // call into the engines.
+            let dwelling = locked_state.dwell_detector.lock().unwrap().dwelling;
+            frame_result.dwelling = dwelling;
+            if dwelling != locked_state.dwell_applied {
+                locked_state.dwell_applied = dwelling;
+                let update_interval = Self::operate_update_interval(&locked_state);
+                if let Err(x) = Self::set_update_interval(
+                    &*locked_state, update_interval).await
+                {
+                    warn!("Could not set update interval {:?}", x);
+                }
+            }
+        }
         let captured_image = &detect_result.captured_image;
         frame_result.exposure_time = Some(prost_types::Duration::try_from(
             captured_image.capture_params.exposure_duration).unwrap());
+        let auto_exposure =
+            locked_state.operation_settings.exposure_time ==
+            Some(prost_types::Duration{seconds: 0, nanos: 0});
+        frame_result.exposure_time_text = format!(
+            "{} ({})", format_duration(captured_image.capture_params.exposure_duration),
+            if auto_exposure { "auto" } else { "manual" });
+        let optimal_gain = locked_state.camera.lock().await.optimal_gain().value();
+        frame_result.gain_text = format_gain(
+            captured_image.capture_params.gain.value(), Some(optimal_gain));
         frame_result.capture_time = Some(prost_types::Timestamp::try_from(
             captured_image.readout_time).unwrap());
         frame_result.camera_temperature_celsius = captured_image.temperature.0 as f32;
 
         let mut centroids = Vec::<StarCentroid>::new();
+        let noise_estimate = detect_result.noise_estimate;
         for star in &detect_result.star_candidates {
             centroids.push(StarCentroid{
                 centroid_position: Some(ImageCoord {
@@ -741,10 +2345,14 @@ impl MyCedar {
                 }),
                 brightness: star.brightness,
                 num_saturated: star.num_saturated as i32,
+                snr: if noise_estimate > 0.0 { star.brightness / noise_estimate } else { 0.0 },
             });
         }
         frame_result.star_candidates = centroids;
         frame_result.noise_estimate = detect_result.noise_estimate;
+        frame_result.dark_frame_active = detect_result.dark_frame_applied;
+        frame_result.flat_field_active = detect_result.flat_field_applied;
+        let masked_hot_pixel_count = detect_result.masked_hot_pixel_count;
 
         let display_sampling = locked_state.display_sampling;
 
@@ -768,9 +2376,7 @@ impl MyCedar {
             // Populate `center_peak_image`.
             let center_peak_image = &fa.peak_image;
             let peak_image_region = &fa.peak_image_region;
-            let (center_peak_width, center_peak_height) =
-                center_peak_image.dimensions();
-            let mut center_peak_bmp_buf = Vec::<u8>::new();
+            let center_peak_buf;
             // center_peak_image_image is taken from the camera's full
             // resolution acquired image. If it is a color camera, we 2x2 bin it
             // to avoid displaying the Bayer grid.
@@ -778,16 +2384,13 @@ impl MyCedar {
             if locked_state.camera.lock().await.is_color() {
                 let binned_center_peak_image = bin_2x2(center_peak_image.clone());
                 binning_factor = 2;
-                center_peak_bmp_buf.reserve(
-                    (center_peak_width / 2 * center_peak_height / 2) as usize);
-                binned_center_peak_image.write_to(&mut Cursor::new(&mut center_peak_bmp_buf),
-                                                  ImageFormat::Bmp).unwrap();
+                center_peak_buf = encode_display_image(
+                    &binned_center_peak_image, display_image_format,
+                    DISPLAY_JPEG_QUALITY);
             } else {
                 binning_factor = 1;
-                center_peak_bmp_buf.reserve(
-                    (center_peak_width * center_peak_height) as usize);
-                center_peak_image.write_to(&mut Cursor::new(&mut center_peak_bmp_buf),
-                                           ImageFormat::Bmp).unwrap();
+                center_peak_buf = encode_display_image(
+                    center_peak_image, display_image_format, DISPLAY_JPEG_QUALITY);
             }
             frame_result.center_peak_image = Some(Image{
                 binning_factor,
@@ -797,7 +2400,8 @@ impl MyCedar {
                     width: peak_image_region.width() as i32,
                     height: peak_image_region.height() as i32,
                 }),
-                image_data: center_peak_bmp_buf,
+                image_data: center_peak_buf,
+                format: display_image_format.into(),
             });
         } else {
             peak_value = detect_result.peak_star_pixel;
@@ -809,6 +2413,7 @@ impl MyCedar {
         if detect_result.binned_image.is_some() {
             disp_image = detect_result.binned_image.as_ref().unwrap();
         }
+        frame_result.histogram = coarse_histogram(disp_image, HISTOGRAM_BINS);
         let mut resized_disp_image = disp_image;
         let resize_result: Arc<GrayImage>;
         if display_sampling {
@@ -816,17 +2421,15 @@ impl MyCedar {
             resized_disp_image = &resize_result;
         }
 
-        let mut bmp_buf = Vec::<u8>::new();
-        let (width, height) = resized_disp_image.dimensions();
-        bmp_buf.reserve((width * height) as usize);
-        let scaled_image = scale_image(resized_disp_image,
-                                       detect_result.display_black_level,
-                                       peak_value,
-                                       /*gamma=*/0.7);
-        // Save most recent display image.
-        locked_state.scaled_image = Some(Arc::new(scaled_image.clone()));
-        scaled_image.write_to(&mut Cursor::new(&mut bmp_buf),
-                              ImageFormat::Bmp).unwrap();
+        let scaled_image = Arc::new(scale_image(resized_disp_image,
+                                                detect_result.display_black_level,
+                                                peak_value,
+                                                /*gamma=*/0.7));
+        // Save most recent display image. This shares (rather than copies) the
+        // buffer that is encoded below.
+        locked_state.scaled_image = Some(scaled_image.clone());
+        let image_buf = encode_display_image(
+            &scaled_image, display_image_format, DISPLAY_JPEG_QUALITY);
 
         let binning_factor = locked_state.binning * if display_sampling { 2 } else { 1 };
         locked_state.scaled_image_binning_factor = binning_factor;
@@ -834,7 +2437,8 @@ impl MyCedar {
             binning_factor: binning_factor as i32,
             // Rectangle is always in full resolution coordinates.
             rectangle: Some(image_rectangle),
-            image_data: bmp_buf,
+            image_data: image_buf,
+            format: display_image_format.into(),
         });
 
         locked_state.serve_latency_stats.add_value(
@@ -842,25 +2446,33 @@ impl MyCedar {
         locked_state.overall_latency_stats.add_value(
             overall_start_time.elapsed().as_secs_f64());
 
-        frame_result.processing_stats =
-            Some(ProcessingStats{..Default::default()});
-        let stats = &mut frame_result.processing_stats.as_mut().unwrap();
-        stats.detect_latency = Some(detect_result.detect_latency_stats);
-        stats.serve_latency =
-            Some(locked_state.serve_latency_stats.value_stats.clone());
-        stats.overall_latency =
-            Some(locked_state.overall_latency_stats.value_stats.clone());
+        {
+            let mut stats = ProcessingStats{..Default::default()};
+            stats.detect_latency = Some(detect_result.detect_latency_stats);
+            stats.serve_latency =
+                Some(locked_state.serve_latency_stats.value_stats.clone());
+            stats.overall_latency =
+                Some(locked_state.overall_latency_stats.value_stats.clone());
+            stats.measured_frame_rate = detect_result.frame_rate.map(|r| r as f32);
+            if let Some(psr) = &plate_solution {
+                stats.measured_solve_rate = psr.solve_rate.map(|r| r as f32);
+                stats.solve_interval = Some(psr.solve_interval_stats.clone());
+                stats.solve_latency = Some(psr.solve_latency_stats.clone());
+                stats.solve_attempt_fraction =
+                    Some(psr.solve_attempt_stats.clone());
+                stats.solve_success_fraction =
+                    Some(psr.solve_success_stats.clone());
+            }
+            if include_processing_stats {
+                frame_result.processing_stats = Some(stats.clone());
+            }
+            locked_state.latest_processing_stats = Some(stats);
+        }
         if plate_solution.is_some() {
             let psr = &plate_solution.as_ref().unwrap();
-            stats.solve_interval = Some(psr.solve_interval_stats.clone());
-            stats.solve_latency = Some(psr.solve_latency_stats.clone());
-            stats.solve_attempt_fraction =
-                Some(psr.solve_attempt_stats.clone());
-            stats.solve_success_fraction =
-                Some(psr.solve_success_stats.clone());
             frame_result.slew_request = psr.slew_request.clone();
             if let Some(boresight_image) = &psr.boresight_image {
-                let mut bmp_buf = Vec::<u8>::new();
+                let image_buf;
                 let bsi_rect = psr.boresight_image_region.unwrap();
                 // boresight_image is taken from the camera's acquired image. In
                 // OPERATE mode the camera capture is always full resolution. If
@@ -870,14 +2482,13 @@ impl MyCedar {
                 if locked_state.camera.lock().await.is_color() {
                     let binned_boresight_image = bin_2x2(boresight_image.clone());
                     binning_factor = 2;
-                    bmp_buf.reserve((bsi_rect.width() / 2 * bsi_rect.height() / 2) as usize);
-                    binned_boresight_image.write_to(&mut Cursor::new(&mut bmp_buf),
-                                                    ImageFormat::Bmp).unwrap();
+                    image_buf = encode_display_image(
+                        &binned_boresight_image, display_image_format,
+                        DISPLAY_JPEG_QUALITY);
                 } else {
                     binning_factor = 1;
-                    bmp_buf.reserve((bsi_rect.width() * bsi_rect.height()) as usize);
-                    boresight_image.write_to(&mut Cursor::new(&mut bmp_buf),
-                                             ImageFormat::Bmp).unwrap();
+                    image_buf = encode_display_image(
+                        boresight_image, display_image_format, DISPLAY_JPEG_QUALITY);
                 }
                 frame_result.boresight_image = Some(Image{
                     binning_factor,
@@ -886,14 +2497,32 @@ impl MyCedar {
                                               origin_y: bsi_rect.top(),
                                               width: bsi_rect.width() as i32,
                                               height: bsi_rect.height() as i32}),
-                    image_data: bmp_buf,
+                    image_data: image_buf,
+                    format: display_image_format.into(),
                 });
             }
         }
         if tetra3_solve_result.is_some() {
             let tsr = &tetra3_solve_result.unwrap();
             frame_result.plate_solution = Some(tsr.clone());
+            // Report roll relative to the mount rather than the sensor. This
+            // applies whether or not the observer location is known.
+            let camera_mount_angle =
+                locked_state.preferences.camera_mount_angle.unwrap_or(0.0);
+            if let Some(roll) = tsr.roll {
+                frame_result.plate_solution.as_mut().unwrap().roll =
+                    Some(add_roll_offset(roll, camera_mount_angle));
+            }
             if tsr.status == Some(SolveStatus::MatchFound.into()) {
+                if let (Some(fov_override), Some(fov)) = (fixed_settings.fov_override, tsr.fov) {
+                    if !locked_state.fov_override_warned &&
+                        (fov - fov_override).abs() > 0.05 * fov_override
+                    {
+                        warn!("Plate solution FOV {:.2} differs from --fov_override {:.2}",
+                              fov, fov_override);
+                        locked_state.fov_override_warned = true;
+                    }
+                }
                 let celestial_coords;
                 if tsr.target_coords.len() > 0 {
                     celestial_coords = tsr.target_coords[0].clone();
@@ -902,6 +2531,46 @@ impl MyCedar {
                 }
                 let bs_ra = celestial_coords.ra.to_radians() as f64;
                 let bs_dec = celestial_coords.dec.to_radians() as f64;
+                let coord_system = locked_state.preferences.celestial_coord_system;
+                let alternate_coords =
+                    if coord_system == Some(CelestialCoordSystem::SystemGalactic.into()) {
+                        Some(galactic_from_equatorial(bs_ra, bs_dec))
+                    } else if coord_system == Some(CelestialCoordSystem::SystemEcliptic.into()) {
+                        Some(ecliptic_from_equatorial(bs_ra, bs_dec))
+                    } else {
+                        None
+                    };
+                frame_result.boresight_alternate_coords = alternate_coords.map(
+                    |(longitude, latitude)| AlternateCoords{
+                        system: coord_system.unwrap(),
+                        longitude: longitude.to_degrees() as f32,
+                        latitude: latitude.to_degrees() as f32,
+                    });
+
+                for matched_star in &tsr.matched_stars {
+                    if matched_star.magnitude > locked_state.bright_star_magnitude {
+                        continue;
+                    }
+                    let star_coord = matched_star.celestial_coord.as_ref().unwrap();
+                    let separation = angular_separation(
+                        bs_ra, bs_dec,
+                        star_coord.ra.to_radians() as f64,
+                        star_coord.dec.to_radians() as f64).to_degrees() as f32;
+                    if let Some(nearest) = &frame_result.nearest_bright_star {
+                        if nearest.separation <= separation {
+                            continue;
+                        }
+                    }
+                    let name = star_name(star_coord.ra as f64, star_coord.dec as f64)
+                        .map(|n| n.to_string()).or_else(|| matched_star.cat_id.clone());
+                    frame_result.nearest_bright_star = Some(NearestStar{
+                        cat_id: matched_star.cat_id.clone(),
+                        name,
+                        magnitude: matched_star.magnitude,
+                        celestial_coord: Some(star_coord.clone()),
+                        separation,
+                    });
+                }
 
                 if frame_result.slew_request.is_some() &&
                     locked_state.preferences.mount_type == Some(MountType::Equatorial.into())
@@ -927,41 +2596,79 @@ impl MyCedar {
                     let geo_location = fixed_settings.observer_location.clone().unwrap();
                     let lat = geo_location.latitude.to_radians() as f64;
                     let long = geo_location.longitude.to_radians() as f64;
-                    let time = captured_image.readout_time;
+                    let time = exposure_midpoint(
+                        captured_image.readout_time,
+                        captured_image.capture_params.exposure_duration);
+                    // Plate solutions and catalog targets are J2000; the local
+                    // sky (and hence alt/az and hour angle) is of the current
+                    // epoch. Clients set the server time along with the
+                    // observer location.
+                    let (bs_ra, bs_dec) = precess_from_j2000(bs_ra, bs_dec, time);
                     // alt/az of boresight. Also boresight hour angle.
                     let (bs_alt, bs_az, bs_ha) =
                         alt_az_from_equatorial(bs_ra, bs_dec, lat, long, time);
+                    // Converts true altitude to what is reported.
+                    let apparent_altitude = |alt: f64| -> f64 {
+                        if fixed_settings.refraction_correction.unwrap_or(true) {
+                            alt + refraction(
+                                alt,
+                                fixed_settings.temperature.map_or(
+                                    STANDARD_TEMPERATURE, |t| t as f64),
+                                fixed_settings.pressure.map_or(
+                                    STANDARD_PRESSURE, |p| p as f64))
+                        } else {
+                            alt
+                        }
+                    };
                     // ra/dec of zenith.
                     let (z_ra, z_dec) = equatorial_from_alt_az(
                         90_f64.to_radians(),
                         0.0,
                         lat, long, time);
-                    let mut zenith_roll_angle = (position_angle(
-                        bs_ra, bs_dec, z_ra, z_dec).to_degrees() as f32 +
-                                                 tsr.roll.unwrap()) % 360.0;
-                    // Arrange for angle to be 0..360.
-                    if zenith_roll_angle < 0.0 {
-                        zenith_roll_angle += 360.0;
+                    let zenith_roll_angle = add_roll_offset(
+                        position_angle(bs_ra, bs_dec, z_ra, z_dec).to_degrees() as f32 +
+                            tsr.roll.unwrap(),
+                        camera_mount_angle);
+                    if locked_state.preferences.mount_type == Some(MountType::AltAz.into()) {
+                        let rate = field_rotation_rate(lat, bs_az, bs_alt);
+                        // Radians/sec -> degrees/minute.
+                        frame_result.field_rotation_rate =
+                            Some((rate.to_degrees() * 60.0) as f32);
                     }
                     frame_result.location_based_info =
                         Some(LocationBasedInfo{zenith_roll_angle,
-                                               altitude: bs_alt.to_degrees() as f32,
+                                               altitude: apparent_altitude(bs_alt)
+                                                   .to_degrees() as f32,
                                                azimuth: bs_az.to_degrees() as f32,
                                                hour_angle: bs_ha.to_degrees() as f32,
                         });
 
+                    // Target alt/az, if there is a slew target.
+                    let mut target_alt_az = None;
+                    if let Some(slew_request) = frame_result.slew_request.as_mut() {
+                        let target_ra = slew_request.target.as_ref().unwrap().ra;
+                        let target_dec = slew_request.target.as_ref().unwrap().dec;
+                        let (target_ra, target_dec) = precess_from_j2000(
+                            target_ra.to_radians() as f64,
+                            target_dec.to_radians() as f64, time);
+                        let (target_alt, target_az, _target_ha) =
+                            alt_az_from_equatorial(target_ra, target_dec,
+                                                   lat, long, time);
+                        target_alt_az = Some((target_alt, target_az));
+                        let prefs = &locked_state.preferences;
+                        let min_altitude = horizon_altitude(
+                            &prefs.horizon_profile, target_az.to_degrees()).max(
+                                prefs.min_slew_altitude.unwrap_or(0.0) as f64);
+                        slew_request.target_below_horizon = Some(
+                            apparent_altitude(target_alt).to_degrees() < min_altitude);
+                    }
                     if frame_result.slew_request.is_some() &&
                         locked_state.preferences.mount_type == Some(MountType::AltAz.into())
                     {
                         let slew_request = frame_result.slew_request.as_mut().unwrap();
                         // Compute the movement required in azimuith and altitude to move
                         // boresight to target.
-                        let target_ra = slew_request.target.as_ref().unwrap().ra;
-                        let target_dec = slew_request.target.as_ref().unwrap().dec;
-                        let (target_alt, target_az, _target_ha) =
-                            alt_az_from_equatorial(target_ra.to_radians() as f64,
-                                                   target_dec.to_radians() as f64,
-                                                   lat, long, time);
+                        let (target_alt, target_az) = target_alt_az.unwrap();
                         let mut rel_az = target_az.to_degrees() - bs_az.to_degrees();
                         if rel_az < -180.0 {
                             rel_az += 360.0;
@@ -971,10 +2678,35 @@ impl MyCedar {
                         }
                         slew_request.offset_rotation_axis = Some(rel_az as f32);
 
-                        let rel_alt = target_alt.to_degrees() - bs_alt.to_degrees();
+                        let rel_alt = apparent_altitude(target_alt).to_degrees() -
+                            apparent_altitude(bs_alt).to_degrees();
                         slew_request.offset_tilt_axis = Some(rel_alt as f32);
                     }
                 }
+                if frame_result.slew_request.is_some() &&
+                    locked_state.preferences.mount_type == Some(MountType::Equatorial.into())
+                {
+                    let slew_request = frame_result.slew_request.as_mut().unwrap();
+                    let mut locked_position = locked_state.telescope_position.lock().unwrap();
+                    if let Some(lbi) = &frame_result.location_based_info {
+                        locked_position.infer_pier_side(lbi.hour_angle as f64);
+                        // Hour angle increases as right ascension decreases.
+                        let mut target_ha =
+                            lbi.hour_angle - slew_request.offset_rotation_axis.unwrap();
+                        if target_ha < -180.0 {
+                            target_ha += 360.0;
+                        }
+                        if target_ha > 180.0 {
+                            target_ha -= 360.0;
+                        }
+                        slew_request.meridian_flip_recommended =
+                            locked_position.meridian_flip_needed(target_ha as f64);
+                    }
+                    if locked_position.pier_side == PierSide::West {
+                        slew_request.offset_tilt_axis =
+                            slew_request.offset_tilt_axis.map(|t| -t);
+                    }
+                }
             }
         }
         let boresight_position =
@@ -987,10 +2719,61 @@ impl MyCedar {
                 Some(ImageCoord{x: locked_state.width as f32 / 2.0,
                                 y: locked_state.height as f32 / 2.0});
         }
-        frame_result.calibration_data =
-            Some(locked_state.calibration_data.lock().await.clone());
+        if plate_solution.is_some() {
+            let mut locked_solve_engine = locked_state.solve_engine.lock().await;
+            frame_result.blind_solve_in_progress =
+                locked_solve_engine.blind_solve_in_progress();
+            if let Some(fov) = locked_solve_engine.take_blind_solve_fov() {
+                Self::set_calibrated_fov(
+                    locked_state.calibration_data.lock().await.deref_mut(), fov,
+                    &locked_state.camera).await;
+            }
+        }
+        let mut calibration_data = locked_state.calibration_data.lock().await.clone();
+        calibration_data.masked_hot_pixel_count = masked_hot_pixel_count;
+        if let (Some(pixel_angular_size), Some(eyepiece_fov)) =
+            (calibration_data.pixel_angular_size, locked_state.preferences.eyepiece_fov)
+        {
+            let radius = radius_to_pixels((eyepiece_fov as f64 / 2.0).to_radians(),
+                                          (pixel_angular_size as f64).to_radians());
+            frame_result.eyepiece_fov_radius_pixels = Some(
+                radius as f32 / locked_state.scaled_image_binning_factor as f32);
+        }
+        frame_result.calibration_data = Some(calibration_data);
         frame_result.polar_align_advice = Some(
             locked_state.polar_analyzer.lock().unwrap().get_polar_align_advice());
+        frame_result.three_point_polar_align =
+            locked_state.polar_analyzer.lock().unwrap().get_three_point_status();
+        frame_result.drift_align = locked_state.drift_align.lock().unwrap().get_status();
+        frame_result.track_recording =
+            locked_state.track_recorder.lock().unwrap().is_recording();
+        if plate_solution.is_some() {
+            let fov = locked_state.calibration_data.lock().await.fov_horizontal;
+            let locked_spiral_search = locked_state.spiral_search.lock().unwrap();
+            if let (Some(fov), Some((center, time))) =
+                (fov, locked_spiral_search.search_center())
+            {
+                // Position of the search center on the mount's tilt axis.
+                let center_tilt =
+                    if locked_state.preferences.mount_type == Some(MountType::AltAz.into()) {
+                        fixed_settings.observer_location.as_ref().map(|geo_location| {
+                            let (alt, _az, _ha) = alt_az_from_equatorial(
+                                center.ra.to_radians() as f64,
+                                center.dec.to_radians() as f64,
+                                geo_location.latitude.to_radians() as f64,
+                                geo_location.longitude.to_radians() as f64,
+                                time);
+                            alt.to_degrees() as f32
+                        })
+                    } else {
+                        Some(center.dec)
+                    };
+                if let Some(center_tilt) = center_tilt {
+                    frame_result.spiral_search_advice =
+                        locked_spiral_search.get_advice(fov, center_tilt);
+                }
+            }
+        }
 
         frame_result
     }
@@ -1000,7 +2783,20 @@ impl MyCedar {
                      tetra3_script: String,
                      tetra3_database: String,
                      tetra3_uds: String,
+                     min_solve_interval: Duration,
+                     auto_save_interval: Duration,
+                     centroid_radius: Option<f32>,
+                     bright_star_magnitude: f32,
+                     max_calibration_time: Duration,
+                     max_solve_time: Duration,
+                     frame_recorder: Option<FrameRecorder>,
+                     focus_exposure_debounce: u32,
+                     min_update_interval: Duration,
+                     solve_outage: Duration,
+                     dwell_threshold: Duration,
+                     dwell_log_file: PathBuf,
                      camera: Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>,
+                     no_camera: bool,
                      telescope_position: Arc<Mutex<TelescopePosition>>,
                      binning: u32,
                      display_sampling: bool,
@@ -1009,7 +2805,16 @@ impl MyCedar {
                      min_detection_sigma: f32,
                      stats_capacity: usize,
                      preferences_file: PathBuf,
-                     log_file: PathBuf) -> Self {
+                     preferences_write_delay: Duration,
+                     ignore_saved_calibration: bool,
+                     fov_override: Option<f32>,
+                     sync_points_file: PathBuf,
+                     max_throughput_test_bytes: usize,
+                     dark_frame_dir: PathBuf,
+                     dark_frame_count: usize,
+                     cpu_temp_path: PathBuf,
+                     log_file: PathBuf,
+                     log_filter_handle: reload::Handle<EnvFilter, Registry>) -> Self {
         let detect_engine = Arc::new(tokio::sync::Mutex::new(DetectEngine::new(
             min_exposure_duration, max_exposure_duration,
             min_detection_sigma, base_detection_sigma,
@@ -1019,16 +2824,17 @@ impl MyCedar {
             /*auto_exposure=*/true,
             /*focus_mode_enabled=*/true,
             stats_capacity)));
+        let camera_reconnecting = detect_engine.lock().await.camera_reconnecting();
+        let (event_sender, _) = broadcast::channel(100);
         let tetra3_subprocess = Arc::new(Mutex::new(
             Tetra3Subprocess::new(tetra3_script, tetra3_database).unwrap()));
-        let mut preferences = Preferences{
-            celestial_coord_format: Some(CelestialCoordFormat::HmsDms.into()),
-            eyepiece_fov: Some(1.0),
-            night_vision_theme: Some(false),
-            show_perf_stats: Some(false),
-            hide_app_bar: Some(false),
-            mount_type: Some(MountType::Equatorial.into()),
-        };
+        let restart_event_sender = event_sender.clone();
+        tetra3_subprocess.lock().unwrap().set_restart_callback(Box::new(move || {
+            Self::publish_event(&restart_event_sender, CedarEvent{
+                event_type: EventType::SolverRestarted.into(),
+                ..Default::default()});
+        }));
+        let mut preferences = Self::default_preferences();
 
         // Load UI preferences file.
         let prefs_path = Path::new(&preferences_file);
@@ -1038,12 +2844,7 @@ impl MyCedar {
         } else {
             match Preferences::decode(bytes.unwrap().as_slice()) {
                 Ok(mut p) => {
-                    if p.eyepiece_fov.unwrap() < 0.1 {
-                        p.eyepiece_fov = Some(0.1);
-                    }
-                    if p.eyepiece_fov.unwrap() > 2.0 {
-                        p.eyepiece_fov = Some(2.0);
-                    }
+                    Self::sanitize_preferences(&mut p);
                     preferences = p;
                 }
                 Err(e) => {
@@ -1052,15 +2853,77 @@ impl MyCedar {
             }
         }
 
+        // Load previously captured sync points.
+        let mut sync_points = SyncPointList::default();
+        match fs::read(&sync_points_file) {
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Could not read file {:?}: {:?}", sync_points_file, e);
+                }
+            },
+            Ok(bytes) => {
+                match SyncPointList::decode(bytes.as_slice()) {
+                    Ok(s) => {
+                        sync_points = s;
+                    }
+                    Err(e) => {
+                        warn!("Could not decode sync points {:?}", e);
+                    },
+                }
+            }
+        }
+
+        // Load saved calibration, if it applies to our camera. A given
+        // `fov_override` takes precedence.
+        let calibration_file = preferences_file.with_file_name("cedar_calibration.binpb");
+        let saved_calibration = if ignore_saved_calibration || fov_override.is_some() {
+            None
+        } else {
+            let locked_camera = camera.lock().await;
+            Self::read_calibration_file(&calibration_file, &locked_camera.model(),
+                                        locked_camera.dimensions())
+        };
+
+        let dark_frames = match DarkFrameLibrary::new(&dark_frame_dir) {
+            Ok(dfl) => Some(Arc::new(Mutex::new(dfl))),
+            Err(e) => {
+                warn!("Dark frames disabled: {:?}", e);
+                None
+            }
+        };
+        // Hot pixel maps live alongside the dark frames.
+        let hot_pixels = if dark_frames.is_some() {
+            match HotPixelLibrary::new(&dark_frame_dir) {
+                Ok(hpl) => Some(Arc::new(Mutex::new(hpl))),
+                Err(e) => {
+                    warn!("Hot pixel masking disabled: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let preferences_read_only = !Self::is_writable(&preferences_file);
+        if preferences_read_only {
+            warn!("Preferences file {:?} is not writable; preference changes will \
+                   not persist", preferences_file);
+        }
+
         let fixed_settings = Arc::new(Mutex::new(FixedSettings {
-            observer_location: None,
+            observer_location: preferences.observer_location.clone(),
             current_time: None,
             session_name: None,
             max_exposure_time: Some(
                 prost_types::Duration::try_from(max_exposure_duration).unwrap()),
+            fov_override,
+            temperature: Some(STANDARD_TEMPERATURE as f32),
+            pressure: Some(STANDARD_PRESSURE as f32),
+            refraction_correction: Some(true),
         }));
 
         let polar_analyzer = Arc::new(Mutex::new(PolarAnalyzer::new()));
+        let drift_align = Arc::new(Mutex::new(DriftAlignAssistant::new()));
 
         // Define callback invoked from SolveEngine().
         let closure_fixed_settings = fixed_settings.clone();
@@ -1069,6 +2932,19 @@ impl MyCedar {
             /*gap_tolerance=*/Duration::from_secs(3),
             /*bump_tolerance=*/Duration::from_secs_f32(2.0))));
         let closure_polar_analyzer = polar_analyzer.clone();
+        let closure_drift_align = drift_align.clone();
+        let reacquisition = Arc::new(Mutex::new(ReacquisitionDetector::new(solve_outage)));
+        let closure_reacquisition = reacquisition.clone();
+        let dwell_detector = Arc::new(Mutex::new(
+            DwellDetector::new(dwell_threshold, dwell_log_file)));
+        let closure_dwell_detector = dwell_detector.clone();
+        let spiral_search = Arc::new(Mutex::new(SpiralSearchAdvisor::new(
+            SPIRAL_SEARCH_FAILURE_THRESHOLD, SPIRAL_SEARCH_WAYPOINTS)));
+        let closure_spiral_search = spiral_search.clone();
+        let track_recorder = Arc::new(Mutex::new(TrackRecorder::new(
+            log_file.parent().unwrap_or(Path::new(".")))));
+        let closure_track_recorder = track_recorder.clone();
+        let closure_event_sender = event_sender.clone();
         let closure = Arc::new(move |detect_result: Option<DetectResult>,
                                      solve_result_proto: Option<SolveResultProto>|
         {
@@ -1078,11 +2954,30 @@ impl MyCedar {
                 closure_fixed_settings.lock().unwrap().observer_location.clone(),
                 &mut closure_telescope_position.lock().unwrap(),
                 &mut motion_estimator.lock().unwrap(),
-                &mut closure_polar_analyzer.lock().unwrap())
+                &mut closure_polar_analyzer.lock().unwrap(),
+                &mut closure_drift_align.lock().unwrap(),
+                &mut closure_reacquisition.lock().unwrap(),
+                &mut closure_dwell_detector.lock().unwrap(),
+                &mut closure_spiral_search.lock().unwrap(),
+                &mut closure_track_recorder.lock().unwrap(),
+                &closure_event_sender)
         });
         let dimensions = camera.lock().await.dimensions();
+        // Not all cameras provide offset control. Find out up front, rather
+        // than attempting (and failing) each time we set the offset.
+        let camera_supports_offset = {
+            let mut locked_camera = camera.lock().await;
+            let offset = locked_camera.get_offset();
+            locked_camera.set_offset(offset).is_ok()
+        };
+        if !camera_supports_offset {
+            info!("Camera does not support offset setting");
+        }
         let state = Arc::new(tokio::sync::Mutex::new(CedarState {
             camera: camera.clone(),
+            camera_supports_offset,
+            no_camera,
+            camera_reconnecting,
             fixed_settings,
             operation_settings: OperationSettings {
                 operating_mode: Some(OperatingMode::Setup as i32),
@@ -1097,6 +2992,14 @@ impl MyCedar {
                     seconds: 1, nanos: 0,
                 }),
                 log_dwelled_positions: Some(false),
+                auto_save_images: Some(false),
+                display_image_format: Some(DisplayImageFormat::Bmp.into()),
+                auto_exposure: Some(false),
+                binning: Some(binning as i32),
+                display_sampling: Some(display_sampling),
+                max_solve_time: Some(prost_types::Duration::try_from(
+                    max_solve_time).unwrap()),
+                center_region_fraction: Some(DEFAULT_CENTER_REGION_FRACTION),
             },
             calibration_data: Arc::new(tokio::sync::Mutex::new(
                 CalibrationData{..Default::default()})),
@@ -1105,13 +3008,20 @@ impl MyCedar {
             solve_engine: Arc::new(tokio::sync::Mutex::new(SolveEngine::new(
                 tetra3_subprocess.clone(), detect_engine.clone(), tetra3_uds,
                 /*update_interval=*/Duration::ZERO,
+                min_solve_interval,
+                auto_save_interval,
                 stats_capacity, closure).await.unwrap())),
             calibrator: Arc::new(tokio::sync::Mutex::new(
                 Calibrator::new(camera.clone()))),
             telescope_position,
             polar_analyzer,
+            drift_align,
             binning, display_sampling,
+            written_preferences: preferences.encode_to_vec(),
+            preferences_write_pending: false,
             preferences,
+            bright_star_magnitude,
+            sync_points,
             scaled_image: None,
             scaled_image_binning_factor: 1,
             width: dimensions.0 as u32,
@@ -1120,21 +3030,77 @@ impl MyCedar {
             cancel_calibration: Arc::new(Mutex::new(false)),
             calibration_start: Instant::now(),
             calibration_duration_estimate: Duration::MAX,
+            max_calibration_time,
+            calibrated_solve_timeout: None,
+            calibration_file,
+            saved_calibration,
+            fov_override_warned: false,
+            min_update_interval,
             center_peak_position: Arc::new(Mutex::new(None)),
             serve_latency_stats: ValueStatsAccumulator::new(stats_capacity),
             overall_latency_stats: ValueStatsAccumulator::new(stats_capacity),
+            frame_interval_stats: ValueStatsAccumulator::new(stats_capacity),
+            last_served_frame: None,
+            latest_processing_stats: None,
+            event_sender,
+            reacquisition,
+            dwell_detector,
+            spiral_search,
+            track_recorder,
+            dark_frames: dark_frames.clone(),
+            hot_pixels: hot_pixels.clone(),
+            dark_frame_dir: dark_frame_dir.clone(),
+            dwell_applied: false,
+            thermal_throttling: false,
         }));
         let cedar = MyCedar {
             state: state.clone(),
             preferences_file,
+            preferences_read_only,
+            preferences_write_delay,
+            sync_points_file,
+            max_throughput_test_bytes,
+            dark_frame_count,
+            cpu_temp_path,
+            processor_model: Self::read_device_tree_string("model"),
+            serial_number: Self::read_device_tree_string("serial-number"),
             log_file,
+            log_filter_handle,
         };
         // Set pre-calibration defaults on camera.
         let locked_state = state.lock().await;
         if let Err(x) = Self::set_pre_calibration_defaults(&*locked_state).await {
             warn!("Could not set default settings on camera {:?}", x);
         }
+        // Start out in SETUP mode, full speed (subject to
+        // `min_update_interval`).
+        if let Err(x) = Self::set_update_interval(&*locked_state, Duration::ZERO).await {
+            warn!("Could not set update interval {:?}", x);
+        }
         locked_state.detect_engine.lock().await.set_focus_mode(true, binning);
+        if let Err(x) = locked_state.detect_engine.lock().await.set_focus_exposure_debounce(
+            focus_exposure_debounce)
+        {
+            warn!("Could not set focus exposure debounce {:?}", x);
+        }
+        if let Err(x) = locked_state.solve_engine.lock().await.set_centroid_radius(
+            centroid_radius)
+        {
+            warn!("Could not set centroid radius {:?}", x);
+        }
+        locked_state.solve_engine.lock().await.set_frame_recorder(frame_recorder);
+        if let Some(dark_frames) = dark_frames {
+            let mut locked_detect_engine = locked_state.detect_engine.lock().await;
+            locked_detect_engine.set_dark_frames(dark_frames);
+            locked_detect_engine.set_dark_frame_subtraction(
+                locked_state.preferences.dark_frame_subtraction.unwrap());
+        }
+        if let Some(hot_pixels) = hot_pixels {
+            locked_state.detect_engine.lock().await.set_hot_pixels(hot_pixels);
+        }
+        if let Err(x) = Self::apply_flat_field(&locked_state).await {
+            warn!("Flat field disabled: {:?}", x);
+        }
         Self::update_accuracy_adjusted_params(&*locked_state).await;
 
         cedar
@@ -1153,6 +3119,84 @@ impl MyCedar {
         locked_detect_engine.set_accuracy_multiplier(multiplier);
     }
 
+    // Returns the named device-tree property, or "unknown" (with a warning) if
+    // it is not available, as on most non-Raspberry Pi systems.
+    fn read_device_tree_string(name: &str) -> String {
+        let path = Path::new("/proc/device-tree").join(name);
+        match fs::read_to_string(&path) {
+            // Device-tree strings are nul terminated.
+            Ok(value) => value.trim_end_matches('\0').trim().to_string(),
+            Err(e) => {
+                warn!("Could not read {:?}: {:?}", path, e);
+                "unknown".to_string()
+            }
+        }
+    }
+
+    // Returns the CPU temperature in degrees Celsius, or None if it cannot be
+    // read from `path`.
+    fn read_cpu_temperature(path: &Path) -> Option<f32> {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                debug!("Could not read {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        match content.trim().parse::<f32>() {
+            Ok(millidegrees) => Some(millidegrees / 1000.0),
+            Err(e) => {
+                warn!("Could not parse {:?} from {:?}: {:?}", content, path, e);
+                None
+            }
+        }
+    }
+
+    // Returns the current log file along with any rotated log files, ordered
+    // from least to most recently modified.
+    fn find_log_files(log_file: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = match log_file.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        let base_name = log_file.file_name().unwrap_or_default().to_string_lossy();
+        let mut files = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_name().to_string_lossy().starts_with(base_name.as_ref()) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                files.push((metadata.modified()?, entry.path()));
+            }
+        }
+        files.sort();
+        Ok(files.into_iter().map(|(_, path)| path).collect())
+    }
+
+    // Sends the content of `path` in chunks. Stops early (without error) if the
+    // receiver is closed.
+    fn send_log_file(path: &Path,
+                     sender: &tokio::sync::mpsc::Sender<Result<LogChunk, tonic::Status>>)
+                     -> io::Result<()> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut f = fs::File::open(path)?;
+        loop {
+            let mut data = vec![0_u8; CHUNK_SIZE];
+            let count = f.read(&mut data)?;
+            if count == 0 {
+                return Ok(());
+            }
+            data.truncate(count);
+            let chunk = LogChunk{file_name: file_name.clone(), data};
+            if sender.blocking_send(Ok(chunk)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
     fn read_file_tail(log_file: &PathBuf, bytes_to_read: i32) -> io::Result<String> {
         let mut f = fs::File::open(log_file)?;
         let len = f.metadata()?.len();
@@ -1160,7 +3204,9 @@ impl MyCedar {
         f.seek(SeekFrom::End(-to_read))?;
         let mut content = String::new();
         f.read_to_string(&mut content)?;
-        // Trim leading portion of content until first newline.
+        // Trim leading portion of content until first newline. This also
+        // ensures that with --log_format=json, only whole JSON lines are
+        // returned.
         if let Some(pos) = content.find('\n') {
             content = content[pos+1..].to_string();
         }
@@ -1172,11 +3218,27 @@ impl MyCedar {
                          geo_location: Option<LatLong>,
                          telescope_position: &mut TelescopePosition,
                          motion_estimator: &mut MotionEstimator,
-                         polar_analyzer: &mut PolarAnalyzer) -> Option<CelestialCoord> {
+                         polar_analyzer: &mut PolarAnalyzer,
+                         drift_align: &mut DriftAlignAssistant,
+                         reacquisition: &mut ReacquisitionDetector,
+                         dwell_detector: &mut DwellDetector,
+                         spiral_search: &mut SpiralSearchAdvisor,
+                         track_recorder: &mut TrackRecorder,
+                         event_sender: &broadcast::Sender<CedarEvent>)
+                         -> Option<CelestialCoord> {
         if solve_result_proto.is_none() {
             telescope_position.boresight_valid = false;
             if let Some(detect_result) = detect_result {
-                motion_estimator.add(detect_result.captured_image.readout_time, None, None);
+                let readout_time = detect_result.captured_image.readout_time;
+                motion_estimator.add(readout_time, None, None);
+                dwell_detector.update(readout_time,
+                                      motion_estimator.get_estimate().is_some());
+                spiral_search.failed();
+            } else {
+                // Solve engine is stopping.
+                reacquisition.reset();
+                dwell_detector.reset();
+                spiral_search.reset();
             }
         } else {
             let solve_result_proto = solve_result_proto.unwrap();
@@ -1190,21 +3252,50 @@ impl MyCedar {
             telescope_position.boresight_ra = coords.ra as f64;
             telescope_position.boresight_dec = coords.dec as f64;
             telescope_position.boresight_valid = true;
-            let readout_time = detect_result.unwrap().captured_image.readout_time;
+            let detect_result = detect_result.unwrap();
+            let readout_time = detect_result.captured_image.readout_time;
+            if reacquisition.solved(readout_time, detect_result.frame_id) {
+                info!("Plate solving reacquired after outage");
+                // Don't let the motion estimator interpret the outage gap as
+                // motion.
+                motion_estimator.reset();
+                Self::publish_event(event_sender, CedarEvent{
+                    event_type: EventType::Reacquired.into(),
+                    ..Default::default()});
+            }
             motion_estimator.add(readout_time, Some(coords.clone()), solve_result_proto.rmse);
+            spiral_search.solved(&coords, readout_time);
+            if dwell_detector.update(readout_time, motion_estimator.get_estimate().is_some()) {
+                info!("Dwelling at {:?}", coords);
+                if dwell_detector.log_enabled {
+                    dwell_detector.log_position(readout_time, &coords,
+                                                solve_result_proto.rmse);
+                }
+            }
+            let mut alt_az = None;
             if let Some(geo_location) = geo_location {
                 let lat = geo_location.latitude.to_radians() as f64;
                 let long = geo_location.longitude.to_radians() as f64;
                 let bs_ra = coords.ra.to_radians() as f64;
                 let bs_dec = coords.dec.to_radians() as f64;
+                // The solved position is that of the middle of the exposure.
+                let observation_time = exposure_midpoint(
+                    readout_time,
+                    detect_result.captured_image.capture_params.exposure_duration);
                 // alt/az of boresight. Also boresight hour angle.
-                let (_alt, _az, ha) =
-                    alt_az_from_equatorial(bs_ra, bs_dec, lat, long, readout_time);
+                let (alt, az, ha) =
+                    alt_az_from_equatorial(bs_ra, bs_dec, lat, long, observation_time);
+                alt_az = Some((alt.to_degrees(), az.to_degrees()));
                 polar_analyzer.process_solution(&coords,
                                                 ha.to_degrees() as f32,
                                                 geo_location.latitude,
-                                                &motion_estimator.get_estimate());
+                                                &motion_estimator.get_estimate(),
+                                                observation_time);
+                drift_align.process_solution(&coords, ha.to_degrees() as f32,
+                                             geo_location.latitude, observation_time);
             }
+            track_recorder.record(readout_time, &coords, solve_result_proto.roll,
+                                  solve_result_proto.rmse, alt_az);
         }
         if telescope_position.slew_active {
             Some(CelestialCoord{ra: telescope_position.slew_target_ra as f32,
@@ -1306,10 +3397,21 @@ struct Args {
     #[arg(long)]
     display_sampling: Option<bool>,
 
+    /// Number of threads used for per-frame image processing (e.g. display
+    /// image scaling). 0 uses one per CPU core; 1 processes serially.
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+
     /// Test image to use instead of camera.
     #[arg(long, default_value = "")]
     test_image: String,
 
+    /// When `test_image` is used, processing is done no more often than this
+    /// (seconds), regardless of the requested update interval. Set to 0 to
+    /// process the test image as fast as possible (e.g. for benchmarking).
+    #[arg(long, value_parser = parse_duration, default_value = "0.2")]
+    test_image_update_interval: Duration,
+
     /// Minimum exposure duration, seconds.
     #[arg(long, value_parser = parse_duration, default_value = "0.00001")]
     min_exposure: Duration,
@@ -1338,10 +3440,173 @@ struct Args {
     #[arg(long, default_value_t = 5.0)]
     min_sigma: f32,
 
+    /// Minimum time between plate solve attempts, seconds. This limits the
+    /// CPU used by the plate solver on fast hosts when the update interval is
+    /// zero (as fast as possible). Does not affect calibration.
+    #[arg(long, value_parser = parse_duration, default_value = "0.05")]
+    min_solve_interval: Duration,
+
+    /// In SETUP mode, auto exposure changes are made only after being called
+    /// for on this many consecutive frames. Larger values give a steadier
+    /// focus image; 1 gives the most responsive exposure. Large brightness
+    /// changes are responded to immediately regardless.
+    #[arg(long, default_value_t = 3)]
+    focus_exposure_debounce: u32,
+
+    /// When OperationSettings.auto_save_images is enabled, the minimum time
+    /// between saved images, seconds.
+    #[arg(long, value_parser = parse_duration, default_value = "30.0")]
+    auto_save_interval: Duration,
+
+    /// If given, star centroids farther than this many degrees from the
+    /// boresight are de-prioritized when plate solving. This can improve solve
+    /// reliability with wide field lenses that have strong edge distortion.
+    /// The de-prioritized stars are still displayed and used to verify
+    /// matches.
+    #[arg(long)]
+    solve_centroid_radius: Option<f32>,
+
+    /// Magnitude limit for stars reported as FrameResult.nearest_bright_star.
+    #[arg(long, default_value_t = 3.0)]
+    bright_star_magnitude: f32,
+
+    /// In OPERATE mode, a gap of this many seconds between successful plate
+    /// solutions is treated as an outage (e.g. clouds). The next successful
+    /// solution is reported as a reacquisition.
+    #[arg(long, value_parser = parse_duration, default_value = "10.0")]
+    solve_outage: Duration,
+
+    /// In OPERATE mode, the boresight is considered to be dwelling once it has
+    /// been stationary for this many seconds. While dwelling, the
+    /// OperationSettings.dwell_update_interval applies.
+    #[arg(long, value_parser = parse_duration, default_value = "5.0")]
+    dwell_threshold: Duration,
+
+    /// When OperationSettings.log_dwelled_positions is enabled, the position
+    /// at the onset of each dwell is appended to this file.
+    #[arg(long, default_value = "./cedar_dwelled_positions.csv")]
+    dwell_log: String,
+
+    /// Maximum time, in seconds, that the SETUP -> OPERATE calibration may
+    /// take. When exceeded, the remaining calibration steps are skipped and
+    /// fallback values are used.
+    #[arg(long, value_parser = parse_duration, default_value = "30.0")]
+    max_calibration_time: Duration,
+
+    /// Maximum time, in seconds, for each OPERATE mode plate solve. The solve
+    /// timeout is otherwise ten times the calibration solve duration (at least
+    /// 0.5 seconds). Can be changed at runtime with
+    /// OperationSettings.max_solve_time.
+    #[arg(long, value_parser = parse_duration, default_value = "1.0")]
+    max_solve_time: Duration,
+
+    /// If given, OPERATE mode frames (full resolution images plus capture,
+    /// detection, and plate solve metadata) are recorded to this directory,
+    /// for offline debugging.
+    #[arg(long)]
+    record_frames_dir: Option<String>,
+
+    /// When recording frames, the number of most recent frames that are kept.
+    #[arg(long, default_value_t = 100)]
+    record_frames_max: usize,
+
+    /// When recording frames, the minimum time between recorded frames,
+    /// seconds.
+    #[arg(long, value_parser = parse_duration, default_value = "10.0")]
+    record_frames_interval: Duration,
+
+    /// Frame recording is paused when free disk space falls below this many
+    /// megabytes.
+    #[arg(long, default_value_t = 500)]
+    record_frames_min_free_mb: u64,
+
     /// Path to UI preferences file.
     #[arg(long, default_value = "./cedar_ui_prefs.binpb")]
     ui_prefs: String,
 
+    /// Changes to UI preferences are written to `ui_prefs` after this delay
+    /// (seconds), coalescing rapid updates into a single write.
+    #[arg(long, value_parser = parse_duration, default_value = "3.0")]
+    ui_prefs_write_delay: Duration,
+
+    /// Successful calibrations are saved alongside `ui_prefs` and reused after
+    /// a restart. This forces a fresh calibration instead.
+    #[arg(long)]
+    ignore_saved_calibration: bool,
+
+    /// Horizontal field of view (degrees) of a known lens. When given,
+    /// calibration skips the optical (plate solving) step and uses this value.
+    #[arg(long)]
+    fov_override: Option<f32>,
+
+    /// Path to file in which captured sync points are kept.
+    #[arg(long, default_value = "./cedar_sync_points.binpb")]
+    sync_points_file: String,
+
+    /// Maximum payload size (bytes), in each direction, for the
+    /// ThroughputTest RPC. Note that gRPC limits messages to 4MB by default.
+    #[arg(long, default_value_t = 2_000_000)]
+    max_throughput_test_bytes: usize,
+
+    /// Directory in which captured dark frames are kept.
+    #[arg(long, default_value = "./dark_frames")]
+    dark_frame_dir: String,
+
+    /// Number of exposures combined (by median) when capturing a dark frame.
+    #[arg(long, default_value_t = 9)]
+    dark_frame_count: usize,
+
+    /// Whether to serve Prometheus metrics at /metrics.
+    #[arg(long, default_value_t = false)]
+    metrics: bool,
+
+    /// File from which the CPU temperature (millidegrees Celsius) is read.
+    #[arg(long, default_value = "/sys/class/thermal/thermal_zone0/temp")]
+    cpu_temp_path: String,
+
+    /// CPU temperature (degrees Celsius) above which OPERATE mode updates are
+    /// slowed to let the CPU cool. 0 disables.
+    #[arg(long, default_value_t = 75.0)]
+    cpu_temp_throttle: f32,
+
+    /// Address on which the web app and gRPC service are served. The default
+    /// listens on all interfaces.
+    #[arg(long, default_value = "0.0.0.0")]
+    bind_addr: String,
+
+    /// Port for the web app and gRPC service.
+    #[arg(long, default_value_t = 8080)]
+    http_port: u16,
+
+    /// Optional second port (e.g. 80) also serving the web app and gRPC
+    /// service.
+    #[arg(long)]
+    alt_http_port: Option<u16>,
+
+    /// TCP port for Stellarium's telescope control protocol. 0 disables.
+    #[arg(long, default_value_t = 10001)]
+    stellarium_port: u16,
+
+    /// Whether to serve INDI clients, presenting Cedar as a telescope driver.
+    #[arg(long, default_value_t = false)]
+    enable_indi: bool,
+
+    /// TCP port for INDI clients, if enabled.
+    #[arg(long, default_value_t = 7624)]
+    indi_port: u16,
+
+    /// Serial/USB GPS receiver (e.g. /dev/ttyACM0) providing NMEA sentences,
+    /// from which the observer location is set. The device's baud rate must
+    /// already be configured (e.g. with stty). If not given, the location
+    /// comes from the client.
+    #[arg(long)]
+    gps_device: Option<String>,
+
+    /// Whether the GPS receiver (see `gps_device`) also sets the system clock.
+    /// Requires the CAP_SYS_TIME capability.
+    #[arg(long, default_value_t = false)]
+    gps_set_clock: bool,
+
     /// Directory for log file(s).
     #[arg(long, default_value = ".")]
     log_dir: String,
@@ -1350,7 +3615,10 @@ struct Args {
     #[arg(long, default_value = "cedar_log.txt")]
     log_file: String,
 
-    // TODO: max solve time
+    /// Format of the log file: "text", or "json" for one JSON object per line
+    /// (for log collectors). Stdout logging is always text.
+    #[arg(long, default_value = "text")]
+    log_format: String,
 }
 
 // Adapted from
@@ -1361,6 +3629,63 @@ fn parse_duration(arg: &str)
     Ok(std::time::Duration::from_secs_f32(seconds))
 }
 
+// /readyz fails if the most recent frame is older than this.
+const READY_MAX_FRAME_AGE: Duration = Duration::from_secs(10);
+
+// Handles the /healthz (`ready` false) and /readyz (`ready` true) endpoints.
+// Healthy means the camera has produced at least one frame; ready means it
+// has done so recently. The JSON body gives details either way.
+async fn health_response(state: Arc<tokio::sync::Mutex<CedarState>>, ready: bool)
+                         -> (StatusCode, String) {
+    let locked_state = state.lock().await;
+    let camera_model = locked_state.camera.lock().await.model().to_string();
+    let operating_mode = OperatingMode::try_from(
+        locked_state.operation_settings.operating_mode.unwrap())
+        .map_or("UNKNOWN", |m| m.as_str_name());
+    let last_readout_time = locked_state.detect_engine.lock().await.last_readout_time();
+    let frame_age = last_readout_time.map(
+        |t| SystemTime::now().duration_since(t).unwrap_or(Duration::ZERO));
+    let ok = match frame_age {
+        Some(age) => !ready || age <= READY_MAX_FRAME_AGE,
+        None => false,
+    };
+    let frame_age_json = match frame_age {
+        Some(age) => format!("{:.3}", age.as_secs_f64()),
+        None => "null".to_string(),
+    };
+    let body = format!(
+        "{{\"ok\": {}, \"camera_model\": {:?}, \"operating_mode\": {:?}, \
+         \"last_frame_age_secs\": {}}}\n",
+        ok, camera_model, operating_mode, frame_age_json);
+    let status = if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, body)
+}
+
+// Handles the /metrics endpoint. If the server state is busy for too long we
+// give up rather than queue up behind the frame pipeline.
+async fn metrics_response(state: Arc<tokio::sync::Mutex<CedarState>>,
+                          cpu_temp_path: PathBuf) -> (StatusCode, String) {
+    let snapshot = {
+        let locked_state =
+            match tokio::time::timeout(Duration::from_secs(2), state.lock()).await {
+                Ok(ls) => ls,
+                Err(_) => {
+                    return (StatusCode::SERVICE_UNAVAILABLE, "Server busy\n".to_string());
+                }
+            };
+        MetricsSnapshot{
+            frame_interval: Some(locked_state.frame_interval_stats.value_stats.clone()),
+            processing_stats: locked_state.latest_processing_stats.clone(),
+            cpu_temperature: None,
+        }
+    };
+    let snapshot = MetricsSnapshot{
+        cpu_temperature: MyCedar::read_cpu_temperature(&cpu_temp_path),
+        ..snapshot
+    };
+    (StatusCode::OK, format_metrics(&snapshot))
+}
+
 // Adapted from
 // https://github.com/tokio-rs/axum/tree/main/examples/rest-grpc-multiplex
 // https://github.com/tokio-rs/axum/blob/main/examples/static-file-server
@@ -1376,32 +3701,68 @@ async fn main() {
     let (non_blocking_stdout, _guard2) = NonBlockingBuilder::default()
         .lossy(false)
         .finish(std::io::stdout());
+    let (log_filter, log_filter_handle) = reload::Layer::new(
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")));
+    let json_log = match args.log_format.as_str() {
+        "text" => false,
+        "json" => true,
+        _ => {
+            eprintln!("Unrecognized 'log_format' value: {}", args.log_format);
+            std::process::exit(1);
+        }
+    };
+    let (text_file_layer, json_file_layer) = if json_log {
+        (None, Some(fmt::layer().json().with_writer(non_blocking_file)))
+    } else {
+        (Some(fmt::layer().with_ansi(false).with_writer(non_blocking_file)), None)
+    };
     let _subscriber = registry()
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(log_filter)
         .with(fmt::layer().with_writer(non_blocking_stdout))
-        .with(fmt::layer().with_ansi(false).with_writer(non_blocking_file))
+        .with(text_file_layer)
+        .with(json_file_layer)
         .init();
 
+    if args.threads > 0 {
+        if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(args.threads)
+            .build_global()
+        {
+            warn!("Could not configure {} image processing threads: {:?}",
+                  args.threads, e);
+        }
+    }
+
     info!("Using Tetra3 server {:?} listening at {:?}",
           args.tetra3_script, args.tetra3_socket);
-    // Build the static content web service.
-    let rest = Router::new().nest_service(
-        "/", ServeDir::new("../cedar_flutter/build/web"));
-
-    let camera_interface = match args.camera_interface.as_str() {
-        "" => None,
-        "asi" => Some(CameraInterface::ASI),
-        "rpi" => Some(CameraInterface::Rpi),
-        _ => {
-            error!("Unrecognized 'camera_interface' value: {}", args.camera_interface);
-            std::process::exit(1);
-        }
+    let Some(camera_interface) = parse_camera_interface(&args.camera_interface) else {
+        error!("Unrecognized 'camera_interface' value: {}", args.camera_interface);
+        std::process::exit(1);
     };
-    let abstract_cam = match select_camera(camera_interface, args.camera_index) {
-        Ok(cam) => cam,
+    // Without a camera we still start up, serving a blank image, so that the
+    // UI can report the problem.
+    let mut no_camera = false;
+    let mut camera_opener: Option<CameraOpener> = None;
+    let abstract_cam: Box<dyn AbstractCamera + Send> =
+        match select_camera(camera_interface, args.camera_index) {
+        Ok(cam) => {
+            // If the camera stops working (e.g. drops off the USB bus), the
+            // detect engine reopens it the same way.
+            if args.test_image.is_empty() {
+                let interface_arg = args.camera_interface.clone();
+                let camera_index = args.camera_index;
+                let opener: CameraOpener = Arc::new(move || {
+                    select_camera(parse_camera_interface(&interface_arg).unwrap(),
+                                  camera_index)
+                });
+                camera_opener = Some(opener);
+            }
+            cam
+        },
         Err(e) => {
             error!("Could not select camera: {:?}", e);
-            std::process::exit(1);
+            no_camera = args.test_image.is_empty();
+            Box::new(ImageCamera::new(
+                GrayImage::from_pixel(1280, 960, Luma::<u8>([32]))).unwrap())
         }
     };
     info!("Using camera {} {}x{}",
@@ -1414,9 +3775,13 @@ async fn main() {
         match args.test_image.as_str() {
         "" => Arc::new(tokio::sync::Mutex::new(abstract_cam)),
         _ => {
-            let input_path = PathBuf::from(&args.test_image);
-            let img = ImageReader::open(&input_path).unwrap().decode().unwrap();
-            let img_u8 = img.to_luma8();
+            let img_u8 = match load_gray_image(Path::new(&args.test_image)) {
+                Ok(img) => img,
+                Err(e) => {
+                    error!("Could not load test image: {}", e.message);
+                    std::process::exit(1);
+                }
+            };
             info!("Using test image {} instead of camera.", args.test_image);
             Arc::new(tokio::sync::Mutex::new(Box::new(ImageCamera::new(img_u8).unwrap())))
         },
@@ -1481,42 +3846,174 @@ async fn main() {
     // See: https://greptime.com/blogs/2023-01-12-hidden-control-flow
     //      https://github.com/hyperium/tonic/issues/981
 
+    let min_update_interval = if args.test_image.is_empty() {
+        Duration::ZERO
+    } else {
+        args.test_image_update_interval
+    };
+
+    let frame_recorder = match &args.record_frames_dir {
+        Some(dir) => {
+            match FrameRecorder::new(Path::new(dir), args.record_frames_max,
+                                     args.record_frames_interval,
+                                     args.record_frames_min_free_mb * 1024 * 1024) {
+                Ok(fr) => Some(fr),
+                Err(e) => {
+                    error!("Frame recording disabled: {:?}", e);
+                    None
+                }
+            }
+        },
+        None => None,
+    };
+
     // Build the gRPC service.
     let path: PathBuf = [args.log_dir, args.log_file].iter().collect();
+    let cedar = MyCedar::new(
+        args.min_exposure, args.max_exposure,
+        args.tetra3_script, args.tetra3_database, args.tetra3_socket,
+        args.min_solve_interval,
+        args.auto_save_interval,
+        args.solve_centroid_radius,
+        args.bright_star_magnitude,
+        args.max_calibration_time,
+        args.max_solve_time,
+        frame_recorder,
+        args.focus_exposure_debounce,
+        min_update_interval,
+        args.solve_outage,
+        args.dwell_threshold,
+        PathBuf::from(args.dwell_log),
+        camera, no_camera, shared_telescope_position.clone(),
+        binning, display_sampling,
+        args.star_count_goal, args.sigma, args.min_sigma,
+        // TODO: arg for this?
+        /*stats_capacity=*/100,
+        PathBuf::from(args.ui_prefs),
+        args.ui_prefs_write_delay,
+        args.ignore_saved_calibration,
+        args.fov_override,
+        PathBuf::from(args.sync_points_file),
+        args.max_throughput_test_bytes,
+        PathBuf::from(args.dark_frame_dir),
+        args.dark_frame_count,
+        PathBuf::from(&args.cpu_temp_path),
+        path,
+        log_filter_handle,
+    ).await;
+    if let Some(camera_opener) = camera_opener {
+        let locked_state = cedar.state.lock().await;
+        locked_state.detect_engine.lock().await.set_camera_opener(camera_opener);
+    }
+    // On control-c or SIGTERM (e.g. systemd stopping the service), stop the
+    // solver subprocess and write any deferred preferences change, then exit.
+    {
+        let exit_state = cedar.state.clone();
+        let preferences_file = cedar.preferences_file.clone();
+        let tetra3_subprocess = cedar.state.lock().await.tetra3_subprocess.clone();
+        let mut sigterm = signal(SignalKind::terminate()).unwrap();
+        tokio::task::spawn(async move {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => info!("Got control-c"),
+                _ = sigterm.recv() => info!("Got SIGTERM"),
+            }
+            tetra3_subprocess.lock().unwrap().stop();
+            MyCedar::flush_preferences_on_exit(&exit_state, &preferences_file).await;
+            info!("Exiting");
+            std::process::exit(-1);
+        });
+    }
+    if args.cpu_temp_throttle > 0.0 {
+        cedar.start_thermal_monitor(args.cpu_temp_throttle);
+    }
+    if let Some(gps_device) = args.gps_device {
+        cedar.start_gps(PathBuf::from(gps_device), args.gps_set_clock);
+    }
+    let healthz_state = cedar.state.clone();
+    let readyz_state = cedar.state.clone();
+    let metrics_state = cedar.state.clone();
     let grpc = tonic::transport::Server::builder()
         .accept_http1(true)
         .layer(GrpcWebLayer::new())
         .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any))
-        .add_service(CedarServer::new(MyCedar::new(
-            args.min_exposure, args.max_exposure,
-            args.tetra3_script, args.tetra3_database, args.tetra3_socket,
-            camera, shared_telescope_position.clone(),
-            binning, display_sampling,
-            args.star_count_goal, args.sigma, args.min_sigma,
-            // TODO: arg for this?
-            /*stats_capacity=*/100,
-            PathBuf::from(args.ui_prefs),
-            path,
-        ).await
-        )).into_service();
+        .add_service(CedarServer::new(cedar))
+        .into_service();
+
+    // Build the static content web service, along with health check (and
+    // optionally metrics) endpoints for deployment monitoring.
+    let mut rest = Router::new()
+        .route("/healthz", get(move || health_response(healthz_state.clone(), false)))
+        .route("/readyz", get(move || health_response(readyz_state.clone(), true)));
+    if args.metrics {
+        let cpu_temp_path = PathBuf::from(&args.cpu_temp_path);
+        rest = rest.route("/metrics", get(move || metrics_response(
+            metrics_state.clone(), cpu_temp_path.clone())));
+    }
+    let rest = rest.nest_service("/", ServeDir::new("../cedar_flutter/build/web"));
 
     // Combine static content (flutter app) server and gRPC server into one service.
     let service = MultiplexService::new(rest, grpc);
 
-    // Listen on any address for the given port.
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
+    let bind_addr = match args.bind_addr.parse::<IpAddr>() {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Invalid 'bind_addr' value {:?}: {:?}", args.bind_addr, e);
+            std::process::exit(1);
+        }
+    };
+    let addr = SocketAddr::new(bind_addr, args.http_port);
     info!("Listening at {:?}", addr);
-
+    let alt_service = service.clone();
     let service_future =
         hyper::Server::bind(&addr).serve(tower::make::Shared::new(service));
 
+    let alt_http_port = args.alt_http_port;
+    let alt_service_future = async move {
+        let Some(alt_http_port) = alt_http_port else {
+            return Ok(());
+        };
+        let alt_addr = SocketAddr::new(bind_addr, alt_http_port);
+        info!("Listening at {:?}", alt_addr);
+        hyper::Server::bind(&alt_addr).serve(tower::make::Shared::new(alt_service)).await
+    };
+
     // Spin up ASCOM Alpaca server for reporting our RA/Dec solution as the
     // telescope position.
-    let alpaca_server = create_alpaca_server(shared_telescope_position);
+    let alpaca_server = create_alpaca_server(shared_telescope_position.clone());
     let alpaca_server_future = alpaca_server.start();
 
-    let (service_result, alpaca_result) = join!(service_future, alpaca_server_future);
+    // Likewise for Stellarium's telescope control protocol.
+    let stellarium_port = args.stellarium_port;
+    let stellarium_telescope_position = shared_telescope_position.clone();
+    let stellarium_server_future = async move {
+        if stellarium_port == 0 {
+            return;
+        }
+        if let Err(e) = run_stellarium_server(
+            bind_addr, stellarium_port, stellarium_telescope_position).await
+        {
+            error!("Stellarium server failed: {:?}", e);
+        }
+    };
+
+    // INDI is opt-in.
+    let indi_port = if args.enable_indi { args.indi_port } else { 0 };
+    let indi_server_future = async move {
+        if indi_port == 0 {
+            return;
+        }
+        if let Err(e) = run_indi_server(
+            bind_addr, indi_port, shared_telescope_position).await
+        {
+            error!("INDI server failed: {:?}", e);
+        }
+    };
+
+    let (service_result, alt_service_result, alpaca_result, (), ()) =
+        join!(service_future, alt_service_future, alpaca_server_future,
+              stellarium_server_future, indi_server_future);
     service_result.unwrap();
+    alt_service_result.unwrap();
     alpaca_result.unwrap();
 }
 
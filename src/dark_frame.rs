@@ -0,0 +1,229 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use canonical_error::{CanonicalError, failed_precondition_error};
+use image::{GrayImage, ImageFormat};
+use log::{info, warn};
+
+// A dark frame is applied only if its exposure duration is within this factor
+// of the image's exposure duration.
+const MAX_EXPOSURE_RATIO: f64 = 2.0;
+
+struct DarkFrameEntry {
+    exposure_duration: Duration,
+    gain: i32,
+    path: PathBuf,
+}
+
+// Maintains a directory of dark frames, each captured at a particular exposure
+// duration and gain, and selects the most appropriate one for a given image.
+pub struct DarkFrameLibrary {
+    dir: PathBuf,
+    entries: Vec<DarkFrameEntry>,
+
+    // Index into `entries` of the most recently selected dark frame, along
+    // with its image.
+    current: Option<(usize, Arc<GrayImage>)>,
+}
+
+impl DarkFrameLibrary {
+    // Creates `dir` if needed, and catalogs the dark frames already present.
+    pub fn new(dir: &Path) -> Result<Self, CanonicalError> {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return Err(failed_precondition_error(
+                format!("Could not create directory {:?}: {:?}", dir, e).as_str()));
+        }
+        let read_dir = match fs::read_dir(dir) {
+            Ok(rd) => rd,
+            Err(e) => {
+                return Err(failed_precondition_error(
+                    format!("Could not read directory {:?}: {:?}", dir, e).as_str()));
+            }
+        };
+        let mut entries = Vec::<DarkFrameEntry>::new();
+        for dir_entry in read_dir.flatten() {
+            let path = dir_entry.path();
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            if let Some((exposure_duration, gain)) = Self::parse_file_name(&file_name) {
+                entries.push(DarkFrameEntry{exposure_duration, gain, path});
+            }
+        }
+        info!("Found {} dark frames in {:?}", entries.len(), dir);
+        Ok(DarkFrameLibrary{dir: dir.to_path_buf(), entries, current: None})
+    }
+
+    fn file_name(exposure_duration: Duration, gain: i32) -> String {
+        format!("dark_{}us_gain{}.png", exposure_duration.as_micros(), gain)
+    }
+
+    fn parse_file_name(file_name: &str) -> Option<(Duration, i32)> {
+        let stem = file_name.strip_prefix("dark_")?.strip_suffix(".png")?;
+        let (exp_str, gain_str) = stem.split_once("us_gain")?;
+        let exp_micros = exp_str.parse::<u64>().ok()?;
+        let gain = gain_str.parse::<i32>().ok()?;
+        Some((Duration::from_micros(exp_micros), gain))
+    }
+
+    // Saves `dark` as the dark frame for the given exposure duration and gain,
+    // replacing any existing one. It becomes the current selection.
+    pub fn add(&mut self, exposure_duration: Duration, gain: i32, dark: GrayImage)
+               -> Result<(), CanonicalError> {
+        let path = self.dir.join(Self::file_name(exposure_duration, gain));
+        if let Err(e) = dark.save_with_format(&path, ImageFormat::Png) {
+            return Err(failed_precondition_error(
+                format!("Could not save dark frame {:?}: {:?}", path, e).as_str()));
+        }
+        self.entries.retain(|e| e.path != path);
+        self.entries.push(DarkFrameEntry{exposure_duration, gain, path});
+        self.current = Some((self.entries.len() - 1, Arc::new(dark)));
+        Ok(())
+    }
+
+    // Returns the dark frame best matching the given exposure duration and
+    // gain, if there is a suitable one.
+    pub fn select(&mut self, exposure_duration: Duration, gain: i32)
+                  -> Option<Arc<GrayImage>> {
+        let keys: Vec<(Duration, i32)> =
+            self.entries.iter().map(|e| (e.exposure_duration, e.gain)).collect();
+        let index = closest_dark_frame(&keys, exposure_duration, gain)?;
+        if let Some((current_index, dark)) = &self.current {
+            if *current_index == index {
+                return Some(dark.clone());
+            }
+        }
+        let path = &self.entries[index].path;
+        match image::open(path) {
+            Ok(img) => {
+                let dark = Arc::new(img.to_luma8());
+                self.current = Some((index, dark.clone()));
+                Some(dark)
+            },
+            Err(e) => {
+                warn!("Could not load dark frame {:?}: {:?}", path, e);
+                None
+            }
+        }
+    }
+}
+
+// Returns the index within `keys` (exposure duration, gain) of the dark frame
+// to use for an image with the given exposure duration and gain. Matching gain
+// is preferred, then the nearest exposure duration (by ratio). None if no
+// dark frame is within MAX_EXPOSURE_RATIO of `exposure_duration`.
+pub fn closest_dark_frame(keys: &[(Duration, i32)],
+                          exposure_duration: Duration, gain: i32) -> Option<usize> {
+    let exp_secs = exposure_duration.as_secs_f64();
+    if exp_secs <= 0.0 {
+        return None;
+    }
+    let mut best: Option<(usize, (i32, f64))> = None;
+    for (index, (dark_exposure, dark_gain)) in keys.iter().enumerate() {
+        let dark_secs = dark_exposure.as_secs_f64();
+        if dark_secs <= 0.0 {
+            continue;
+        }
+        let log_ratio = (dark_secs / exp_secs).ln().abs();
+        if log_ratio > MAX_EXPOSURE_RATIO.ln() {
+            continue;
+        }
+        let score = ((dark_gain - gain).abs(), log_ratio);
+        if best.is_none() || score < best.unwrap().1 {
+            best = Some((index, score));
+        }
+    }
+    best.map(|(index, _)| index)
+}
+
+// Combines several dark exposures into one by taking the per-pixel median,
+// which rejects transients such as cosmic ray hits.
+pub fn median_dark_frame(frames: &[Arc<GrayImage>]) -> GrayImage {
+    assert!(!frames.is_empty());
+    let (width, height) = frames[0].dimensions();
+    let mut dark = GrayImage::new(width, height);
+    let mut values = Vec::<u8>::with_capacity(frames.len());
+    for (i, pixel) in dark.iter_mut().enumerate() {
+        values.clear();
+        values.extend(frames.iter().map(|f| f.as_raw()[i]));
+        values.sort_unstable();
+        *pixel = values[values.len() / 2];
+    }
+    dark
+}
+
+// Subtracts `dark` from `image`. The dark frame's mean level is added back so
+// that the background stays above zero, leaving the noise estimate and display
+// black level meaningful.
+pub fn subtract_dark_frame(image: &GrayImage, dark: &GrayImage) -> GrayImage {
+    assert_eq!(image.dimensions(), dark.dimensions());
+    let dark_raw = dark.as_raw();
+    let sum: u64 = dark_raw.iter().map(|p| *p as u64).sum();
+    let pedestal = (sum / dark_raw.len().max(1) as u64) as i16;
+    let mut result = image.clone();
+    for (pixel, dark_pixel) in result.iter_mut().zip(dark_raw.iter()) {
+        *pixel = (*pixel as i16 - *dark_pixel as i16 + pedestal).clamp(0, 255) as u8;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn test_parse_file_name() {
+        let file_name = DarkFrameLibrary::file_name(Duration::from_millis(1500), 100);
+        assert_eq!(DarkFrameLibrary::parse_file_name(&file_name),
+                   Some((Duration::from_millis(1500), 100)));
+        assert_eq!(DarkFrameLibrary::parse_file_name("dark_abcus_gain1.png"), None);
+        assert_eq!(DarkFrameLibrary::parse_file_name("cedar_ui_prefs.binpb"), None);
+    }
+
+    #[test]
+    fn test_closest_dark_frame() {
+        let keys = vec![(Duration::from_millis(100), 50),
+                        (Duration::from_millis(1000), 50),
+                        (Duration::from_millis(900), 100)];
+        // Matching gain is preferred.
+        assert_eq!(closest_dark_frame(&keys, Duration::from_millis(950), 50), Some(1));
+        assert_eq!(closest_dark_frame(&keys, Duration::from_millis(950), 100), Some(2));
+        // Nearest exposure.
+        assert_eq!(closest_dark_frame(&keys, Duration::from_millis(150), 50), Some(0));
+        // Nothing close enough in exposure.
+        assert_eq!(closest_dark_frame(&keys, Duration::from_millis(5000), 50), None);
+        assert_eq!(closest_dark_frame(&[], Duration::from_millis(100), 50), None);
+    }
+
+    #[test]
+    fn test_median_dark_frame() {
+        let mut frames = Vec::<Arc<GrayImage>>::new();
+        for value in [10, 200, 12] {
+            let mut frame = GrayImage::new(2, 1);
+            frame.put_pixel(0, 0, Luma::<u8>([value]));
+            frame.put_pixel(1, 0, Luma::<u8>([5]));
+            frames.push(Arc::new(frame));
+        }
+        let dark = median_dark_frame(&frames);
+        assert_eq!(dark.get_pixel(0, 0).0[0], 12);
+        assert_eq!(dark.get_pixel(1, 0).0[0], 5);
+    }
+
+    #[test]
+    fn test_subtract_dark_frame() {
+        let mut dark = GrayImage::new(2, 1);
+        dark.put_pixel(0, 0, Luma::<u8>([30]));
+        dark.put_pixel(1, 0, Luma::<u8>([10]));  // Mean is 20.
+        let mut image = GrayImage::new(2, 1);
+        image.put_pixel(0, 0, Luma::<u8>([40]));
+        image.put_pixel(1, 0, Luma::<u8>([250]));
+        let result = subtract_dark_frame(&image, &dark);
+        assert_eq!(result.get_pixel(0, 0).0[0], 30);
+        assert_eq!(result.get_pixel(1, 0).0[0], 255);
+    }
+
+}  // mod tests.
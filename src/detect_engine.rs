@@ -1,25 +1,53 @@
 // Copyright (c) 2024 Steven Rosenthal smr@dt3.org
 // See LICENSE file in root directory for license terms.
 
-use cedar_camera::abstract_camera::{AbstractCamera, CapturedImage};
+use cedar_camera::abstract_camera::{AbstractCamera, CapturedImage, Gain, Offset};
+use cedar_camera::image_camera::ImageCamera;
 
+use std::cmp::{max, min};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use canonical_error::CanonicalError;
+use canonical_error::{CanonicalError, failed_precondition_error, invalid_argument_error};
 use image::{GenericImageView, GrayImage};
 use imageproc::rect::Rect;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use cedar_detect::algorithm::{StarDescription, estimate_noise_from_image,
                               get_stars_from_image, summarize_region_of_interest};
 use cedar_detect::histogram_funcs::{average_top_values,
                                     get_level_for_fraction,
                                     remove_stars_from_histogram};
+use crate::dark_frame::{DarkFrameLibrary, subtract_dark_frame};
+use crate::flat_field::FlatField;
+use crate::hot_pixels::{HotPixelLibrary, mask_hot_pixels};
+use crate::rate_estimator::EventRate;
 use crate::scale_image::scale_image_mut;
 use crate::value_stats::ValueStatsAccumulator;
 use crate::cedar;
 
+// Default size of the central region, as a fraction of the image width and
+// height.
+pub const DEFAULT_CENTER_REGION_FRACTION: f32 = 1.0 / 3.0;
+
+// When image capture fails (e.g. a USB camera dropping out), capture is
+// retried after this delay, doubling with each further failure up to
+// CAMERA_RETRY_MAX_DELAY.
+const CAMERA_RETRY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const CAMERA_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// After this many consecutive capture failures the camera is closed and
+// reopened (see DetectEngine::set_camera_opener()); a USB camera that has
+// dropped off the bus does not recover through its old handle.
+const CAMERA_REOPEN_FAILURES: u32 = 3;
+
+/// Opens the camera afresh, e.g. by calling select_camera() as was done at
+/// startup.
+pub type CameraOpener =
+    Arc<dyn Fn() -> Result<Box<dyn AbstractCamera + Send>, CanonicalError> + Send + Sync>;
+
+type SharedCamera = Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>;
+
 pub struct DetectEngine {
     // Bounds the range of exposure durations to be set by auto-exposure.
     // The set_exposure_time() function is not bound by these limits.
@@ -48,6 +76,13 @@ pub struct DetectEngine {
 
     // Signaled at worker_thread exit.
     worker_done: Arc<AtomicBool>,
+
+    // Set while image capture is failing and being retried.
+    camera_reconnecting: Arc<AtomicBool>,
+
+    // Used to reopen the camera after repeated capture failures. If None, the
+    // existing camera handle is retried indefinitely.
+    camera_opener: Option<CameraOpener>,
 }
 
 // State shared between worker thread and the DetectEngine methods.
@@ -63,6 +98,13 @@ struct DetectState {
     // True means populate `DetectResult.focus_aid` info.
     focus_mode_enabled: bool,
 
+    // In focus mode, auto exposure changes are made only after the needed
+    // correction has persisted (in the same direction) for this many
+    // consecutive frames. This keeps the exposure from chasing a star that
+    // drifts in and out of the central region. Large corrections are applied
+    // immediately regardless.
+    focus_exposure_debounce: u32,
+
     // When running CedarDetect, this supplies the `binning` value used.
     // See "About Resolutions" in cedar_server.rs.
     binning: u32,
@@ -78,8 +120,29 @@ struct DetectState {
     // operation; a value < 1 instead favors speed. Range is roughly [0.5 .. 1.5].
     accuracy_multiplier: f32,
 
+    // If `dark_frame_subtraction` is true, each captured image has subtracted
+    // from it the best matching (by exposure duration and gain) dark frame
+    // from `dark_frames`, if any.
+    dark_frames: Option<Arc<Mutex<DarkFrameLibrary>>>,
+    dark_frame_subtraction: bool,
+
+    // Size of the central region, as a fraction of the image width and
+    // height.
+    center_region_fraction: f32,
+
+    // Hot pixels from the map best matching each captured image's gain are
+    // masked prior to star detection.
+    hot_pixels: Option<Arc<Mutex<HotPixelLibrary>>>,
+
+    // If present, applied to each captured image (after dark frame subtraction)
+    // whose size matches.
+    flat_field: Option<Arc<FlatField>>,
+
     detect_latency_stats: ValueStatsAccumulator,
 
+    // Rate at which frames are captured, by readout time.
+    frame_rate: EventRate,
+
     // Estimated time at which `detect_result` will next be updated.
     eta: Option<Instant>,
 
@@ -118,19 +181,34 @@ impl DetectEngine {
                 auto_exposure,
                 update_interval,
                 focus_mode_enabled,
+                focus_exposure_debounce: 3,
                 binning: 1,
                 calibrated_exposure_duration: None,
                 accuracy_multiplier: 1.0,
+                dark_frames: None,
+                dark_frame_subtraction: false,
+                center_region_fraction: DEFAULT_CENTER_REGION_FRACTION,
+                hot_pixels: None,
+                flat_field: None,
                 detect_latency_stats: ValueStatsAccumulator::new(stats_capacity),
+                frame_rate: EventRate::new(stats_capacity),
                 eta: None,
                 detect_result: None,
                 stop_request: false,
             })),
             worker_thread: None,
             worker_done: Arc::new(AtomicBool::new(false)),
+            camera_reconnecting: Arc::new(AtomicBool::new(false)),
+            camera_opener: None,
         }
     }
 
+    /// Registers the means of reopening the camera when image capture keeps
+    /// failing. Takes effect when the worker is next started.
+    pub fn set_camera_opener(&mut self, camera_opener: CameraOpener) {
+        self.camera_opener = Some(camera_opener);
+    }
+
     // If `exp_time` is zero, enables auto exposure. In setup mode, auto
     // exposure is based on a histogram of the central region, and aims to make
     // the brightest part of the central region bright but not saturated. In
@@ -170,6 +248,23 @@ impl DetectEngine {
         // it finishes the current interval.
     }
 
+    // Number of consecutive frames for which a focus mode auto exposure
+    // correction must persist before it is applied. 1 means apply
+    // immediately.
+    pub fn set_focus_exposure_debounce(&mut self, frames: u32)
+                                       -> Result<(), CanonicalError> {
+        if frames < 1 {
+            return Err(invalid_argument_error(
+                format!("focus_exposure_debounce must be at least 1; got {}",
+                        frames).as_str()));
+        }
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.focus_exposure_debounce = frames;
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+        Ok(())
+    }
+
     pub fn get_detection_sigma(&self) -> f32 {
         return self.detection_sigma;
     }
@@ -186,6 +281,28 @@ impl DetectEngine {
         // it finishes the current interval.
     }
 
+    // Adopts the camera's current exposure duration (bounded by our min/max
+    // exposure duration) as the calibrated exposure duration. In operate mode,
+    // auto exposure varies the exposure duration only within two stops of the
+    // calibrated value; rebasing lets it follow larger changes in sky
+    // conditions. Does nothing if there is no calibrated exposure duration.
+    pub async fn rebase_calibrated_exposure_duration(&mut self) {
+        if self.state.lock().unwrap().calibrated_exposure_duration.is_none() {
+            return;
+        }
+        let exp_duration = self.camera.lock().await.get_exposure_duration();
+        let exp_duration = exp_duration.clamp(self.min_exposure_duration,
+                                              self.max_exposure_duration);
+        let mut locked_state = self.state.lock().unwrap();
+        if locked_state.calibrated_exposure_duration != Some(exp_duration) {
+            info!("Rebasing calibrated exposure duration {:?} -> {:?}",
+                  locked_state.calibrated_exposure_duration.unwrap(), exp_duration);
+            locked_state.calibrated_exposure_duration = Some(exp_duration);
+        }
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
     pub fn set_accuracy_multiplier(&mut self, accuracy_multiplier: f32) {
         let mut locked_state = self.state.lock().unwrap();
         locked_state.accuracy_multiplier = accuracy_multiplier;
@@ -193,6 +310,43 @@ impl DetectEngine {
         // it finishes the current interval.
     }
 
+    pub fn set_dark_frames(&mut self, dark_frames: Arc<Mutex<DarkFrameLibrary>>) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.dark_frames = Some(dark_frames);
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
+    pub fn set_hot_pixels(&mut self, hot_pixels: Arc<Mutex<HotPixelLibrary>>) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.hot_pixels = Some(hot_pixels);
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
+    pub fn set_flat_field(&mut self, flat_field: Option<Arc<FlatField>>) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.flat_field = flat_field;
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
+    /// `fraction` must be in (0.0, 1.0].
+    pub fn set_center_region_fraction(&mut self, fraction: f32) {
+        assert!(fraction > 0.0 && fraction <= 1.0);
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.center_region_fraction = fraction;
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
+    pub fn set_dark_frame_subtraction(&mut self, enabled: bool) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.dark_frame_subtraction = enabled;
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
     /// Obtains a result bundle, as configured above. The returned result is
     /// "fresh" in that we either wait to process a new exposure or return the
     /// result of processing the most recently completed exposure.
@@ -218,6 +372,8 @@ impl DetectEngine {
             let cloned_state = self.state.clone();
             let cloned_camera = self.camera.clone();
             let cloned_done = self.worker_done.clone();
+            let cloned_reconnecting = self.camera_reconnecting.clone();
+            let cloned_opener = self.camera_opener.clone();
 
             // The DetectEngine::worker() function is async because it uses the
             // camera interface, which is async. Note however that worker()
@@ -237,7 +393,8 @@ impl DetectEngine {
                     DetectEngine::worker(
                         min_exposure_duration, max_exposure_duration,
                         detection_min_sigma, detection_sigma,
-                        star_count_goal, cloned_state, cloned_camera, cloned_done).await;
+                        star_count_goal, cloned_state, cloned_camera, cloned_done,
+                        CameraSupervisor::new(cloned_opener, cloned_reconnecting)).await;
                 });
             }));
         }
@@ -268,9 +425,18 @@ impl DetectEngine {
         }
     }
 
+    /// Returns a flag that is true while image capture is failing (e.g. the
+    /// camera was disconnected) and being retried. Results are not produced
+    /// meanwhile, so get_next_result() blocks; the flag can be polled without
+    /// locking the DetectEngine.
+    pub fn camera_reconnecting(&self) -> Arc<AtomicBool> {
+        self.camera_reconnecting.clone()
+    }
+
     pub fn reset_session_stats(&mut self) {
         let mut state = self.state.lock().unwrap();
         state.detect_latency_stats.reset_session();
+        state.frame_rate.reset();
     }
 
     pub fn estimate_delay(&self, prev_frame_id: Option<i32>) -> Option<Duration> {
@@ -288,6 +454,12 @@ impl DetectEngine {
         }
     }
 
+    /// Returns the readout time of the most recently processed frame, if any.
+    pub fn last_readout_time(&self) -> Option<SystemTime> {
+        let locked_state = self.state.lock().unwrap();
+        locked_state.detect_result.as_ref().map(|dr| dr.captured_image.readout_time)
+    }
+
     /// Shuts down the worker thread; this can save power if get_next_result()
     /// will not be called soon. A subsequent call to get_next_result() will
     /// re-start processing, at the expense of that first get_next_result() call
@@ -312,18 +484,28 @@ impl DetectEngine {
                     detection_sigma: f32,
                     star_count_goal: i32,
                     state: Arc<Mutex<DetectState>>,
-                    camera: Arc<tokio::sync::Mutex<Box<dyn AbstractCamera + Send>>>,
-                    done: Arc<AtomicBool>) {
+                    camera: SharedCamera,
+                    done: Arc<AtomicBool>,
+                    mut camera_supervisor: CameraSupervisor) {
         debug!("Starting detect engine");
         // Keep track of when we started the detect cycle.
         let mut last_result_time: Option<Instant> = None;
+        // For debouncing focus mode auto exposure: how many consecutive frames
+        // have called for an exposure increase (positive) or decrease
+        // (negative).
+        let mut pending_correction_frames: i32 = 0;
         loop {
             let auto_exposure: bool;
             let update_interval: Duration;
             let focus_mode_enabled: bool;
+            let focus_exposure_debounce: u32;
             let binning: u32;
             let calibrated_exposure_duration: Option<Duration>;
             let accuracy_multiplier: f32;
+            let dark_frames: Option<Arc<Mutex<DarkFrameLibrary>>>;
+            let hot_pixels: Option<Arc<Mutex<HotPixelLibrary>>>;
+            let flat_field: Option<Arc<FlatField>>;
+            let center_region_fraction: f32;
             {
                 let mut locked_state = state.lock().unwrap();
                 if locked_state.stop_request {
@@ -335,10 +517,19 @@ impl DetectEngine {
                 auto_exposure = locked_state.auto_exposure;
                 update_interval = locked_state.update_interval;
                 focus_mode_enabled = locked_state.focus_mode_enabled;
+                focus_exposure_debounce = locked_state.focus_exposure_debounce;
                 binning = locked_state.binning;
                 calibrated_exposure_duration =
                     locked_state.calibrated_exposure_duration;
                 accuracy_multiplier = locked_state.accuracy_multiplier;
+                dark_frames = if locked_state.dark_frame_subtraction {
+                    locked_state.dark_frames.clone()
+                } else {
+                    None
+                };
+                hot_pixels = locked_state.hot_pixels.clone();
+                flat_field = locked_state.flat_field.clone();
+                center_region_fraction = locked_state.center_region_fraction;
             }
             // Is it time to generate the next DetectResult?
             let now = Instant::now();
@@ -357,32 +548,77 @@ impl DetectEngine {
             last_result_time = Some(now);
 
             let frame_id = state.lock().unwrap().frame_id;
-            let captured_image;
-            {
+            let capture_result;
+            if camera_supervisor.reopen_if_closed(&camera).await {
                 let mut locked_camera = camera.lock().await;
                 let delay_est = locked_camera.estimate_delay(frame_id);
                 if delay_est.is_some() {
                     state.lock().unwrap().eta =
                         Some(Instant::now() + delay_est.unwrap());
                 }
-                match locked_camera.capture_image(frame_id).await {
-                    Ok((img, id)) => {
-                        captured_image = img;
-                        state.lock().unwrap().frame_id = Some(id);
-                    }
-                    Err(e) => {
-                        error!("Error capturing image: {}", &e.to_string());
-                        break;  // Abandon thread execution!
-                    }
+                capture_result = locked_camera.capture_image(frame_id).await;
+            } else {
+                capture_result = Err(failed_precondition_error("Camera is closed"));
+            }
+            let mut captured_image;
+            match capture_result {
+                Ok((img, id)) => {
+                    captured_image = img;
+                    let mut locked_state = state.lock().unwrap();
+                    locked_state.frame_id = Some(id);
+                    locked_state.frame_rate.add_event(captured_image.readout_time);
+                    drop(locked_state);
+                    camera_supervisor.capture_succeeded();
+                }
+                Err(e) => {
+                    // Retry with backoff, without holding the camera lock.
+                    let delay = camera_supervisor.capture_failed(&camera).await;
+                    error!("Error capturing image (failure {}): {}; retrying in {:?}",
+                           camera_supervisor.failures, &e.to_string(), delay);
+                    state.lock().unwrap().eta = Some(Instant::now() + delay);
+                    tokio::time::sleep(delay).await;
+                    last_result_time = None;
+                    continue;
                 }
             }
 
             // Process the just-acquired image.
             let process_start_time = Instant::now();
+            let mut dark_frame_applied = false;
+            if let Some(dark_frames) = &dark_frames {
+                let dark = dark_frames.lock().unwrap().select(
+                    captured_image.capture_params.exposure_duration,
+                    captured_image.capture_params.gain.value());
+                if let Some(dark) = dark {
+                    if dark.dimensions() == captured_image.image.dimensions() {
+                        captured_image.image = Arc::new(
+                            subtract_dark_frame(&captured_image.image, &dark));
+                        dark_frame_applied = true;
+                    }
+                }
+            }
+            let mut masked_hot_pixel_count: Option<i32> = None;
+            if let Some(hot_pixels) = &hot_pixels {
+                let pixels = hot_pixels.lock().unwrap().select(
+                    captured_image.capture_params.gain.value());
+                if let Some(pixels) = pixels {
+                    let mut image = (*captured_image.image).clone();
+                    masked_hot_pixel_count =
+                        Some(mask_hot_pixels(&mut image, &pixels) as i32);
+                    captured_image.image = Arc::new(image);
+                }
+            }
+            let mut flat_field_applied = false;
+            if let Some(flat_field) = &flat_field {
+                if flat_field.dimensions() == captured_image.image.dimensions() {
+                    captured_image.image = Arc::new(flat_field.apply(&captured_image.image));
+                    flat_field_applied = true;
+                }
+            }
             let image: &GrayImage = &captured_image.image;
             let (width, height) = image.dimensions();
-            let center_width = width / 3;
-            let center_height = height / 3;
+            let center_width = ((width as f32 * center_region_fraction) as u32).max(1);
+            let center_height = ((height as f32 * center_region_fraction) as u32).max(1);
             let center_region = Rect::at(((width - center_width) / 2) as i32,
                                          ((height - center_height) / 2) as i32)
                 .of_size(center_width, center_height);
@@ -418,8 +654,26 @@ impl DetectEngine {
                     // Don't adjust exposure time too often, is a bit janky
                     // because the camera re-initializes.
                     if correction_factor < 0.7 || correction_factor > 1.3 {
-                        new_exposure_duration_secs =
-                            prev_exposure_duration_secs * correction_factor;
+                        // Require the correction to persist before applying
+                        // it, unless it is large (e.g. slewed to a bright
+                        // star).
+                        if correction_factor > 1.0 {
+                            pending_correction_frames =
+                                max(pending_correction_frames, 0) + 1;
+                        } else {
+                            pending_correction_frames =
+                                min(pending_correction_frames, 0) - 1;
+                        }
+                        if correction_factor < 0.25 || correction_factor > 4.0 ||
+                            pending_correction_frames.unsigned_abs() >=
+                            focus_exposure_debounce
+                        {
+                            new_exposure_duration_secs =
+                                prev_exposure_duration_secs * correction_factor;
+                            pending_correction_frames = 0;
+                        }
+                    } else {
+                        pending_correction_frames = 0;
                     }
                 }  // auto_exposure
 
@@ -591,13 +845,99 @@ impl DetectEngine {
                 focus_aid,
                 center_region,
                 processing_duration: elapsed,
+                dark_frame_applied,
+                masked_hot_pixel_count,
+                flat_field_applied,
                 detect_latency_stats:
                 locked_state.detect_latency_stats.value_stats.clone(),
+                frame_rate: locked_state.frame_rate.rate(),
             });
         }  // loop.
     }
 }
 
+// Tracks image capture failures for the worker, maintaining the
+// `camera_reconnecting` flag. After repeated failures the camera handle is
+// dropped, and reopened before the next capture.
+struct CameraSupervisor {
+    opener: Option<CameraOpener>,
+    camera_reconnecting: Arc<AtomicBool>,
+
+    // Consecutive failed capture (or reopen) attempts.
+    failures: u32,
+
+    // The camera handle has been dropped and must be reopened.
+    closed: bool,
+
+    // Settings of the dropped camera handle, applied to its replacement.
+    settings: Option<(Gain, Offset, Duration)>,
+}
+
+impl CameraSupervisor {
+    fn new(opener: Option<CameraOpener>, camera_reconnecting: Arc<AtomicBool>) -> Self {
+        CameraSupervisor{opener, camera_reconnecting, failures: 0, closed: false,
+                         settings: None}
+    }
+
+    fn capture_succeeded(&mut self) {
+        if self.failures > 0 {
+            info!("Image capture recovered after {} failures", self.failures);
+            self.failures = 0;
+            self.camera_reconnecting.store(false, Ordering::Relaxed);
+        }
+    }
+
+    // Returns how long to wait before the next attempt.
+    async fn capture_failed(&mut self, camera: &SharedCamera) -> Duration {
+        self.failures += 1;
+        self.camera_reconnecting.store(true, Ordering::Relaxed);
+        if self.opener.is_some() && !self.closed && self.failures >= CAMERA_REOPEN_FAILURES {
+            // Release the device so that it can be reopened. A blank camera of
+            // the same dimensions stands in until then.
+            let mut locked_camera = camera.lock().await;
+            self.settings = Some((locked_camera.get_gain(), locked_camera.get_offset(),
+                                  locked_camera.get_exposure_duration()));
+            let (width, height) = locked_camera.dimensions();
+            *locked_camera = Box::new(ImageCamera::new(
+                GrayImage::new(width as u32, height as u32)).unwrap());
+            self.closed = true;
+            warn!("Closed camera after {} capture failures", self.failures);
+        }
+        min(CAMERA_RETRY_INITIAL_DELAY * 2_u32.saturating_pow(min(self.failures - 1, 8)),
+            CAMERA_RETRY_MAX_DELAY)
+    }
+
+    // If the camera was closed, tries to reopen it. Returns false if the camera
+    // remains closed.
+    async fn reopen_if_closed(&mut self, camera: &SharedCamera) -> bool {
+        if !self.closed {
+            return true;
+        }
+        info!("Reopening camera (after {} failures)", self.failures);
+        let mut new_camera = match (self.opener.as_ref().unwrap())() {
+            Ok(cam) => cam,
+            Err(e) => {
+                warn!("Could not reopen camera: {:?}", e);
+                return false;
+            }
+        };
+        if let Some((gain, offset, exposure_duration)) = self.settings {
+            if let Err(e) = new_camera.set_gain(gain) {
+                warn!("Could not restore camera gain: {:?}", e);
+            }
+            let _ = new_camera.set_offset(offset);  // Ignore unsupported offset.
+            if let Err(e) = new_camera.set_exposure_duration(exposure_duration) {
+                warn!("Could not restore camera exposure: {:?}", e);
+            }
+        }
+        info!("Reopened camera {}", new_camera.model());
+        *camera.lock().await = new_camera;
+        self.closed = false;
+        // `camera_reconnecting` remains set until a capture succeeds.
+        true
+    }
+}
+
 #[derive(Clone)]
 pub struct DetectResult {
     // See the corresponding field in cedar.FrameResult proto message.
@@ -642,8 +982,22 @@ pub struct DetectResult {
     // acquire the image.
     pub processing_duration: std::time::Duration,
 
+    // True if a dark frame was subtracted from `captured_image`.
+    pub dark_frame_applied: bool,
+
+    // Number of hot pixels masked in `captured_image`; None if there is no hot
+    // pixel map.
+    pub masked_hot_pixel_count: Option<i32>,
+
+    // True if a flat field correction was applied to `captured_image`.
+    pub flat_field_applied: bool,
+
     // Distribution of `processing_duration` values.
     pub detect_latency_stats: cedar::ValueStats,
+
+    // Frames captured per second, since the session stats were last reset.
+    // None until two frames have been captured.
+    pub frame_rate: Option<f64>,
 }
 
 #[derive(Clone)]
@@ -661,3 +1015,61 @@ pub struct FocusAid {
     // The location of `peak_image`.
     pub peak_image_region: Rect,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+    use image::Luma;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_camera_supervisor_reopen() {
+        // Stands in for a camera that has dropped off the bus.
+        let camera: SharedCamera = Arc::new(tokio::sync::Mutex::new(Box::new(
+            ImageCamera::new(GrayImage::new(64, 48)).unwrap())));
+        // The first reopen attempt fails; the second finds the camera again.
+        let open_attempts = Arc::new(AtomicU32::new(0));
+        let cloned_attempts = open_attempts.clone();
+        let opener: CameraOpener = Arc::new(move || {
+            if cloned_attempts.fetch_add(1, Ordering::Relaxed) == 0 {
+                return Err(failed_precondition_error("No camera"));
+            }
+            let cam: Box<dyn AbstractCamera + Send> = Box::new(
+                ImageCamera::new(GrayImage::from_pixel(64, 48, Luma::<u8>([100]))).unwrap());
+            Ok(cam)
+        });
+        let reconnecting = Arc::new(AtomicBool::new(false));
+        let mut supervisor = CameraSupervisor::new(Some(opener), reconnecting.clone());
+
+        // Failures are retried on the existing handle, with backoff.
+        assert_eq!(supervisor.capture_failed(&camera).await, CAMERA_RETRY_INITIAL_DELAY);
+        assert!(reconnecting.load(Ordering::Relaxed));
+        assert!(supervisor.reopen_if_closed(&camera).await);
+        for _ in 1..CAMERA_REOPEN_FAILURES {
+            supervisor.capture_failed(&camera).await;
+        }
+        assert_eq!(open_attempts.load(Ordering::Relaxed), 0);
+
+        // Now closed; reopening fails and counts as another failure.
+        assert!(!supervisor.reopen_if_closed(&camera).await);
+        assert_eq!(open_attempts.load(Ordering::Relaxed), 1);
+        supervisor.capture_failed(&camera).await;
+        assert!(reconnecting.load(Ordering::Relaxed));
+
+        // Reopened, but still reconnecting until a frame is captured.
+        assert!(supervisor.reopen_if_closed(&camera).await);
+        assert_eq!(open_attempts.load(Ordering::Relaxed), 2);
+        assert!(reconnecting.load(Ordering::Relaxed));
+        let (captured_image, _id) =
+            camera.lock().await.capture_image(None).await.unwrap();
+        assert_eq!(captured_image.image.get_pixel(0, 0).0[0], 100);
+        supervisor.capture_succeeded();
+        assert!(!reconnecting.load(Ordering::Relaxed));
+        assert_eq!(supervisor.failures, 0);
+
+        // No further reopening while capture works.
+        assert!(supervisor.reopen_if_closed(&camera).await);
+        assert_eq!(open_attempts.load(Ordering::Relaxed), 2);
+    }
+
+}  // mod tests.
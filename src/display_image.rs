@@ -0,0 +1,65 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use image::GrayImage;
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+
+use crate::cedar::DisplayImageFormat;
+
+// Encodes `image` for sending to the client in the given `format`. `quality`
+// (1..100) applies only to JPEG. DisplayImageFormat::Unspecified is treated as
+// BMP.
+pub fn encode_display_image(image: &GrayImage, format: DisplayImageFormat,
+                            quality: u8) -> Vec<u8> {
+    let mut buf = Vec::<u8>::with_capacity(encoded_size_bound(image));
+    match format {
+        DisplayImageFormat::Jpeg => {
+            image.write_with_encoder(
+                JpegEncoder::new_with_quality(&mut buf, quality)).unwrap();
+        },
+        DisplayImageFormat::Png => {
+            image.write_with_encoder(PngEncoder::new(&mut buf)).unwrap();
+        },
+        DisplayImageFormat::Bmp | DisplayImageFormat::Unspecified => {
+            image.write_with_encoder(BmpEncoder::new(&mut buf)).unwrap();
+        },
+    }
+    buf
+}
+
+// Upper bound on the encoded size of `image` in any DisplayImageFormat, short
+// of pathological PNG expansion: uncompressed size plus BMP's header and
+// grayscale palette.
+fn encoded_size_bound(image: &GrayImage) -> usize {
+    let (width, height) = image.dimensions();
+    // BMP rows are padded to a multiple of 4 bytes.
+    (width as usize).next_multiple_of(4) * height as usize + 2048
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Luma};
+
+    #[test]
+    fn test_encode_display_image() {
+        let mut image = GrayImage::new(16, 8);
+        image.put_pixel(3, 4, Luma::<u8>([200]));
+        for (format, image_format) in [
+            (DisplayImageFormat::Bmp, ImageFormat::Bmp),
+            (DisplayImageFormat::Jpeg, ImageFormat::Jpeg),
+            (DisplayImageFormat::Png, ImageFormat::Png)] {
+            let buf = encode_display_image(&image, format, 95);
+            assert_eq!(image::guess_format(&buf).unwrap(), image_format);
+            let decoded = image::load_from_memory(&buf).unwrap().to_luma8();
+            assert_eq!(decoded.dimensions(), (16, 8));
+        }
+        // PNG round-trips exactly.
+        let buf = encode_display_image(&image, DisplayImageFormat::Png, 95);
+        let decoded = image::load_from_memory(&buf).unwrap().to_luma8();
+        assert_eq!(decoded, image);
+    }
+
+}  // mod tests.
@@ -0,0 +1,353 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Guides a drift alignment of an equatorial mount's polar axis. Declination
+// drift of a star near the meridian measures the axis' azimuth error; drift
+// of a star near the eastern or western horizon measures its altitude error.
+// Unlike PolarAnalyzer's opportunistic estimates, the user explicitly selects
+// each target and observes it for as long as desired; the drift rate is
+// fitted over the whole observation, so longer observations give more precise
+// results.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::time::{Duration, SystemTime};
+
+use canonical_error::{CanonicalError, failed_precondition_error};
+use log::info;
+
+use crate::astro_util::angular_separation;
+use crate::cedar::{DriftAlign, DriftAlignTarget, ErrorBoundedValue};
+use crate::tetra3_server::CelestialCoord;
+
+// Sidereal rate, radians per minute.
+const SIDEREAL_RATE: f64 = 2.0 * PI / 1436.068;
+
+// No estimate is given until the observation has this many samples spanning
+// at least MIN_OBSERVATION_TIME.
+const MIN_SAMPLES: usize = 10;
+const MIN_OBSERVATION_TIME: Duration = Duration::from_secs(30);
+
+// Bounds memory use during very long observations.
+const MAX_SAMPLES: usize = 20000;
+
+// A position change (degrees) between successive solutions larger than this
+// means the mount was moved or adjusted, so the observation is restarted.
+const JUMP_THRESHOLD: f64 = 1.0 / 60.0;
+
+// If the fitted RA drift exceeds this fraction of the sidereal rate, the
+// mount is not tracking and drift measurements are meaningless.
+const MAX_RA_DRIFT_FRACTION: f64 = 0.3;
+
+// The observed drift is divided by cos(hour angle) (meridian target) or
+// sin(hour angle) (horizon target); below this the target is too far from
+// the ideal position to be useful.
+const MIN_GEOMETRY_FACTOR: f64 = 0.5;
+
+struct Sample {
+    time: SystemTime,
+    ra: f64,  // Degrees.
+    dec: f64,  // Degrees.
+    hour_angle: f64,  // Degrees.
+}
+
+pub struct DriftAlignAssistant {
+    // None if drift alignment has not been started.
+    target: Option<DriftAlignTarget>,
+    latitude: f64,  // Degrees.
+
+    // Plate solutions of the current observation, oldest first.
+    samples: VecDeque<Sample>,
+
+    // Most recent results of each target's observation, arcminutes. These are
+    // retained when switching targets.
+    azimuth_error: Option<ErrorBoundedValue>,
+    altitude_error: Option<ErrorBoundedValue>,
+
+    dec_drift_rate: Option<ErrorBoundedValue>,
+    advice: Option<String>,
+}
+
+impl DriftAlignAssistant {
+    pub fn new() -> Self {
+        DriftAlignAssistant{
+            target: None,
+            latitude: 0.0,
+            samples: VecDeque::new(),
+            azimuth_error: None,
+            altitude_error: None,
+            dec_drift_rate: None,
+            advice: None,
+        }
+    }
+
+    // Starts (or restarts) drift alignment, observing a star near the meridian
+    // and the celestial equator.
+    pub fn begin(&mut self) {
+        *self = Self::new();
+        self.target = Some(DriftAlignTarget::DriftAlignMeridian);
+    }
+
+    // Starts a new observation with the other target: after the meridian, a
+    // star near the eastern or western horizon (and the celestial equator);
+    // after the horizon, back to the meridian, to check the azimuth after
+    // adjusting the altitude.
+    pub fn switch_target(&mut self) -> Result<(), CanonicalError> {
+        let target = match self.target {
+            None => {
+                return Err(failed_precondition_error(
+                    "Drift alignment has not been started"));
+            }
+            Some(DriftAlignTarget::DriftAlignMeridian) => DriftAlignTarget::DriftAlignHorizon,
+            Some(_) => DriftAlignTarget::DriftAlignMeridian,
+        };
+        self.target = Some(target);
+        self.restart_observation();
+        Ok(())
+    }
+
+    fn restart_observation(&mut self) {
+        self.samples.clear();
+        self.dec_drift_rate = None;
+        self.advice = None;
+    }
+
+    // Called with each plate solution. `boresight_pos` is in degrees, as are
+    // `hour_angle` and `latitude`. `time` is the capture time of the image.
+    pub fn process_solution(&mut self, boresight_pos: &CelestialCoord, hour_angle: f32,
+                            latitude: f32, time: SystemTime) {
+        if self.target.is_none() {
+            return;
+        }
+        let ra = boresight_pos.ra as f64;
+        let dec = boresight_pos.dec as f64;
+        if let Some(prev) = self.samples.back() {
+            let moved = angular_separation(
+                prev.ra.to_radians(), prev.dec.to_radians(),
+                ra.to_radians(), dec.to_radians()).to_degrees();
+            if moved > JUMP_THRESHOLD || time < prev.time {
+                info!("Drift alignment: mount moved; restarting observation");
+                self.restart_observation();
+            }
+        }
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample{time, ra, dec, hour_angle: hour_angle as f64});
+        self.latitude = latitude as f64;
+        self.update_estimate();
+    }
+
+    fn update_estimate(&mut self) {
+        self.dec_drift_rate = None;
+        self.advice = None;
+        let first = self.samples.front().unwrap();
+        let last = self.samples.back().unwrap();
+        let observation_time =
+            last.time.duration_since(first.time).unwrap_or(Duration::ZERO);
+        if self.samples.len() < MIN_SAMPLES || observation_time < MIN_OBSERVATION_TIME {
+            return;
+        }
+
+        // Drifts in arcseconds, versus minutes since the first sample.
+        let minutes = |s: &Sample| {
+            s.time.duration_since(first.time).unwrap_or(Duration::ZERO).as_secs_f64() / 60.0
+        };
+        let ra_points: Vec<(f64, f64)> = self.samples.iter().map(|s| {
+            let mut d_ra = s.ra - first.ra;
+            if d_ra > 180.0 {
+                d_ra -= 360.0;
+            } else if d_ra < -180.0 {
+                d_ra += 360.0;
+            }
+            (minutes(s), d_ra * s.dec.to_radians().cos() * 3600.0)
+        }).collect();
+        let dec_points: Vec<(f64, f64)> = self.samples.iter().map(
+            |s| (minutes(s), (s.dec - first.dec) * 3600.0)).collect();
+        let (ra_rate, _) = fit_rate(&ra_points).unwrap();
+        let sidereal_arcsec_per_min = SIDEREAL_RATE.to_degrees() * 3600.0;
+        if ra_rate.abs() > MAX_RA_DRIFT_FRACTION * sidereal_arcsec_per_min {
+            self.advice = Some("Mount is not tracking".to_string());
+            return;
+        }
+        let (dec_rate, dec_rate_error) = fit_rate(&dec_points).unwrap();
+        self.dec_drift_rate = Some(ErrorBoundedValue{
+            value: dec_rate as f32, error: dec_rate_error as f32});
+
+        let hour_angle = (self.samples.iter().map(|s| s.hour_angle).sum::<f64>() /
+                          self.samples.len() as f64).to_radians();
+        let latitude = self.latitude.to_radians();
+        // Drift rate (arcsec/min) is SIDEREAL_RATE * (az_error * cos(latitude) *
+        // cos(hour_angle) + alt_error * sin(hour_angle)), for errors in
+        // arcseconds; see http://celestialwonders.com/articles/polaralignment/
+        match self.target.unwrap() {
+            DriftAlignTarget::DriftAlignMeridian => {
+                let factor = hour_angle.cos() * latitude.cos();
+                if hour_angle.cos() < MIN_GEOMETRY_FACTOR || factor <= 0.0 {
+                    self.advice = Some("Target is too far from the meridian".to_string());
+                    return;
+                }
+                self.azimuth_error = Some(arcmin_error(dec_rate, dec_rate_error, factor));
+            }
+            _ => {
+                let mut factor = hour_angle.sin();
+                if factor.abs() < MIN_GEOMETRY_FACTOR {
+                    self.advice = Some("Target is too far from the horizon".to_string());
+                    return;
+                }
+                if latitude < 0.0 {
+                    // Southern hemisphere: reverse sense of altitude.
+                    factor = -factor;
+                }
+                self.altitude_error = Some(arcmin_error(dec_rate, dec_rate_error, factor));
+            }
+        }
+    }
+
+    pub fn get_status(&self) -> Option<DriftAlign> {
+        let target = self.target?;
+        let observation_time = match (self.samples.front(), self.samples.back()) {
+            (Some(first), Some(last)) =>
+                last.time.duration_since(first.time).unwrap_or(Duration::ZERO),
+            _ => Duration::ZERO,
+        };
+        Some(DriftAlign{
+            target: target.into(),
+            sample_count: self.samples.len() as i32,
+            observation_time: Some(prost_types::Duration::try_from(observation_time).unwrap()),
+            dec_drift_rate: self.dec_drift_rate.clone(),
+            azimuth_error_arcmin: self.azimuth_error.clone(),
+            altitude_error_arcmin: self.altitude_error.clone(),
+            advice: self.advice.clone(),
+        })
+    }
+}
+
+// Converts a declination drift rate (and its error), arcseconds per minute,
+// to a polar axis error in arcminutes, given the drift's geometric factor.
+fn arcmin_error(rate: f64, rate_error: f64, factor: f64) -> ErrorBoundedValue {
+    let scale = 1.0 / (SIDEREAL_RATE * factor * 60.0);
+    ErrorBoundedValue{value: (rate * scale) as f32, error: (rate_error * scale.abs()) as f32}
+}
+
+// Least squares fit of y = a + b * x. Returns the slope b and its standard
+// error. None if there are fewer than three points or the x values are all
+// the same.
+fn fit_rate(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len();
+    if n < 3 {
+        return None;
+    }
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n as f64;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n as f64;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.0 - mean_x)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+    let ssr: f64 = points.iter().map(|p| {
+        let r = p.1 - (intercept + slope * p.0);
+        r * r
+    }).sum();
+    Some((slope, (ssr / (n - 2) as f64 / sxx).sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate approx;
+    use approx::assert_abs_diff_eq;
+    use super::*;
+
+    // Feeds `count` solutions, one per `interval`, with the given drift
+    // rates (arcsec/min).
+    fn observe(assistant: &mut DriftAlignAssistant, start: SystemTime, count: usize,
+               interval: Duration, hour_angle: f32, latitude: f32,
+               ra_rate: f64, dec_rate: f64) {
+        for i in 0..count {
+            let minutes = (interval * i as u32).as_secs_f64() / 60.0;
+            let coords = CelestialCoord{
+                ra: (100.0 + ra_rate * minutes / 3600.0) as f32,
+                dec: (1.0 + dec_rate * minutes / 3600.0) as f32};
+            assistant.process_solution(&coords, hour_angle, latitude,
+                                       start + interval * i as u32);
+        }
+    }
+
+    #[test]
+    fn test_fit_rate() {
+        let (slope, error) = fit_rate(&[(0.0, 1.0), (1.0, 3.0), (2.0, 5.0)]).unwrap();
+        assert_abs_diff_eq!(slope, 2.0, epsilon = 1.0e-9);
+        assert_abs_diff_eq!(error, 0.0, epsilon = 1.0e-9);
+        assert!(fit_rate(&[(1.0, 1.0), (1.0, 2.0), (1.0, 3.0)]).is_none());
+        assert!(fit_rate(&[(0.0, 1.0), (1.0, 2.0)]).is_none());
+    }
+
+    #[test]
+    fn test_meridian_then_horizon() {
+        let mut assistant = DriftAlignAssistant::new();
+        assert!(assistant.get_status().is_none());
+        assert!(assistant.switch_target().is_err());
+        assistant.begin();
+
+        // Axis 10 arcmin clockwise of the pole at latitude 60: drift of
+        // 10 * 60 * SIDEREAL_RATE * cos(60) arcsec/min northward.
+        let start = SystemTime::now();
+        let interval = Duration::from_secs(1);
+        let dec_rate = 600.0 * SIDEREAL_RATE * 0.5;
+        observe(&mut assistant, start, 5, interval, 0.0, 60.0, 0.0, dec_rate);
+        let status = assistant.get_status().unwrap();
+        assert_eq!(status.target, DriftAlignTarget::DriftAlignMeridian as i32);
+        assert_eq!(status.sample_count, 5);
+        assert!(status.dec_drift_rate.is_none());
+
+        observe(&mut assistant, start, 61, interval, 0.0, 60.0, 0.0, dec_rate);
+        let status = assistant.get_status().unwrap();
+        assert_abs_diff_eq!(status.dec_drift_rate.unwrap().value as f64, dec_rate,
+                            epsilon = 0.05);
+        assert_abs_diff_eq!(status.azimuth_error_arcmin.unwrap().value, 10.0,
+                            epsilon = 0.2);
+        assert!(status.altitude_error_arcmin.is_none());
+
+        // Rising star, axis 5 arcmin too high: drifts south.
+        assistant.switch_target().unwrap();
+        let start = start + Duration::from_secs(600);
+        let dec_rate = -300.0 * SIDEREAL_RATE;
+        observe(&mut assistant, start, 61, interval, -90.0, 60.0, 0.0, dec_rate);
+        let status = assistant.get_status().unwrap();
+        assert_eq!(status.target, DriftAlignTarget::DriftAlignHorizon as i32);
+        assert_abs_diff_eq!(status.altitude_error_arcmin.unwrap().value, 5.0,
+                            epsilon = 0.2);
+        // The meridian result is retained.
+        assert_abs_diff_eq!(status.azimuth_error_arcmin.unwrap().value, 10.0,
+                            epsilon = 0.2);
+    }
+
+    #[test]
+    fn test_advice() {
+        let mut assistant = DriftAlignAssistant::new();
+        assistant.begin();
+        let start = SystemTime::now();
+        let interval = Duration::from_secs(1);
+        // Untracked mount.
+        observe(&mut assistant, start, 61, interval, 0.0, 40.0, 900.0, 0.0);
+        let status = assistant.get_status().unwrap();
+        assert_eq!(status.advice.unwrap(), "Mount is not tracking");
+        assert!(status.dec_drift_rate.is_none());
+
+        // Too far from the meridian.
+        assistant.begin();
+        observe(&mut assistant, start, 61, interval, 75.0, 40.0, 0.0, 1.0);
+        let status = assistant.get_status().unwrap();
+        assert_eq!(status.advice.unwrap(), "Target is too far from the meridian");
+        assert!(status.dec_drift_rate.is_some());
+        assert!(status.azimuth_error_arcmin.is_none());
+
+        // A jump restarts the observation.
+        let coords = CelestialCoord{ra: 110.0, dec: 1.0};
+        assistant.process_solution(&coords, 75.0, 40.0, start + Duration::from_secs(61));
+        assert_eq!(assistant.get_status().unwrap().sample_count, 1);
+    }
+
+}  // mod tests.
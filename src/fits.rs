@@ -0,0 +1,252 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Minimal FITS writer for 8-bit grayscale images, with optional world
+// coordinate system (WCS) header keywords describing a plate solution.
+
+use std::fs;
+use std::path::Path;
+
+use canonical_error::{CanonicalError, failed_precondition_error};
+use image::GrayImage;
+
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+
+// A gnomonic (tangent plane) world coordinate solution, in the form of the
+// FITS WCS keywords.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WcsSolution {
+    // Sky coordinate (degrees) of the reference pixel.
+    pub crval1: f64,  // RA.
+    pub crval2: f64,  // Dec.
+
+    // Reference pixel, in FITS 1-based pixel coordinates.
+    pub crpix1: f64,
+    pub crpix2: f64,
+
+    // Linear transformation matrix, degrees per pixel.
+    pub cd1_1: f64,
+    pub cd1_2: f64,
+    pub cd2_1: f64,
+    pub cd2_2: f64,
+
+    // Rotation (degrees) and scale (degrees per pixel) from which the CD matrix
+    // was derived; some software prefers these older keywords.
+    pub crota2: f64,
+    pub pixel_scale: f64,
+}
+
+impl WcsSolution {
+    // `ra`/`dec` (degrees) is the sky position of the image center; `roll`
+    // (degrees) is the plate solution's roll angle; `pixel_scale` is degrees
+    // per pixel. Pixel coordinates follow the FITS convention: the first row
+    // is the bottom of the image (see write_fits()).
+    pub fn new(ra: f64, dec: f64, roll: f64, pixel_scale: f64,
+               width: u32, height: u32) -> Self {
+        let (sin_roll, cos_roll) = roll.to_radians().sin_cos();
+        // East is to the left when north is up, hence the negative x scale.
+        let cdelt1 = -pixel_scale;
+        let cdelt2 = pixel_scale;
+        WcsSolution{
+            crval1: ra, crval2: dec,
+            crpix1: (width as f64 + 1.0) / 2.0,
+            crpix2: (height as f64 + 1.0) / 2.0,
+            cd1_1: cdelt1 * cos_roll,
+            cd1_2: -cdelt2 * sin_roll,
+            cd2_1: cdelt1 * sin_roll,
+            cd2_2: cdelt2 * cos_roll,
+            crota2: roll,
+            pixel_scale,
+        }
+    }
+
+    // Returns the WCS keywords as FITS header cards.
+    pub fn cards(&self) -> Vec<String> {
+        vec![
+            string_card("CTYPE1", "RA---TAN", "Gnomonic projection"),
+            string_card("CTYPE2", "DEC--TAN", "Gnomonic projection"),
+            string_card("RADESYS", "ICRS", ""),
+            number_card("EQUINOX", 2000.0, ""),
+            number_card("CRVAL1", self.crval1, "RA of reference pixel (deg)"),
+            number_card("CRVAL2", self.crval2, "Dec of reference pixel (deg)"),
+            number_card("CRPIX1", self.crpix1, "Reference pixel x"),
+            number_card("CRPIX2", self.crpix2, "Reference pixel y"),
+            number_card("CD1_1", self.cd1_1, ""),
+            number_card("CD1_2", self.cd1_2, ""),
+            number_card("CD2_1", self.cd2_1, ""),
+            number_card("CD2_2", self.cd2_2, ""),
+            number_card("CDELT1", -self.pixel_scale, "Deg per pixel"),
+            number_card("CDELT2", self.pixel_scale, "Deg per pixel"),
+            number_card("CROTA2", self.crota2, "Rotation (deg)"),
+        ]
+    }
+}
+
+fn card(keyword: &str, value: &str, comment: &str) -> String {
+    let mut card = format!("{:<8}= {}", keyword, value);
+    if !comment.is_empty() {
+        card.push_str(" / ");
+        card.push_str(comment);
+    }
+    card.truncate(CARD_SIZE);
+    format!("{:<80}", card)
+}
+
+// Numeric values are right justified to column 30, per the FITS fixed format.
+pub fn number_card(keyword: &str, value: f64, comment: &str) -> String {
+    let value_str = if value.fract() == 0.0 && value.abs() < 1.0e15 {
+        format!("{:.1}", value)
+    } else {
+        format!("{:.10E}", value)
+    };
+    card(keyword, &format!("{:>20}", value_str), comment)
+}
+
+pub fn integer_card(keyword: &str, value: i64, comment: &str) -> String {
+    card(keyword, &format!("{:>20}", value), comment)
+}
+
+pub fn string_card(keyword: &str, value: &str, comment: &str) -> String {
+    // Quotes within strings are doubled; the quoted value is at least 8
+    // characters.
+    let quoted = format!("'{:<8}'", value.replace('\'', "''"));
+    card(keyword, &quoted, comment)
+}
+
+// Writes `image` as a FITS primary HDU to `path`. `cards` are additional
+// header cards (see number_card() etc). Rows are written bottom first so that
+// FITS viewers display the image the same way up as other formats.
+pub fn write_fits(path: &Path, image: &GrayImage, cards: &[String])
+                  -> Result<(), CanonicalError> {
+    if let Err(e) = fs::write(path, encode_fits(image, cards)) {
+        return Err(failed_precondition_error(
+            format!("Error saving FITS file {:?}: {:?}", path, e).as_str()));
+    }
+    Ok(())
+}
+
+// Writes a header-only FITS file describing `wcs` for an image of the given
+// size, in the style of astrometry.net's .wcs files, to accompany an image
+// saved in another format.
+pub fn write_wcs_file(path: &Path, wcs: &WcsSolution, width: u32, height: u32)
+                      -> Result<(), CanonicalError> {
+    if let Err(e) = fs::write(path, encode_wcs_header(wcs, width, height)) {
+        return Err(failed_precondition_error(
+            format!("Error saving WCS file {:?}: {:?}", path, e).as_str()));
+    }
+    Ok(())
+}
+
+fn encode_wcs_header(wcs: &WcsSolution, width: u32, height: u32) -> Vec<u8> {
+    let mut header = vec![
+        card("SIMPLE", &format!("{:>20}", "T"), "FITS standard"),
+        integer_card("BITPIX", 8, ""),
+        integer_card("NAXIS", 0, "No image data"),
+        integer_card("IMAGEW", width as i64, "Image width"),
+        integer_card("IMAGEH", height as i64, "Image height"),
+    ];
+    header.extend(wcs.cards());
+    header.push(format!("{:<80}", "END"));
+    let mut out: Vec<u8> = header.concat().into_bytes();
+    out.resize(padded_size(out.len()), b' ');
+    out
+}
+
+fn encode_fits(image: &GrayImage, cards: &[String]) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mut header = Vec::<String>::with_capacity(cards.len() + 6);
+    header.push(card("SIMPLE", &format!("{:>20}", "T"), "FITS standard"));
+    header.push(integer_card("BITPIX", 8, "Unsigned 8-bit pixels"));
+    header.push(integer_card("NAXIS", 2, ""));
+    header.push(integer_card("NAXIS1", width as i64, "Width"));
+    header.push(integer_card("NAXIS2", height as i64, "Height"));
+    header.extend(cards.iter().cloned());
+    header.push(format!("{:<80}", "END"));
+
+    let data_size = (width * height) as usize;
+    let mut out = Vec::<u8>::with_capacity(
+        padded_size(header.len() * CARD_SIZE) + padded_size(data_size));
+    for card in &header {
+        out.extend_from_slice(card.as_bytes());
+    }
+    out.resize(padded_size(out.len()), b' ');
+    let header_size = out.len();
+
+    let raw = image.as_raw();
+    for row in (0..height as usize).rev() {
+        let start = row * width as usize;
+        out.extend_from_slice(&raw[start..start + width as usize]);
+    }
+    out.resize(header_size + padded_size(data_size), 0);
+    out
+}
+
+fn padded_size(size: usize) -> usize {
+    size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate approx;
+    use approx::assert_abs_diff_eq;
+    use image::Luma;
+    use super::*;
+
+    #[test]
+    fn test_cards() {
+        let c = integer_card("NAXIS1", 640, "Width");
+        assert_eq!(c.len(), 80);
+        assert_eq!(&c[..30], "NAXIS1  =                  640");
+        assert_eq!(c.trim_end(), "NAXIS1  =                  640 / Width");
+        let c = string_card("CTYPE1", "RA---TAN", "");
+        assert_eq!(c.trim_end(), "CTYPE1  = 'RA---TAN'");
+        let c = string_card("RADESYS", "ICRS", "");
+        assert_eq!(c.trim_end(), "RADESYS = 'ICRS    '");
+        let c = number_card("EXPTIME", 0.25, "");
+        assert_eq!(c.trim_end(), "EXPTIME =      2.5000000000E-1");
+    }
+
+    #[test]
+    fn test_encode_fits() {
+        let mut image = GrayImage::new(3, 2);
+        image.put_pixel(0, 0, Luma::<u8>([7]));  // Top left.
+        image.put_pixel(2, 1, Luma::<u8>([9]));  // Bottom right.
+        let encoded = encode_fits(&image, &[number_card("EXPTIME", 1.0, "")]);
+        assert_eq!(encoded.len(), 2 * BLOCK_SIZE);
+        assert!(encoded.starts_with(b"SIMPLE  =                    T"));
+        let header = String::from_utf8_lossy(&encoded[..BLOCK_SIZE]);
+        assert_eq!(header.find("END").unwrap() % CARD_SIZE, 0);
+        // Bottom row first.
+        assert_eq!(&encoded[BLOCK_SIZE..BLOCK_SIZE + 6], &[0, 0, 9, 7, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode_wcs_header() {
+        let wcs = WcsSolution::new(10.0, 20.0, 0.0, 0.01, 100, 50);
+        let encoded = encode_wcs_header(&wcs, 100, 50);
+        assert_eq!(encoded.len(), BLOCK_SIZE);
+        let header = String::from_utf8_lossy(&encoded);
+        assert!(header.contains("NAXIS   =                    0"));
+        assert!(header.contains("IMAGEW  =                  100"));
+        assert!(header.contains("CTYPE1  = 'RA---TAN'"));
+    }
+
+    #[test]
+    fn test_wcs_solution() {
+        let wcs = WcsSolution::new(10.0, 20.0, 0.0, 0.01, 100, 50);
+        assert_abs_diff_eq!(wcs.crpix1, 50.5);
+        assert_abs_diff_eq!(wcs.crpix2, 25.5);
+        assert_abs_diff_eq!(wcs.cd1_1, -0.01);
+        assert_abs_diff_eq!(wcs.cd2_2, 0.01);
+        assert_abs_diff_eq!(wcs.cd1_2, 0.0);
+
+        let wcs = WcsSolution::new(10.0, 20.0, 90.0, 0.01, 100, 50);
+        assert_abs_diff_eq!(wcs.cd1_1, 0.0, epsilon = 1.0e-12);
+        assert_abs_diff_eq!(wcs.cd1_2, -0.01, epsilon = 1.0e-12);
+        assert_abs_diff_eq!(wcs.cd2_1, -0.01, epsilon = 1.0e-12);
+        assert_abs_diff_eq!(wcs.cd2_2, 0.0, epsilon = 1.0e-12);
+        assert_eq!(wcs.cards().len(), 15);
+    }
+
+}  // mod tests.
@@ -0,0 +1,123 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use std::path::{Path, PathBuf};
+
+use canonical_error::{CanonicalError, failed_precondition_error, invalid_argument_error};
+use image::{GrayImage, ImageFormat};
+use log::info;
+
+// Limits the brightening applied to the darkest parts of the flat (e.g. the
+// extreme corners of a heavily vignetted lens), where noise would otherwise be
+// amplified too much.
+const MAX_GAIN: f32 = 4.0;
+
+// Corrects vignetting (and dust shadows), using a flat frame captured against
+// a uniform light source. Flats are stored in the dark frame directory, named
+// by optical configuration (e.g. "50mm"), since each lens vignettes
+// differently.
+pub struct FlatField {
+    width: u32,
+    height: u32,
+
+    // Per-pixel multiplier bringing each pixel to the flat's mean level.
+    gains: Vec<f32>,
+}
+
+impl FlatField {
+    pub fn new(flat: &GrayImage) -> Self {
+        let (width, height) = flat.dimensions();
+        let raw = flat.as_raw();
+        let sum: u64 = raw.iter().map(|p| *p as u64).sum();
+        let mean = sum as f32 / raw.len().max(1) as f32;
+        let gains = raw.iter().map(
+            |p| (mean / (*p as f32).max(1.0)).clamp(1.0 / MAX_GAIN, MAX_GAIN)).collect();
+        FlatField{width, height, gains}
+    }
+
+    // Loads the flat named `name` from `dir`.
+    pub fn load(dir: &Path, name: &str) -> Result<Self, CanonicalError> {
+        let path = flat_field_path(dir, name)?;
+        match image::open(&path) {
+            Ok(img) => Ok(Self::new(&img.to_luma8())),
+            Err(e) => Err(failed_precondition_error(
+                format!("Could not load flat field {:?}: {:?}", path, e).as_str())),
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // Returns `image` with the flat field correction applied. `image` must be
+    // the same size as the flat.
+    pub fn apply(&self, image: &GrayImage) -> GrayImage {
+        assert_eq!(image.dimensions(), self.dimensions());
+        let mut result = image.clone();
+        for (pixel, gain) in result.iter_mut().zip(self.gains.iter()) {
+            *pixel = (*pixel as f32 * gain).round().min(255.0) as u8;
+        }
+        result
+    }
+}
+
+// Saves `flat` (typically the median of several exposures) as the flat field
+// named `name` in `dir`.
+pub fn save_flat_field(dir: &Path, name: &str, flat: &GrayImage)
+                       -> Result<(), CanonicalError> {
+    let path = flat_field_path(dir, name)?;
+    if let Err(e) = flat.save_with_format(&path, ImageFormat::Png) {
+        return Err(failed_precondition_error(
+            format!("Could not save flat field {:?}: {:?}", path, e).as_str()));
+    }
+    info!("Saved flat field {:?}", path);
+    Ok(())
+}
+
+fn flat_field_path(dir: &Path, name: &str) -> Result<PathBuf, CanonicalError> {
+    if name.is_empty() || name.len() > 64 || !name.chars().all(
+        |c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        return Err(invalid_argument_error(
+            format!("Invalid flat field name {:?}", name).as_str()));
+    }
+    Ok(dir.join(format!("flat_{}.png", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn test_flat_field() {
+        // Vignetted: edges at half the center brightness.
+        let mut flat = GrayImage::from_pixel(3, 1, Luma::<u8>([100]));
+        flat.put_pixel(1, 0, Luma::<u8>([200]));
+        let flat_field = FlatField::new(&flat);  // Mean is 133.3.
+
+        let image = GrayImage::from_fn(3, 1, |x, _y| {
+            Luma::<u8>([if x == 1 { 60 } else { 30 }])
+        });
+        let corrected = flat_field.apply(&image);
+        assert_eq!(corrected.get_pixel(0, 0).0[0], 40);
+        assert_eq!(corrected.get_pixel(1, 0).0[0], 40);
+        assert_eq!(corrected.get_pixel(2, 0).0[0], 40);
+
+        // Gain is bounded for black pixels, and results saturate.
+        let mut flat = GrayImage::from_pixel(2, 1, Luma::<u8>([200]));
+        flat.put_pixel(0, 0, Luma::<u8>([0]));
+        let flat_field = FlatField::new(&flat);
+        let corrected = flat_field.apply(&GrayImage::from_pixel(2, 1, Luma::<u8>([100])));
+        assert_eq!(corrected.get_pixel(0, 0).0[0], 255);
+    }
+
+    #[test]
+    fn test_flat_field_path() {
+        let dir = Path::new("/tmp");
+        assert_eq!(flat_field_path(dir, "50mm").unwrap(), dir.join("flat_50mm.png"));
+        assert!(flat_field_path(dir, "").is_err());
+        assert!(flat_field_path(dir, "../etc").is_err());
+    }
+
+}  // mod tests.
@@ -0,0 +1,54 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use std::time::Duration;
+
+/// Formats a duration (typically an exposure time) for display, choosing
+/// units appropriate to its magnitude. Examples: "800 µs", "2.5 ms", "250 ms",
+/// "1.5 s".
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    if secs < 0.001 {
+        format!("{:.0} µs", secs * 1_000_000.0)
+    } else if secs < 0.01 {
+        format!("{:.1} ms", secs * 1000.0)
+    } else if secs < 1.0 {
+        format!("{:.0} ms", secs * 1000.0)
+    } else {
+        format!("{:.1} s", secs)
+    }
+}
+
+/// Formats a camera gain value for display. If `optimal_gain` is given and
+/// matches `gain`, this is noted.
+pub fn format_gain(gain: i32, optimal_gain: Option<i32>) -> String {
+    if optimal_gain == Some(gain) {
+        format!("gain {} (optimal)", gain)
+    } else {
+        format!("gain {}", gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_micros(10)), "10 µs");
+        assert_eq!(format_duration(Duration::from_micros(800)), "800 µs");
+        assert_eq!(format_duration(Duration::from_micros(2500)), "2.5 ms");
+        assert_eq!(format_duration(Duration::from_millis(10)), "10 ms");
+        assert_eq!(format_duration(Duration::from_millis(250)), "250 ms");
+        assert_eq!(format_duration(Duration::from_millis(1000)), "1.0 s");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5 s");
+    }
+
+    #[test]
+    fn test_format_gain() {
+        assert_eq!(format_gain(120, Some(120)), "gain 120 (optimal)");
+        assert_eq!(format_gain(100, Some(120)), "gain 100");
+        assert_eq!(format_gain(100, None), "gain 100");
+    }
+
+}  // mod tests.
@@ -0,0 +1,205 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use canonical_error::{CanonicalError, failed_precondition_error};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use nix::sys::statvfs::statvfs;
+
+use cedar_camera::abstract_camera::CapturedImage;
+use crate::tetra3_server::{SolveResult as SolveResultProto, SolveStatus};
+
+// Saves a rolling set of full resolution captured images, each accompanied by
+// a text file of metadata (capture parameters, detection and plate solve
+// results). The intent is to let a user share a reproducible dataset when
+// reporting a problem at their observing site; the metadata includes what is
+// needed to replay the frames through the detect/solve pipeline offline.
+//
+// Each recorded frame yields two files in `directory`:
+//   frame_<YYYYmmdd_HHMMSS_mmm>.bmp
+//   frame_<YYYYmmdd_HHMMSS_mmm>.txt
+// When more than `max_frames` have been recorded, the oldest are deleted.
+pub struct FrameRecorder {
+    directory: PathBuf,
+    max_frames: usize,
+
+    // Frames are recorded no more often than this.
+    min_interval: Duration,
+
+    // Recording is skipped when the filesystem holding `directory` has less
+    // than this much free space.
+    min_free_bytes: u64,
+
+    last_record_time: Option<Instant>,
+    low_space_warned: bool,
+
+    // File paths (without extension) of recorded frames, oldest first.
+    recorded: VecDeque<PathBuf>,
+}
+
+// Information about the frame being recorded, beyond the image itself.
+pub struct FrameMetadata<'a> {
+    pub star_count: usize,
+    pub noise_estimate: f32,
+
+    // Solver parameters in effect.
+    pub fov_estimate: Option<f32>,
+    pub distortion: f32,
+
+    // Omitted if a solve was not attempted.
+    pub solve_result: Option<&'a SolveResultProto>,
+}
+
+impl FrameRecorder {
+    pub fn new(directory: &Path, max_frames: usize, min_interval: Duration,
+               min_free_bytes: u64) -> Result<Self, CanonicalError> {
+        if let Err(e) = fs::create_dir_all(directory) {
+            return Err(failed_precondition_error(
+                format!("Could not create directory {:?}: {:?}",
+                        directory, e).as_str()));
+        }
+        // Pick up frames recorded in a previous session, so they count toward
+        // `max_frames`.
+        let mut recorded = Vec::<PathBuf>::new();
+        if let Ok(entries) = fs::read_dir(directory) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_frame = path.extension().is_some_and(|e| e == "bmp") &&
+                    path.file_name().unwrap().to_string_lossy().starts_with("frame_");
+                if is_frame {
+                    recorded.push(path.with_extension(""));
+                }
+            }
+        }
+        // File names sort chronologically.
+        recorded.sort();
+        info!("Recording frames to {:?} ({} already present)",
+              directory, recorded.len());
+        Ok(FrameRecorder{
+            directory: directory.to_path_buf(),
+            max_frames,
+            min_interval,
+            min_free_bytes,
+            last_record_time: None,
+            low_space_warned: false,
+            recorded: VecDeque::from(recorded),
+        })
+    }
+
+    // Records `captured_image` and `metadata`, unless it is too soon since the
+    // previous recorded frame or disk space is low. An error is returned if
+    // the files could not be written.
+    pub fn record(&mut self, captured_image: &CapturedImage,
+                  metadata: &FrameMetadata) -> Result<(), CanonicalError> {
+        if let Some(last_record_time) = self.last_record_time {
+            if last_record_time.elapsed() < self.min_interval {
+                return Ok(());
+            }
+        }
+        if !self.have_free_space() {
+            if !self.low_space_warned {
+                warn!("Low disk space; frame recording paused");
+                self.low_space_warned = true;
+            }
+            return Ok(());
+        }
+        self.low_space_warned = false;
+        self.last_record_time = Some(Instant::now());
+
+        let readout_time: DateTime<Utc> = DateTime::from(captured_image.readout_time);
+        let base_path = self.directory.join(
+            format!("frame_{}", readout_time.format("%Y%m%d_%H%M%S_%3f")));
+        let image_path = base_path.with_extension("bmp");
+        if let Err(e) = captured_image.image.save(&image_path) {
+            return Err(failed_precondition_error(
+                format!("Error saving file {:?}: {:?}", image_path, e).as_str()));
+        }
+        let metadata_path = base_path.with_extension("txt");
+        if let Err(e) = fs::write(&metadata_path,
+                                  Self::format_metadata(captured_image, metadata)) {
+            return Err(failed_precondition_error(
+                format!("Error saving file {:?}: {:?}", metadata_path, e).as_str()));
+        }
+        self.recorded.push_back(base_path);
+
+        while self.recorded.len() > self.max_frames {
+            let oldest = self.recorded.pop_front().unwrap();
+            let _ = fs::remove_file(oldest.with_extension("bmp"));
+            let _ = fs::remove_file(oldest.with_extension("txt"));
+        }
+        Ok(())
+    }
+
+    fn have_free_space(&self) -> bool {
+        match statvfs(&self.directory) {
+            Ok(stat) => {
+                let free_bytes =
+                    stat.blocks_available() as u64 * stat.fragment_size() as u64;
+                free_bytes >= self.min_free_bytes
+            },
+            Err(e) => {
+                warn!("Could not determine free space for {:?}: {:?}",
+                      self.directory, e);
+                false
+            }
+        }
+    }
+
+    fn format_metadata(captured_image: &CapturedImage,
+                       metadata: &FrameMetadata) -> String {
+        let mut text = String::new();
+        let readout_time: DateTime<Utc> = DateTime::from(captured_image.readout_time);
+        let (width, height) = captured_image.image.dimensions();
+        let params = &captured_image.capture_params;
+        writeln!(text, "readout_time: {}", readout_time.to_rfc3339()).unwrap();
+        writeln!(text, "readout_time_unix: {:.3}",
+                 captured_image.readout_time.duration_since(SystemTime::UNIX_EPOCH)
+                 .unwrap().as_secs_f64()).unwrap();
+        writeln!(text, "width: {}", width).unwrap();
+        writeln!(text, "height: {}", height).unwrap();
+        writeln!(text, "exposure_seconds: {}",
+                 params.exposure_duration.as_secs_f64()).unwrap();
+        writeln!(text, "gain: {}", params.gain.value()).unwrap();
+        writeln!(text, "offset: {}", params.offset.value()).unwrap();
+        writeln!(text, "camera_temperature_celsius: {}",
+                 captured_image.temperature.0).unwrap();
+        writeln!(text, "star_count: {}", metadata.star_count).unwrap();
+        writeln!(text, "noise_estimate: {}", metadata.noise_estimate).unwrap();
+        if let Some(fov_estimate) = metadata.fov_estimate {
+            writeln!(text, "fov_estimate: {}", fov_estimate).unwrap();
+        }
+        writeln!(text, "distortion: {}", metadata.distortion).unwrap();
+        match metadata.solve_result {
+            None => {
+                writeln!(text, "solve_status: NOT_ATTEMPTED").unwrap();
+            },
+            Some(tsr) => {
+                let status = tsr.status.and_then(|s| SolveStatus::try_from(s).ok());
+                writeln!(text, "solve_status: {:?}", status).unwrap();
+                if let Some(coords) = &tsr.image_center_coords {
+                    writeln!(text, "ra: {}", coords.ra).unwrap();
+                    writeln!(text, "dec: {}", coords.dec).unwrap();
+                }
+                if let Some(roll) = tsr.roll {
+                    writeln!(text, "roll: {}", roll).unwrap();
+                }
+                if let Some(fov) = tsr.fov {
+                    writeln!(text, "fov: {}", fov).unwrap();
+                }
+                if let Some(rmse) = tsr.rmse {
+                    writeln!(text, "rmse_arcsec: {}", rmse).unwrap();
+                }
+                if let Some(matches) = tsr.matches {
+                    writeln!(text, "matches: {}", matches).unwrap();
+                }
+            },
+        }
+        text
+    }
+}
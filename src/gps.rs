@@ -0,0 +1,208 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Reads NMEA sentences from a serial/USB GPS receiver, for the observer
+// location and time. Only the GGA and RMC sentences are used.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+use log::{info, warn};
+use tokio::sync::mpsc::Sender;
+
+// Wait this long before reopening the device after an error (e.g. the
+// receiver was unplugged).
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpsFix {
+    // Degrees; north and east are positive.
+    pub latitude: f64,
+    pub longitude: f64,
+
+    // Present for RMC sentences, which carry the date as well as the time.
+    pub time: Option<SystemTime>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NmeaReport {
+    Fix(GpsFix),
+    // The receiver reports that it has no fix.
+    NoFix,
+}
+
+// Parses a GGA or RMC sentence. Returns None for other sentence types, and
+// for malformed sentences or those failing their checksum.
+pub fn parse_nmea_sentence(sentence: &str) -> Option<NmeaReport> {
+    let sentence = sentence.trim().strip_prefix('$')?;
+    let body = match sentence.split_once('*') {
+        Some((body, checksum)) => {
+            let expected = u8::from_str_radix(checksum, 16).ok()?;
+            if body.bytes().fold(0, |acc, b| acc ^ b) != expected {
+                return None;
+            }
+            body
+        }
+        None => sentence,
+    };
+    let fields: Vec<&str> = body.split(',').collect();
+    // The talker ID (GP, GN, GL, ...) is ignored.
+    let sentence_type = fields[0].get(2..)?;
+    match sentence_type {
+        "GGA" => {
+            // 1: time, 2-5: lat/long, 6: fix quality.
+            if fields.len() < 7 {
+                return None;
+            }
+            if fields[6].parse::<i32>().ok()? == 0 {
+                return Some(NmeaReport::NoFix);
+            }
+            let (latitude, longitude) = parse_position(&fields[2..6])?;
+            Some(NmeaReport::Fix(GpsFix{latitude, longitude, time: None}))
+        }
+        "RMC" => {
+            // 1: time, 2: status, 3-6: lat/long, 9: date.
+            if fields.len() < 10 {
+                return None;
+            }
+            if fields[2] != "A" {
+                return Some(NmeaReport::NoFix);
+            }
+            let (latitude, longitude) = parse_position(&fields[3..7])?;
+            let time = parse_date_time(fields[9], fields[1]);
+            Some(NmeaReport::Fix(GpsFix{latitude, longitude, time}))
+        }
+        _ => None,
+    }
+}
+
+// `fields` is latitude (ddmm.mmmm), N/S, longitude (dddmm.mmmm), E/W.
+fn parse_position(fields: &[&str]) -> Option<(f64, f64)> {
+    let latitude = parse_degrees_minutes(fields[0], 2)?;
+    let longitude = parse_degrees_minutes(fields[2], 3)?;
+    let latitude = match fields[1] {
+        "N" => latitude,
+        "S" => -latitude,
+        _ => return None,
+    };
+    let longitude = match fields[3] {
+        "E" => longitude,
+        "W" => -longitude,
+        _ => return None,
+    };
+    if latitude.abs() > 90.0 || longitude.abs() > 180.0 {
+        return None;
+    }
+    Some((latitude, longitude))
+}
+
+fn parse_degrees_minutes(field: &str, degree_digits: usize) -> Option<f64> {
+    if field.len() <= degree_digits {
+        return None;
+    }
+    // get() rather than slicing: a corrupted field may have a multibyte
+    // character straddling `degree_digits`.
+    let degrees = field.get(..degree_digits)?.parse::<f64>().ok()?;
+    let minutes = field.get(degree_digits..)?.parse::<f64>().ok()?;
+    if minutes >= 60.0 {
+        return None;
+    }
+    Some(degrees + minutes / 60.0)
+}
+
+// `date` is ddmmyy, `time` is hhmmss.sss (UTC).
+fn parse_date_time(date: &str, time: &str) -> Option<SystemTime> {
+    let date = NaiveDate::parse_from_str(date, "%d%m%y").ok()?;
+    let time = NaiveTime::parse_from_str(time, "%H%M%S%.f").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_time(time)).into())
+}
+
+// Reads NMEA sentences from `device` (which must already be configured for
+// the receiver's baud rate, e.g. with stty), sending each fix and loss of fix
+// to `sender`. Runs until `sender`'s receiver is dropped; call from a
+// dedicated thread, as reads block.
+pub fn run_gps_reader(device: PathBuf, sender: Sender<NmeaReport>) {
+    loop {
+        let file = match File::open(&device) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("Could not open GPS device {:?}: {:?}", device, e);
+                std::thread::sleep(REOPEN_DELAY);
+                continue;
+            }
+        };
+        info!("Reading GPS from {:?}", device);
+        for line in BufReader::new(file).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => {
+                    warn!("Error reading GPS device {:?}: {:?}", device, e);
+                    break;
+                }
+            };
+            if let Some(report) = parse_nmea_sentence(&line) {
+                if sender.blocking_send(report).is_err() {
+                    return;  // Receiver is gone.
+                }
+            }
+        }
+        std::thread::sleep(REOPEN_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate approx;
+    use approx::assert_abs_diff_eq;
+    use super::*;
+
+    #[test]
+    fn test_parse_gga() {
+        let report = parse_nmea_sentence(
+            "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47").unwrap();
+        let NmeaReport::Fix(fix) = report else {
+            panic!("Expected fix");
+        };
+        assert_abs_diff_eq!(fix.latitude, 48.1173, epsilon = 1.0e-4);
+        assert_abs_diff_eq!(fix.longitude, 11.516667, epsilon = 1.0e-4);
+        assert_eq!(fix.time, None);
+
+        // No fix.
+        assert_eq!(parse_nmea_sentence("$GPGGA,123519,,,,,0,00,,,M,,M,,"),
+                   Some(NmeaReport::NoFix));
+    }
+
+    #[test]
+    fn test_parse_rmc() {
+        let report = parse_nmea_sentence(
+            "$GPRMC,123519,A,4807.038,N,01131.000,W,022.4,084.4,230394,003.1,W*78").unwrap();
+        let NmeaReport::Fix(fix) = report else {
+            panic!("Expected fix");
+        };
+        assert_abs_diff_eq!(fix.latitude, 48.1173, epsilon = 1.0e-4);
+        assert_abs_diff_eq!(fix.longitude, -11.516667, epsilon = 1.0e-4);
+        // 1994-03-23T12:35:19Z.
+        assert_eq!(fix.time, Some(SystemTime::UNIX_EPOCH + Duration::from_secs(764426119)));
+
+        // Void.
+        assert_eq!(parse_nmea_sentence(
+            "$GNRMC,123519,V,,,,,,,230394,,,N"), Some(NmeaReport::NoFix));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        // Bad checksum.
+        assert_eq!(parse_nmea_sentence(
+            "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48"), None);
+        // Other sentence types.
+        assert_eq!(parse_nmea_sentence("$GPGSV,3,1,11,03,03,111,00"), None);
+        assert_eq!(parse_nmea_sentence("garbage"), None);
+        assert_eq!(parse_nmea_sentence("$GPGGA,123519,4807.038,X,01131.000,E,1"), None);
+        // Multibyte character at the degrees/minutes boundary.
+        assert_eq!(parse_nmea_sentence("$GPGGA,123519,4°07.038,N,01131.000,E,1"), None);
+    }
+
+}  // mod tests.
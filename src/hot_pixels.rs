@@ -0,0 +1,179 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use canonical_error::{CanonicalError, failed_precondition_error};
+use image::GrayImage;
+use log::{info, warn};
+
+// In a dark frame, a pixel is hot if it exceeds the median of its eight
+// neighbors by more than this.
+const HOT_PIXEL_THRESHOLD: u8 = 24;
+
+// Maintains a directory of hot pixel maps, each derived from a dark frame
+// captured at a particular gain, and selects the one for a given image. The
+// maps are stored alongside the dark frames (see DarkFrameLibrary) as text
+// files with one "x y" line per hot pixel.
+pub struct HotPixelLibrary {
+    dir: PathBuf,
+    maps: Vec<(i32, Arc<Vec<(u32, u32)>>)>,
+}
+
+impl HotPixelLibrary {
+    // Loads the hot pixel maps present in `dir`, which must exist.
+    pub fn new(dir: &Path) -> Result<Self, CanonicalError> {
+        let read_dir = match fs::read_dir(dir) {
+            Ok(rd) => rd,
+            Err(e) => {
+                return Err(failed_precondition_error(
+                    format!("Could not read directory {:?}: {:?}", dir, e).as_str()));
+            }
+        };
+        let mut maps = Vec::<(i32, Arc<Vec<(u32, u32)>>)>::new();
+        for dir_entry in read_dir.flatten() {
+            let path = dir_entry.path();
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            let Some(gain) = Self::parse_file_name(&file_name) else {
+                continue;
+            };
+            match fs::read_to_string(&path) {
+                Ok(content) => maps.push((gain, Arc::new(parse_hot_pixels(&content)))),
+                Err(e) => warn!("Could not read hot pixel map {:?}: {:?}", path, e),
+            }
+        }
+        info!("Found {} hot pixel maps in {:?}", maps.len(), dir);
+        Ok(HotPixelLibrary{dir: dir.to_path_buf(), maps})
+    }
+
+    fn file_name(gain: i32) -> String {
+        format!("hot_pixels_gain{}.txt", gain)
+    }
+
+    fn parse_file_name(file_name: &str) -> Option<i32> {
+        file_name.strip_prefix("hot_pixels_gain")?.strip_suffix(".txt")?
+            .parse::<i32>().ok()
+    }
+
+    // Saves `pixels` as the hot pixel map for the given gain, replacing any
+    // existing one.
+    pub fn add(&mut self, gain: i32, pixels: Vec<(u32, u32)>)
+               -> Result<(), CanonicalError> {
+        let path = self.dir.join(Self::file_name(gain));
+        if let Err(e) = fs::write(&path, format_hot_pixels(&pixels)) {
+            return Err(failed_precondition_error(
+                format!("Could not save hot pixel map {:?}: {:?}", path, e).as_str()));
+        }
+        info!("Saved {} hot pixels for gain {}", pixels.len(), gain);
+        self.maps.retain(|(g, _)| *g != gain);
+        self.maps.push((gain, Arc::new(pixels)));
+        Ok(())
+    }
+
+    // Returns the hot pixel map whose gain is closest to `gain`, if any.
+    pub fn select(&self, gain: i32) -> Option<Arc<Vec<(u32, u32)>>> {
+        self.maps.iter().min_by_key(|(g, _)| (g - gain).abs())
+            .map(|(_, pixels)| pixels.clone())
+    }
+}
+
+// Returns the (x, y) coordinates of the hot pixels in `dark`. Edge pixels are
+// not considered.
+pub fn find_hot_pixels(dark: &GrayImage) -> Vec<(u32, u32)> {
+    let (width, height) = dark.dimensions();
+    let mut hot_pixels = Vec::<(u32, u32)>::new();
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            let value = dark.get_pixel(x, y).0[0];
+            if value > neighbor_median(dark, x, y).saturating_add(HOT_PIXEL_THRESHOLD) {
+                hot_pixels.push((x, y));
+            }
+        }
+    }
+    hot_pixels
+}
+
+// Replaces each of `pixels` in `image` with the median of its neighbors, so
+// that star detection ignores them. Returns the number of pixels replaced.
+pub fn mask_hot_pixels(image: &mut GrayImage, pixels: &[(u32, u32)]) -> usize {
+    let (width, height) = image.dimensions();
+    let mut count = 0;
+    for &(x, y) in pixels {
+        if x == 0 || y == 0 || x + 1 >= width || y + 1 >= height {
+            continue;
+        }
+        let median = neighbor_median(image, x, y);
+        image.get_pixel_mut(x, y).0[0] = median;
+        count += 1;
+    }
+    count
+}
+
+// Median of the eight neighbors of (x, y), which must not be an edge pixel.
+fn neighbor_median(image: &GrayImage, x: u32, y: u32) -> u8 {
+    let mut values = [0_u8; 8];
+    let mut i = 0;
+    for ny in y - 1..=y + 1 {
+        for nx in x - 1..=x + 1 {
+            if nx != x || ny != y {
+                values[i] = image.get_pixel(nx, ny).0[0];
+                i += 1;
+            }
+        }
+    }
+    values.sort_unstable();
+    // Average of the middle two, rounding down.
+    ((values[3] as u16 + values[4] as u16) / 2) as u8
+}
+
+fn format_hot_pixels(pixels: &[(u32, u32)]) -> String {
+    pixels.iter().map(|(x, y)| format!("{} {}\n", x, y)).collect()
+}
+
+fn parse_hot_pixels(content: &str) -> Vec<(u32, u32)> {
+    content.lines().filter_map(|line| {
+        let (x, y) = line.trim().split_once(' ')?;
+        Some((x.parse::<u32>().ok()?, y.parse::<u32>().ok()?))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn test_parse_file_name() {
+        let file_name = HotPixelLibrary::file_name(100);
+        assert_eq!(HotPixelLibrary::parse_file_name(&file_name), Some(100));
+        assert_eq!(HotPixelLibrary::parse_file_name("dark_1000us_gain100.png"), None);
+    }
+
+    #[test]
+    fn test_format_hot_pixels() {
+        let pixels = vec![(1, 2), (30, 40)];
+        assert_eq!(format_hot_pixels(&pixels), "1 2\n30 40\n");
+        assert_eq!(parse_hot_pixels(&format_hot_pixels(&pixels)), pixels);
+        assert_eq!(parse_hot_pixels("junk\n5 6\n"), vec![(5, 6)]);
+    }
+
+    #[test]
+    fn test_find_and_mask_hot_pixels() {
+        let mut dark = GrayImage::from_pixel(5, 5, Luma::<u8>([10]));
+        dark.put_pixel(2, 2, Luma::<u8>([200]));  // Hot.
+        dark.put_pixel(1, 3, Luma::<u8>([30]));  // Not hot enough.
+        dark.put_pixel(0, 0, Luma::<u8>([255]));  // Edge; ignored.
+        let hot_pixels = find_hot_pixels(&dark);
+        assert_eq!(hot_pixels, vec![(2, 2)]);
+
+        let mut image = dark.clone();
+        assert_eq!(mask_hot_pixels(&mut image, &hot_pixels), 1);
+        assert_eq!(image.get_pixel(2, 2).0[0], 10);
+        assert_eq!(image.get_pixel(1, 3).0[0], 30);
+        // Out of bounds coordinates are skipped.
+        assert_eq!(mask_hot_pixels(&mut image, &[(4, 4), (10, 10)]), 0);
+    }
+
+}  // mod tests.
@@ -0,0 +1,122 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Loading of still images (e.g. the --test_image) for use in place of camera
+// captures. Any format the image crate decodes is accepted, notably JPEG, BMP,
+// PNG and TIFF; the format is determined from the file content rather than its
+// extension.
+
+use std::io::Cursor;
+use std::path::Path;
+
+use canonical_error::{CanonicalError, invalid_argument_error, not_found_error};
+use image::{DynamicImage, GrayImage, ImageBuffer, ImageReader, Luma};
+
+// File extensions (lower case) of the image formats we expect to load.
+pub const IMAGE_FILE_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "bmp", "png", "tif", "tiff"];
+
+// Returns true if `path` has one of the IMAGE_FILE_EXTENSIONS (ignoring case).
+pub fn is_image_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(ext) => IMAGE_FILE_EXTENSIONS.contains(
+            &ext.to_string_lossy().to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+// Loads the image at `path` as 8-bit grayscale. See decode_gray_image().
+pub fn load_gray_image(path: &Path) -> Result<GrayImage, CanonicalError> {
+    let bytes = match std::fs::read(path) {
+        Ok(b) => b,
+        Err(e) => {
+            return Err(not_found_error(
+                format!("Could not read image {:?}: {:?}", path, e).as_str()));
+        }
+    };
+    decode_gray_image(&bytes).map_err(|e| invalid_argument_error(
+        format!("Image {:?}: {}", path, e.message).as_str()))
+}
+
+// Decodes an encoded image as 8-bit grayscale. Color images are converted to
+// luminance. 16-bit images (common for astro captures, which often use only
+// the low 12 or 14 bits) are scaled so that their brightest pixel keeps 8 bits
+// of precision, rather than being truncated to their high byte.
+pub fn decode_gray_image(bytes: &[u8]) -> Result<GrayImage, CanonicalError> {
+    let reader = match ImageReader::new(Cursor::new(bytes)).with_guessed_format() {
+        Ok(r) => r,
+        Err(e) => {
+            return Err(invalid_argument_error(format!("{:?}", e).as_str()));
+        }
+    };
+    let img = match reader.decode() {
+        Ok(img) => img,
+        Err(e) => {
+            return Err(invalid_argument_error(
+                format!("Could not decode: {:?}", e).as_str()));
+        }
+    };
+    match img {
+        DynamicImage::ImageLuma16(img16) => Ok(scale_to_8_bits(&img16)),
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgb16(_) |
+        DynamicImage::ImageRgba16(_) => Ok(scale_to_8_bits(&img.to_luma16())),
+        _ => Ok(img.to_luma8()),
+    }
+}
+
+fn scale_to_8_bits(img16: &ImageBuffer<Luma<u16>, Vec<u16>>) -> GrayImage {
+    let max_value = img16.as_raw().iter().copied().max().unwrap_or(0);
+    let significant_bits = 16 - max_value.leading_zeros();
+    let shift = significant_bits.saturating_sub(8);
+    let (width, height) = img16.dimensions();
+    GrayImage::from_raw(
+        width, height,
+        img16.as_raw().iter().map(|p| (p >> shift) as u8).collect()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageFormat;
+
+    fn encode(img: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut buf = Vec::<u8>::new();
+        img.write_to(&mut Cursor::new(&mut buf), format).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_decode_formats() {
+        let gray = GrayImage::from_fn(8, 4, |x, y| Luma::<u8>([(x * 30 + y) as u8]));
+        let img = DynamicImage::ImageLuma8(gray.clone());
+        for format in [ImageFormat::Bmp, ImageFormat::Png, ImageFormat::Tiff] {
+            assert_eq!(decode_gray_image(&encode(&img, format)).unwrap(), gray,
+                       "{:?}", format);
+        }
+        // Lossy.
+        let decoded = decode_gray_image(&encode(&img, ImageFormat::Jpeg)).unwrap();
+        assert_eq!(decoded.dimensions(), (8, 4));
+
+        assert!(decode_gray_image(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_decode_16_bit() {
+        // 12-bit data: 4095 is the brightest value.
+        let img16 = ImageBuffer::<Luma<u16>, Vec<u16>>::from_fn(
+            4, 1, |x, _y| Luma::<u16>([[0, 16, 2048, 4095][x as usize]]));
+        let img = DynamicImage::ImageLuma16(img16);
+        for format in [ImageFormat::Png, ImageFormat::Tiff] {
+            let decoded = decode_gray_image(&encode(&img, format)).unwrap();
+            assert_eq!(decoded.as_raw(), &vec![0, 1, 128, 255], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_is_image_file() {
+        assert!(is_image_file(Path::new("demo/m42.TIF")));
+        assert!(is_image_file(Path::new("demo/m42.png")));
+        assert!(!is_image_file(Path::new("demo/m42.txt")));
+        assert!(!is_image_file(Path::new("demo/m42")));
+    }
+
+}  // mod tests.
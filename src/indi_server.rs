@@ -0,0 +1,277 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// A minimal INDI telescope driver, so that INDI clients (KStars/Ekos and
+// others) can display Cedar's plate-solved position and request gotos.
+//
+// The following properties are defined on device "Cedar":
+//   CONNECTION (switch, read-only): always CONNECT. Cedar is connected
+//       whenever it is running.
+//   EQUATORIAL_EOD_COORD (number, read-write): RA (hours) and DEC (degrees)
+//       of the boresight. Reading gives Cedar's plate-solved position; its
+//       state is Alert when the position is stale. Writing sets the slew
+//       target, which Cedar presents as push-to guidance.
+//   ON_COORD_SET (switch, read-only): always SLEW. Cedar cannot track or sync
+//       a mount.
+//
+// Note that Cedar reports J2000 coordinates, even though INDI's property
+// name refers to epoch of date.
+
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, info};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::position_reporter::TelescopePosition;
+
+const DEVICE_NAME: &str = "Cedar";
+
+// How often the current position is sent to each client.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Longest unfinished element we buffer from a client. A goto request is a
+// few hundred bytes; a client exceeding this is disconnected.
+const MAX_PENDING: usize = 8 * 1024;
+
+// Accepts INDI clients on `bind_addr`:`port`, serving each one concurrently.
+// Runs until an error occurs in accepting connections.
+pub async fn run_indi_server(bind_addr: IpAddr, port: u16,
+                             telescope_position: Arc<Mutex<TelescopePosition>>)
+                             -> Result<(), io::Error> {
+    let listener = TcpListener::bind((bind_addr, port)).await?;
+    info!("INDI server listening on {}:{}", bind_addr, port);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("INDI client connected from {:?}", addr);
+        let telescope_position = telescope_position.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(stream, telescope_position).await {
+                debug!("INDI client {:?} disconnected: {:?}", addr, e);
+            }
+        });
+    }
+}
+
+async fn serve_client(stream: TcpStream,
+                      telescope_position: Arc<Mutex<TelescopePosition>>)
+                      -> Result<(), io::Error> {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+    let mut read_buf = [0u8; 1024];
+    // Accumulates client XML until complete elements are available. Kept as
+    // bytes, as a read can end partway through a multibyte character; only
+    // complete elements are decoded.
+    let mut pending = Vec::<u8>::new();
+    // Position updates are sent only after the client has asked for our
+    // properties.
+    let mut defined = false;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if !defined {
+                    continue;
+                }
+                let (ra, dec, valid) = current_position(&telescope_position);
+                writer.write_all(
+                    coord_vector("set", ra, dec, valid).as_bytes()).await?;
+            }
+            result = reader.read(&mut read_buf) => {
+                let count = result?;
+                if count == 0 {
+                    return Ok(());  // Client closed the connection.
+                }
+                pending.extend_from_slice(&read_buf[..count]);
+                if let Some(pos) = find_bytes(&pending, b"<getProperties") {
+                    pending.drain(..pos + b"<getProperties".len());
+                    let (ra, dec, valid) = current_position(&telescope_position);
+                    writer.write_all(define_properties(ra, dec, valid).as_bytes()).await?;
+                    defined = true;
+                }
+                while let Some((element, rest)) =
+                    take_element(&pending, "newNumberVector")
+                {
+                    if let Some((ra, dec)) = parse_new_coord(&element) {
+                        info!("INDI goto ra {:.4} dec {:.4}", ra, dec);
+                        telescope_position.lock().unwrap().start_slew(ra, dec);
+                    }
+                    pending = rest;
+                }
+                discard_consumed(&mut pending)?;
+            }
+        }
+    }
+}
+
+// Discards everything before an unfinished newNumberVector (or else the last
+// unfinished tag) so that `pending` does not grow without bound. Returns an
+// error if what remains is longer than MAX_PENDING.
+fn discard_consumed(pending: &mut Vec<u8>) -> Result<(), io::Error> {
+    match find_bytes(pending, b"<newNumberVector")
+        .or_else(|| pending.iter().rposition(|&b| b == b'<'))
+    {
+        Some(pos) => { pending.drain(..pos); },
+        None => pending.clear(),
+    }
+    if pending.len() > MAX_PENDING {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unfinished INDI element exceeds {} bytes", MAX_PENDING)));
+    }
+    Ok(())
+}
+
+// Returns (ra, dec, valid) with ra in degrees.
+fn current_position(telescope_position: &Arc<Mutex<TelescopePosition>>)
+                    -> (f64, f64, bool) {
+    let locked_position = telescope_position.lock().unwrap();
+    (locked_position.boresight_ra, locked_position.boresight_dec,
+     locked_position.boresight_valid)
+}
+
+// `verb` is "def" or "set". `ra` is in degrees.
+fn coord_vector(verb: &str, ra: f64, dec: f64, valid: bool) -> String {
+    let state = if valid { "Ok" } else { "Alert" };
+    let attrs = if verb == "def" {
+        " label='Eq. Coordinates' group='Main Control' perm='rw' timeout='0'"
+    } else {
+        ""
+    };
+    let ra_attrs = if verb == "def" {
+        " label='RA (hh:mm:ss)' format='%010.6m' min='0' max='24' step='0'"
+    } else {
+        ""
+    };
+    let dec_attrs = if verb == "def" {
+        " label='DEC (dd:mm:ss)' format='%010.6m' min='-90' max='90' step='0'"
+    } else {
+        ""
+    };
+    format!("<{verb}NumberVector device='{DEVICE_NAME}' name='EQUATORIAL_EOD_COORD' \
+             state='{state}'{attrs}>\n\
+             <{verb}Number name='RA'{ra_attrs}>{:.6}</{verb}Number>\n\
+             <{verb}Number name='DEC'{dec_attrs}>{:.6}</{verb}Number>\n\
+             </{verb}NumberVector>\n",
+            ra / 15.0, dec)
+}
+
+fn define_properties(ra: f64, dec: f64, valid: bool) -> String {
+    let mut xml = format!(
+        "<defSwitchVector device='{DEVICE_NAME}' name='CONNECTION' label='Connection' \
+         group='Main Control' state='Ok' perm='ro' rule='OneOfMany' timeout='0'>\n\
+         <defSwitch name='CONNECT' label='Connect'>On</defSwitch>\n\
+         <defSwitch name='DISCONNECT' label='Disconnect'>Off</defSwitch>\n\
+         </defSwitchVector>\n\
+         <defSwitchVector device='{DEVICE_NAME}' name='ON_COORD_SET' label='On Set' \
+         group='Main Control' state='Ok' perm='ro' rule='OneOfMany' timeout='0'>\n\
+         <defSwitch name='SLEW' label='Slew'>On</defSwitch>\n\
+         </defSwitchVector>\n");
+    xml.push_str(&coord_vector("def", ra, dec, valid));
+    xml
+}
+
+// Returns the position of the first occurrence of `needle` in `haystack`.
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// If `buf` contains a complete `tag` element, returns it (decoded) along with
+// the remainder of `buf` following it.
+fn take_element(buf: &[u8], tag: &str) -> Option<(String, Vec<u8>)> {
+    let start = find_bytes(buf, format!("<{}", tag).as_bytes())?;
+    let end_tag = format!("</{}>", tag);
+    let end = start + find_bytes(&buf[start..], end_tag.as_bytes())? + end_tag.len();
+    Some((String::from_utf8_lossy(&buf[start..end]).into_owned(), buf[end..].to_vec()))
+}
+
+// Returns the (ra, dec) in degrees requested by a newNumberVector element, or
+// None if it is not a complete EQUATORIAL_EOD_COORD write for our device.
+fn parse_new_coord(element: &str) -> Option<(f64, f64)> {
+    if !element.contains("EQUATORIAL_EOD_COORD") {
+        return None;
+    }
+    let ra_hours = parse_one_number(element, "RA")?;
+    let dec = parse_one_number(element, "DEC")?;
+    if !(0.0..=24.0).contains(&ra_hours) || !(-90.0..=90.0).contains(&dec) {
+        return None;
+    }
+    Some((ra_hours * 15.0, dec))
+}
+
+// Extracts the value of the oneNumber element with the given name.
+fn parse_one_number(element: &str, name: &str) -> Option<f64> {
+    for quote in ['\'', '"'] {
+        let marker = format!("name={}{}{}", quote, name, quote);
+        if let Some(pos) = element.find(&marker) {
+            let after = &element[pos + marker.len()..];
+            let value_start = after.find('>')? + 1;
+            let value_end = after.find("</oneNumber>")?;
+            return after[value_start..value_end].trim().parse::<f64>().ok();
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate approx;
+    use approx::assert_abs_diff_eq;
+    use super::*;
+
+    #[test]
+    fn test_parse_new_coord() {
+        let element = "<newNumberVector device='Cedar' name='EQUATORIAL_EOD_COORD'>\
+                       <oneNumber name='RA'>6.5</oneNumber>\
+                       <oneNumber name=\"DEC\"> -20.25 </oneNumber>\
+                       </newNumberVector>";
+        let (ra, dec) = parse_new_coord(element).unwrap();
+        assert_abs_diff_eq!(ra, 97.5, epsilon = 1.0e-9);
+        assert_abs_diff_eq!(dec, -20.25, epsilon = 1.0e-9);
+
+        // Different property.
+        assert!(parse_new_coord(&element.replace("EQUATORIAL_EOD_COORD",
+                                                 "TARGET_EOD_COORD")).is_none());
+        // Missing DEC.
+        assert!(parse_new_coord("<newNumberVector name='EQUATORIAL_EOD_COORD'>\
+                                 <oneNumber name='RA'>6.5</oneNumber>\
+                                 </newNumberVector>").is_none());
+    }
+
+    #[test]
+    fn test_take_element() {
+        let buf = b"junk<newNumberVector a='b'>x</newNumberVector><newNum";
+        let (element, rest) = take_element(buf, "newNumberVector").unwrap();
+        assert_eq!(element, "<newNumberVector a='b'>x</newNumberVector>");
+        assert_eq!(rest, b"<newNum");
+        assert!(take_element(&rest, "newNumberVector").is_none());
+
+        // A multibyte character split between reads survives.
+        let xml = "<newNumberVector label='Ré'>x</newNumberVector>".as_bytes();
+        let split = xml.iter().position(|&b| b >= 0x80).unwrap() + 1;
+        let mut pending = xml[..split].to_vec();
+        assert!(take_element(&pending, "newNumberVector").is_none());
+        discard_consumed(&mut pending).unwrap();
+        pending.extend_from_slice(&xml[split..]);
+        let (element, _rest) = take_element(&pending, "newNumberVector").unwrap();
+        assert_eq!(element, "<newNumberVector label='Ré'>x</newNumberVector>");
+    }
+
+    #[test]
+    fn test_discard_consumed() {
+        let mut pending = b"junk<oneNumber name='RA'>6.5</oneNumber><newNum".to_vec();
+        discard_consumed(&mut pending).unwrap();
+        assert_eq!(pending, b"<newNum");
+
+        let mut pending = b"no tags".to_vec();
+        discard_consumed(&mut pending).unwrap();
+        assert!(pending.is_empty());
+
+        // Unterminated element is capped.
+        let mut pending = format!("<newNumberVector>{}", "x".repeat(MAX_PENDING)).into_bytes();
+        assert!(discard_consumed(&mut pending).is_err());
+    }
+
+}  // mod tests.
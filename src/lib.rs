@@ -3,15 +3,33 @@
 
 pub mod astro_util;
 pub mod calibrator;
+pub mod dark_frame;
 pub mod detect_engine;
+pub mod display_image;
+pub mod drift_align;
+pub mod fits;
+pub mod flat_field;
+pub mod format_util;
+pub mod frame_recorder;
+pub mod gps;
+pub mod hot_pixels;
+pub mod image_file;
+pub mod indi_server;
+pub mod metrics;
 pub mod motion_estimator;
 pub mod polar_analyzer;
 pub mod position_reporter;
+pub mod preferences_json;
 pub mod rate_estimator;
 pub mod reservoir_sampler;
 pub mod scale_image;
 pub mod solve_engine;
+pub mod spiral_search;
+pub mod star_names;
+pub mod stellarium_server;
+pub mod throughput_test;
 pub mod tetra3_subprocess;
+pub mod track_recorder;
 pub mod value_stats;
 
 pub mod tetra3_server {
@@ -0,0 +1,133 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Formats Cedar's processing statistics in the Prometheus text exposition
+// format, for the optional /metrics endpoint.
+
+use std::fmt::Write;
+
+use crate::cedar::{ProcessingStats, ValueStats};
+
+// A copy of the statistics to be exported, taken while briefly holding the
+// server's state lock.
+#[derive(Default)]
+pub struct MetricsSnapshot {
+    // Interval (seconds) between successive frames.
+    pub frame_interval: Option<ValueStats>,
+
+    // From the most recently served frame.
+    pub processing_stats: Option<ProcessingStats>,
+
+    // Degrees Celsius.
+    pub cpu_temperature: Option<f32>,
+}
+
+pub fn format_metrics(snapshot: &MetricsSnapshot) -> String {
+    let mut out = String::new();
+    if let Some(recent) = snapshot.frame_interval.as_ref().and_then(|s| s.recent.as_ref()) {
+        if recent.mean > 0.0 {
+            write_gauge(&mut out, "cedar_frame_rate",
+                        "Frames per second, over recent frames.", 1.0 / recent.mean);
+        }
+    }
+    if let Some(stats) = &snapshot.processing_stats {
+        write_latency(&mut out, "cedar_detect_latency_seconds",
+                      "Star detection latency.", &stats.detect_latency);
+        write_latency(&mut out, "cedar_solve_latency_seconds",
+                      "Plate solve latency.", &stats.solve_latency);
+        write_latency(&mut out, "cedar_serve_latency_seconds",
+                      "Latency of preparing a frame for the client.",
+                      &stats.serve_latency);
+        write_latency(&mut out, "cedar_overall_latency_seconds",
+                      "Latency from image readout to frame served.",
+                      &stats.overall_latency);
+        if let Some(recent) = stats.solve_interval.as_ref().and_then(|s| s.recent.as_ref()) {
+            if recent.mean > 0.0 {
+                write_gauge(&mut out, "cedar_solve_rate",
+                            "Plate solves per second, over recent solves.",
+                            1.0 / recent.mean);
+            }
+        }
+        if let Some(recent) =
+            stats.solve_attempt_fraction.as_ref().and_then(|s| s.recent.as_ref())
+        {
+            write_gauge(&mut out, "cedar_solve_attempt_ratio",
+                        "Fraction of recent frames for which a solve was attempted.",
+                        recent.mean);
+        }
+        if let Some(recent) =
+            stats.solve_success_fraction.as_ref().and_then(|s| s.recent.as_ref())
+        {
+            write_gauge(&mut out, "cedar_solve_success_ratio",
+                        "Fraction of recent solve attempts that succeeded.",
+                        recent.mean);
+        }
+    }
+    if let Some(temperature) = snapshot.cpu_temperature {
+        write_gauge(&mut out, "cedar_cpu_temperature_celsius",
+                    "CPU temperature.", temperature as f64);
+    }
+    out
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+// Exports the min/median/max of the recent values as quantiles 0/0.5/1, along
+// with their mean.
+fn write_latency(out: &mut String, name: &str, help: &str,
+                 value_stats: &Option<ValueStats>) {
+    let Some(recent) = value_stats.as_ref().and_then(|s| s.recent.as_ref()) else {
+        return;
+    };
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    writeln!(out, "{}{{quantile=\"0\"}} {}", name, recent.min).unwrap();
+    if let Some(median) = recent.median {
+        writeln!(out, "{}{{quantile=\"0.5\"}} {}", name, median).unwrap();
+    }
+    writeln!(out, "{}{{quantile=\"1\"}} {}", name, recent.max).unwrap();
+    writeln!(out, "{}_mean {}", name, recent.mean).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cedar::DescriptiveStats;
+
+    fn recent_stats(min: f64, median: f64, max: f64, mean: f64) -> Option<ValueStats> {
+        Some(ValueStats{
+            recent: Some(DescriptiveStats{min, max, mean, median: Some(median),
+                                          ..Default::default()}),
+            session: None,
+        })
+    }
+
+    #[test]
+    fn test_format_metrics() {
+        assert_eq!(format_metrics(&MetricsSnapshot::default()), "");
+
+        let snapshot = MetricsSnapshot{
+            frame_interval: recent_stats(0.1, 0.2, 0.3, 0.25),
+            processing_stats: Some(ProcessingStats{
+                detect_latency: recent_stats(0.01, 0.02, 0.05, 0.025),
+                solve_success_fraction: recent_stats(0.0, 1.0, 1.0, 0.75),
+                ..Default::default()
+            }),
+            cpu_temperature: Some(51.5),
+        };
+        let text = format_metrics(&snapshot);
+        assert!(text.contains("# TYPE cedar_frame_rate gauge\ncedar_frame_rate 4\n"));
+        assert!(text.contains("cedar_detect_latency_seconds{quantile=\"0\"} 0.01\n"));
+        assert!(text.contains("cedar_detect_latency_seconds{quantile=\"0.5\"} 0.02\n"));
+        assert!(text.contains("cedar_detect_latency_seconds{quantile=\"1\"} 0.05\n"));
+        assert!(text.contains("cedar_detect_latency_seconds_mean 0.025\n"));
+        assert!(text.contains("cedar_solve_success_ratio 0.75\n"));
+        assert!(text.contains("cedar_cpu_temperature_celsius 51.5\n"));
+        assert!(!text.contains("cedar_solve_latency_seconds"));
+    }
+
+}  // mod tests.
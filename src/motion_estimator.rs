@@ -83,6 +83,17 @@ impl MotionEstimator {
         }
     }
 
+    // Discards all history, returning to the Unknown state. Use this when
+    // there has been a discontinuity (e.g. a long solve outage) that would
+    // otherwise be interpreted as motion.
+    pub fn reset(&mut self) {
+        self.set_state(State::Unknown);
+        self.prev_time = None;
+        self.prev_position = None;
+        self.ra_rate = None;
+        self.dec_rate = None;
+    }
+
     // `time` Time at which the image corresponding to `boresight_position` was
     //     captured. Must not be earlier than `time` passed to previous add()
     //     call.
@@ -4,21 +4,45 @@
 // Module to estimate polar axis (mis)alignment.
 // See http://celestialwonders.com/articles/polaralignment/MeasuringAlignmentError.html
 
-use log::{debug};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, SystemTime};
 
-use crate::cedar::{ErrorBoundedValue, PolarAlignAdvice};
+use canonical_error::{CanonicalError, failed_precondition_error};
+use log::{debug, info};
+
+use crate::astro_util::{alt_az_from_equatorial, precess_from_j2000};
+use crate::cedar::{ErrorBoundedValue, PolarAlignAdvice, PolarAlignSample,
+                   ThreePointPolarAlign};
 use crate::tetra3_server::CelestialCoord;
 use crate::motion_estimator::MotionEstimate;
 
+// Number of PolarAlignAdvice.history entries retained.
+const HISTORY_SIZE: usize = 100;
+
 pub struct PolarAnalyzer {
     polar_align_advice: PolarAlignAdvice,
+
+    // Most recent misalignment estimates, oldest first.
+    history: VecDeque<PolarAlignSample>,
+
+    // Most recent process_solution() position, its time, and whether the
+    // mount was then stationary (tracking but not slewing).
+    latest_solution: Option<(CelestialCoord, SystemTime, bool)>,
+
+    // Three-point polar alignment state. None if not started. Captured
+    // positions are keyed by step index (0..3).
+    three_point_captures: Option<BTreeMap<usize, CelestialCoord>>,
+    three_point_result: Option<ThreePointPolarAlign>,
 }
 
 impl PolarAnalyzer {
     pub fn new() -> Self {
         PolarAnalyzer{
-            polar_align_advice: PolarAlignAdvice{azimuth_correction: None,
-                                                 altitude_correction: None},
+            polar_align_advice: PolarAlignAdvice{..Default::default()},
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            latest_solution: None,
+            three_point_captures: None,
+            three_point_result: None,
         }
     }
 
@@ -28,8 +52,50 @@ impl PolarAnalyzer {
     //   latitude).
     // When certain other conditions are met, this function updates the
     // `polar_align_advice` state.
+    // `time` is the capture time of the image that yielded `boresight_pos`.
     pub fn process_solution(&mut self, boresight_pos: &CelestialCoord, hour_angle: f32,
-                            latitude: f32, motion_estimate: &Option<MotionEstimate>) {
+                            latitude: f32, motion_estimate: &Option<MotionEstimate>,
+                            time: SystemTime) {
+        self.latest_solution =
+            Some((boresight_pos.clone(), time, motion_estimate.is_some()));
+        self.update_corrections(boresight_pos, hour_angle, latitude, motion_estimate);
+        self.update_errors(time);
+    }
+
+    // Derives the arcminute error values, history, and trend from the
+    // corrections.
+    fn update_errors(&mut self, time: SystemTime) {
+        let advice = &mut self.polar_align_advice;
+        advice.azimuth_error_arcmin =
+            advice.azimuth_correction.as_ref().map(|c| -c.value * 60.0);
+        advice.altitude_error_arcmin =
+            advice.altitude_correction.as_ref().map(|c| -c.value * 60.0);
+        advice.improving = None;
+        if advice.azimuth_error_arcmin.is_none() && advice.altitude_error_arcmin.is_none() {
+            return;
+        }
+        // Compare against the most recent prior estimate for the same axis.
+        if let Some(az_error) = advice.azimuth_error_arcmin {
+            if let Some(prev) = self.history.iter().rev().find_map(|s| s.azimuth_error_arcmin) {
+                advice.improving = Some(az_error.abs() < prev.abs());
+            }
+        } else if let Some(alt_error) = advice.altitude_error_arcmin {
+            if let Some(prev) = self.history.iter().rev().find_map(|s| s.altitude_error_arcmin) {
+                advice.improving = Some(alt_error.abs() < prev.abs());
+            }
+        }
+        if self.history.len() >= HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(PolarAlignSample{
+            time: Some(prost_types::Timestamp::from(time)),
+            azimuth_error_arcmin: advice.azimuth_error_arcmin,
+            altitude_error_arcmin: advice.altitude_error_arcmin,
+        });
+    }
+
+    fn update_corrections(&mut self, boresight_pos: &CelestialCoord, hour_angle: f32,
+                          latitude: f32, motion_estimate: &Option<MotionEstimate>) {
         self.polar_align_advice.azimuth_correction = None;
         self.polar_align_advice.altitude_correction = None;
         if motion_estimate.is_none() {
@@ -146,6 +212,172 @@ impl PolarAnalyzer {
     }
 
     pub fn get_polar_align_advice(&self) -> PolarAlignAdvice {
-        self.polar_align_advice.clone()
+        let mut advice = self.polar_align_advice.clone();
+        advice.history = self.history.iter().cloned().collect();
+        advice
+    }
+
+    // Starts a three-point polar alignment, discarding any previous one.
+    pub fn three_point_begin(&mut self) {
+        self.three_point_captures = Some(BTreeMap::new());
+        self.three_point_result = None;
+    }
+
+    // Records the most recent solution as the next three-point position. Once
+    // three positions are captured, computes the mount axis misalignment
+    // using the observer location (degrees) and `now`.
+    pub fn three_point_capture(&mut self, now: SystemTime, latitude: f32, longitude: f32)
+                               -> Result<(), CanonicalError> {
+        // A solution older than this is not considered current.
+        const MAX_SOLUTION_AGE: Duration = Duration::from_secs(5);
+        let Some(captures) = &mut self.three_point_captures else {
+            return Err(failed_precondition_error(
+                "Three-point polar alignment has not been started"));
+        };
+        if captures.len() >= 3 {
+            return Err(failed_precondition_error(
+                "Three-point polar alignment is complete"));
+        }
+        let Some((position, time, stationary)) = &self.latest_solution else {
+            return Err(failed_precondition_error("No plate solution"));
+        };
+        if now.duration_since(*time).unwrap_or(Duration::ZERO) > MAX_SOLUTION_AGE {
+            return Err(failed_precondition_error("No recent plate solution"));
+        }
+        if !*stationary {
+            return Err(failed_precondition_error(
+                "Mount is moving; wait for it to settle"));
+        }
+        let step = captures.len();
+        captures.insert(step, position.clone());
+        info!("Three-point polar alignment captured position {}: {:?}", step, position);
+        if captures.len() < 3 {
+            return Ok(());
+        }
+
+        let points = [captures[&0].clone(), captures[&1].clone(), captures[&2].clone()];
+        let Some((mut axis_ra, mut axis_dec)) = fit_rotation_axis(&points) else {
+            self.three_point_captures = Some(BTreeMap::new());
+            return Err(failed_precondition_error(
+                "Captured positions are too close together; rotate further in RA \
+                 between captures. Restarting."));
+        };
+        // Of the two antipodal axis directions, use the one in the observer's
+        // hemisphere.
+        if (axis_dec < 0.0) != (latitude < 0.0) {
+            axis_ra = (axis_ra + 180.0) % 360.0;
+            axis_dec = -axis_dec;
+        }
+        // Plate solutions are J2000; precess to the current epoch for
+        // comparison with the true pole.
+        let (axis_ra_now, axis_dec_now) = precess_from_j2000(
+            (axis_ra as f64).to_radians(), (axis_dec as f64).to_radians(), now);
+        let (alt, az, _ha) = alt_az_from_equatorial(
+            axis_ra_now, axis_dec_now,
+            (latitude as f64).to_radians(), (longitude as f64).to_radians(), now);
+        let axis_dec_now = axis_dec_now.to_degrees() as f32;
+        let (alt, az) = (alt.to_degrees() as f32, az.to_degrees() as f32);
+        // The celestial pole is due north (south) at altitude |latitude|.
+        let pole_az = if latitude < 0.0 { 180.0 } else { 0.0 };
+        let mut az_delta = az - pole_az;
+        if az_delta > 180.0 {
+            az_delta -= 360.0;
+        } else if az_delta < -180.0 {
+            az_delta += 360.0;
+        }
+        self.three_point_result = Some(ThreePointPolarAlign{
+            captures: 3,
+            axis: Some(CelestialCoord{ra: axis_ra, dec: axis_dec}),
+            azimuth_error_arcmin: Some(az_delta * alt.to_radians().cos() * 60.0),
+            altitude_error_arcmin: Some((alt - latitude.abs()) * 60.0),
+            total_error_arcmin: Some((90.0 - axis_dec_now.abs()) * 60.0),
+        });
+        info!("Three-point polar alignment result: {:?}", self.three_point_result);
+        Ok(())
+    }
+
+    pub fn get_three_point_status(&self) -> Option<ThreePointPolarAlign> {
+        if self.three_point_result.is_some() {
+            return self.three_point_result.clone();
+        }
+        self.three_point_captures.as_ref().map(|captures| ThreePointPolarAlign{
+            captures: captures.len() as i32, ..Default::default()})
+    }
+}
+
+fn unit_vector(coord: &CelestialCoord) -> [f64; 3] {
+    let ra = (coord.ra as f64).to_radians();
+    let dec = (coord.dec as f64).to_radians();
+    [dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin()]
+}
+
+// Given three sky positions (degrees) lying on a circle, as when a mount is
+// rotated about its RA axis, returns the (ra, dec) (degrees) of the circle's
+// axis. Of the two antipodal solutions, the one with non-negative dec is
+// returned. None if the points are (nearly) collinear or coincident.
+pub fn fit_rotation_axis(points: &[CelestialCoord; 3]) -> Option<(f32, f32)> {
+    let p0 = unit_vector(&points[0]);
+    let p1 = unit_vector(&points[1]);
+    let p2 = unit_vector(&points[2]);
+    let a = [p1[0] - p0[0], p1[1] - p0[1], p1[2] - p0[2]];
+    let b = [p2[0] - p0[0], p2[1] - p0[1], p2[2] - p0[2]];
+    let mut n = [a[1] * b[2] - a[2] * b[1],
+                 a[2] * b[0] - a[0] * b[2],
+                 a[0] * b[1] - a[1] * b[0]];
+    let norm = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if norm < 1.0e-6 {
+        return None;
+    }
+    if n[2] < 0.0 {
+        n = [-n[0], -n[1], -n[2]];
     }
+    let dec = (n[2] / norm).asin().to_degrees();
+    let mut ra = n[1].atan2(n[0]).to_degrees();
+    if ra < 0.0 {
+        ra += 360.0;
+    }
+    Some((ra as f32, dec as f32))
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate approx;
+    use approx::assert_abs_diff_eq;
+    use super::*;
+
+    #[test]
+    fn test_fit_rotation_axis() {
+        // Axis at ra=30, dec=89.5; points 20 degrees from the axis, 60 degrees
+        // apart in rotation.
+        let axis = unit_vector(&CelestialCoord{ra: 30.0, dec: 89.5});
+        // Orthonormal basis perpendicular to the axis.
+        let u = {
+            let t = [-axis[1], axis[0], 0.0];
+            let n = (t[0] * t[0] + t[1] * t[1]).sqrt();
+            [t[0] / n, t[1] / n, 0.0]
+        };
+        let v = [axis[1] * u[2] - axis[2] * u[1],
+                 axis[2] * u[0] - axis[0] * u[2],
+                 axis[0] * u[1] - axis[1] * u[0]];
+        let radius = 20.0_f64.to_radians();
+        let mut points = Vec::<CelestialCoord>::new();
+        for step in 0..3 {
+            let t = (step as f64 * 60.0).to_radians();
+            let p: Vec<f64> = (0..3).map(|i| {
+                radius.cos() * axis[i] +
+                    radius.sin() * (t.cos() * u[i] + t.sin() * v[i])
+            }).collect();
+            points.push(CelestialCoord{ra: p[1].atan2(p[0]).to_degrees() as f32,
+                                       dec: p[2].asin().to_degrees() as f32});
+        }
+        let (ra, dec) = fit_rotation_axis(
+            &[points[0].clone(), points[1].clone(), points[2].clone()]).unwrap();
+        assert_abs_diff_eq!(dec, 89.5, epsilon = 0.01);
+        assert_abs_diff_eq!(ra, 30.0, epsilon = 1.0);
+
+        // Coincident points.
+        let p = CelestialCoord{ra: 10.0, dec: 20.0};
+        assert!(fit_rotation_axis(&[p.clone(), p.clone(), p.clone()]).is_none());
+    }
+
+}  // mod tests.
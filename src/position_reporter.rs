@@ -1,13 +1,28 @@
 // Copyright (c) 2024 Steven Rosenthal smr@dt3.org
 // See LICENSE file in root directory for license terms.
 
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use ascom_alpaca::{ASCOMResult, Server};
 use ascom_alpaca::api::{AlignmentMode, Axis, CargoServerInfo,
                         Device, EquatorialSystem, Telescope};
 use async_trait::async_trait;
 
+use crate::cedar::PierSide;
+
+// Number of most recent goto targets retained in TelescopePosition.
+pub const GOTO_HISTORY_SIZE: usize = 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GotoTarget {
+    pub ra: f64,  // 0..360
+    pub dec: f64, // -90..90
+    // When the slew to this target was first initiated.
+    pub time: SystemTime,
+}
+
 #[derive(Default, Debug)]
 pub struct TelescopePosition {
     // The telescope's boresight position is determined by Cedar.
@@ -21,6 +36,17 @@ pub struct TelescopePosition {
     pub slew_target_ra: f64,  // 0..360
     pub slew_target_dec: f64, // -90..90
     pub slew_active: bool,
+
+    // Targets of recent slews, oldest first, bounded by GOTO_HISTORY_SIZE.
+    // Navigating with goto_previous()/goto_next() moves `goto_history_index`
+    // without adding entries.
+    pub goto_history: VecDeque<GotoTarget>,
+    pub goto_history_index: Option<usize>,
+
+    // For an equatorial mount. Inferred from the boresight's hour angle unless
+    // `pier_side_manual` is set.
+    pub pier_side: PierSide,
+    pub pier_side_manual: bool,
 }
 
 impl TelescopePosition {
@@ -28,6 +54,72 @@ impl TelescopePosition {
         // Sky Safari doesn't display (0.0, 0.0).
         TelescopePosition{boresight_ra: 180.0, boresight_dec: 0.0, ..Default::default()}
     }
+
+    // Starts a slew to the given target (degrees), recording it in the goto
+    // history. Re-issuing the most recent target does not add a new entry.
+    pub fn start_slew(&mut self, ra: f64, dec: f64) {
+        self.slew_target_ra = ra;
+        self.slew_target_dec = dec;
+        self.slew_active = true;
+        if let Some(last) = self.goto_history.back() {
+            if last.ra == ra && last.dec == dec {
+                self.goto_history_index = Some(self.goto_history.len() - 1);
+                return;
+            }
+        }
+        if self.goto_history.len() == GOTO_HISTORY_SIZE {
+            self.goto_history.pop_front();
+        }
+        self.goto_history.push_back(GotoTarget{ra, dec, time: SystemTime::now()});
+        self.goto_history_index = Some(self.goto_history.len() - 1);
+    }
+
+    // Re-issues the slew to the goto history entry before (or after) the one
+    // most recently slewed to. Returns false, without slewing, if there is no
+    // such entry.
+    pub fn goto_previous(&mut self) -> bool {
+        match self.goto_history_index {
+            Some(index) if index > 0 => self.goto_history_entry(index - 1),
+            _ => false,
+        }
+    }
+    pub fn goto_next(&mut self) -> bool {
+        match self.goto_history_index {
+            Some(index) if index + 1 < self.goto_history.len() =>
+                self.goto_history_entry(index + 1),
+            _ => false,
+        }
+    }
+
+    fn goto_history_entry(&mut self, index: usize) -> bool {
+        let target = &self.goto_history[index];
+        self.slew_target_ra = target.ra;
+        self.slew_target_dec = target.dec;
+        self.slew_active = true;
+        self.goto_history_index = Some(index);
+        true
+    }
+
+    // Updates the inferred pier side given the boresight hour angle (degrees,
+    // -180..180). A telescope looking west of the meridian is assumed to be on
+    // the east side of the pier.
+    pub fn infer_pier_side(&mut self, hour_angle: f64) {
+        if self.pier_side_manual {
+            return;
+        }
+        self.pier_side = if hour_angle >= 0.0 { PierSide::East } else { PierSide::West };
+    }
+
+    // Returns whether reaching a target at the given hour angle (degrees,
+    // -180..180) requires a meridian flip from the current pier side. None if
+    // the pier side is not known.
+    pub fn meridian_flip_needed(&self, target_hour_angle: f64) -> Option<bool> {
+        match self.pier_side {
+            PierSide::East => Some(target_hour_angle < 0.0),
+            PierSide::West => Some(target_hour_angle >= 0.0),
+            PierSide::Unspecified => None,
+        }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -109,9 +201,7 @@ impl Telescope for MyTelescope {
     async fn slew_to_coordinates_async(&self, right_ascension: f64, declination: f64)
                                        -> ASCOMResult {
         let mut locked_position = self.telescope_position.lock().unwrap();
-        locked_position.slew_target_ra = right_ascension * 15.0;
-        locked_position.slew_target_dec = declination;
-        locked_position.slew_active = true;
+        locked_position.start_slew(right_ascension * 15.0, declination);
         Ok(())
     }
 
@@ -147,3 +237,35 @@ pub fn create_alpaca_server(telescope_position: Arc<Mutex<TelescopePosition>>)
     server.devices.register(MyTelescope::new(telescope_position));
     server
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_goto_history() {
+        let mut position = TelescopePosition::new();
+        assert!(!position.goto_previous());
+        position.start_slew(10.0, 20.0);
+        position.start_slew(30.0, 40.0);
+        position.start_slew(30.0, 40.0);  // Repeat is not recorded again.
+        assert_eq!(position.goto_history.len(), 2);
+        assert!(!position.goto_next());
+
+        assert!(position.goto_previous());
+        assert_eq!((position.slew_target_ra, position.slew_target_dec), (10.0, 20.0));
+        assert!(position.slew_active);
+        assert!(!position.goto_previous());
+        assert!(position.goto_next());
+        assert_eq!((position.slew_target_ra, position.slew_target_dec), (30.0, 40.0));
+
+        // Bounded; oldest entries are dropped.
+        for i in 0..GOTO_HISTORY_SIZE {
+            position.start_slew(i as f64, 0.0);
+        }
+        assert_eq!(position.goto_history.len(), GOTO_HISTORY_SIZE);
+        assert_eq!(position.goto_history[0].ra, 0.0);
+        assert_eq!(position.goto_history_index, Some(GOTO_HISTORY_SIZE - 1));
+    }
+
+}  // mod tests.
@@ -0,0 +1,202 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Human-readable JSON form of the Preferences message, for backing up, diffing
+// and sharing configurations. Enum values are written by name (e.g.
+// "HMS_DMS"); fields absent from the Preferences are omitted.
+//
+// observer_location is device state rather than a preference (it is kept in
+// Preferences only to persist it), so it is neither exported nor imported;
+// a file shared from another device must not move this one.
+
+use canonical_error::{CanonicalError, invalid_argument_error};
+use serde_json::{Map, Value};
+
+use crate::cedar::{CelestialCoordFormat, CelestialCoordSystem, MountType, Preferences};
+
+pub fn preferences_to_json(prefs: &Preferences) -> String {
+    let mut map = Map::new();
+    put(&mut map, "celestial_coord_format", prefs.celestial_coord_format.map(
+        |v| enum_value(CelestialCoordFormat::try_from(v).ok().map(|e| e.as_str_name()), v)));
+    put(&mut map, "eyepiece_fov", prefs.eyepiece_fov);
+    put(&mut map, "night_vision_theme", prefs.night_vision_theme);
+    put(&mut map, "show_perf_stats", prefs.show_perf_stats);
+    put(&mut map, "hide_app_bar", prefs.hide_app_bar);
+    put(&mut map, "mount_type", prefs.mount_type.map(
+        |v| enum_value(MountType::try_from(v).ok().map(|e| e.as_str_name()), v)));
+    put(&mut map, "camera_mount_angle", prefs.camera_mount_angle);
+    put(&mut map, "dark_frame_subtraction", prefs.dark_frame_subtraction);
+    put(&mut map, "celestial_coord_system", prefs.celestial_coord_system.map(
+        |v| enum_value(CelestialCoordSystem::try_from(v).ok().map(|e| e.as_str_name()), v)));
+    put(&mut map, "flat_field", prefs.flat_field.clone());
+    put(&mut map, "mirror_horizontal", prefs.mirror_horizontal);
+    put(&mut map, "mirror_vertical", prefs.mirror_vertical);
+    put(&mut map, "min_slew_altitude", prefs.min_slew_altitude);
+    if !prefs.horizon_profile.is_empty() {
+        map.insert("horizon_profile".to_string(), prefs.horizon_profile.clone().into());
+    }
+    serde_json::to_string_pretty(&Value::Object(map)).unwrap()
+}
+
+// Parses the output of preferences_to_json(). Fields that are absent (or null)
+// are left unset. Range checks are the caller's responsibility.
+pub fn preferences_from_json(json: &str) -> Result<Preferences, CanonicalError> {
+    let value: Value = match serde_json::from_str(json) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(invalid_argument_error(
+                format!("Malformed preferences JSON: {}", e).as_str()));
+        }
+    };
+    let Value::Object(map) = value else {
+        return Err(invalid_argument_error("Preferences JSON must be an object"));
+    };
+    let mut prefs = Preferences::default();
+    for (key, value) in &map {
+        if value.is_null() {
+            continue;
+        }
+        match key.as_str() {
+            "celestial_coord_format" => prefs.celestial_coord_format = Some(
+                parse_enum(key, value, |s| CelestialCoordFormat::from_str_name(s)
+                           .map(|e| e as i32))?),
+            "eyepiece_fov" => prefs.eyepiece_fov = Some(parse_float(key, value)?),
+            "night_vision_theme" => prefs.night_vision_theme = Some(parse_bool(key, value)?),
+            "show_perf_stats" => prefs.show_perf_stats = Some(parse_bool(key, value)?),
+            "hide_app_bar" => prefs.hide_app_bar = Some(parse_bool(key, value)?),
+            "mount_type" => prefs.mount_type = Some(
+                parse_enum(key, value, |s| MountType::from_str_name(s)
+                           .map(|e| e as i32))?),
+            "camera_mount_angle" => prefs.camera_mount_angle = Some(parse_float(key, value)?),
+            "dark_frame_subtraction" => prefs.dark_frame_subtraction =
+                Some(parse_bool(key, value)?),
+            "celestial_coord_system" => prefs.celestial_coord_system = Some(
+                parse_enum(key, value, |s| CelestialCoordSystem::from_str_name(s)
+                           .map(|e| e as i32))?),
+            "flat_field" => prefs.flat_field = Some(parse_string(key, value)?),
+            "mirror_horizontal" => prefs.mirror_horizontal = Some(parse_bool(key, value)?),
+            "mirror_vertical" => prefs.mirror_vertical = Some(parse_bool(key, value)?),
+            "min_slew_altitude" => prefs.min_slew_altitude = Some(parse_float(key, value)?),
+            "horizon_profile" => {
+                let Some(values) = value.as_array() else {
+                    return Err(invalid_argument_error(
+                        format!("Preference {:?} must be a list; got {}", key, value)
+                            .as_str()));
+                };
+                prefs.horizon_profile = values.iter().map(|v| parse_float(key, v))
+                    .collect::<Result<Vec<f32>, CanonicalError>>()?;
+            }
+            // Written by earlier versions; ignored (see above).
+            "observer_location" => (),
+            _ => {
+                return Err(invalid_argument_error(
+                    format!("Unknown preference {:?}", key).as_str()));
+            }
+        }
+    }
+    Ok(prefs)
+}
+
+fn put<T: Into<Value>>(map: &mut Map<String, Value>, key: &str, value: Option<T>) {
+    if let Some(v) = value {
+        map.insert(key.to_string(), v.into());
+    }
+}
+
+// Unrecognized enum values are written as numbers.
+fn enum_value(name: Option<&str>, value: i32) -> Value {
+    match name {
+        Some(name) => Value::from(name),
+        None => Value::from(value),
+    }
+}
+
+fn parse_float(key: &str, value: &Value) -> Result<f32, CanonicalError> {
+    match value.as_f64() {
+        Some(v) if v.is_finite() => Ok(v as f32),
+        _ => Err(invalid_argument_error(
+            format!("Preference {:?} must be a number; got {}", key, value).as_str())),
+    }
+}
+
+fn parse_string(key: &str, value: &Value) -> Result<String, CanonicalError> {
+    value.as_str().map(|s| s.to_string()).ok_or_else(|| invalid_argument_error(
+        format!("Preference {:?} must be a string; got {}", key, value).as_str()))
+}
+
+fn parse_bool(key: &str, value: &Value) -> Result<bool, CanonicalError> {
+    value.as_bool().ok_or_else(|| invalid_argument_error(
+        format!("Preference {:?} must be true or false; got {}", key, value).as_str()))
+}
+
+// Accepts either the enum value's name or its number.
+fn parse_enum(key: &str, value: &Value, from_name: impl Fn(&str) -> Option<i32>)
+              -> Result<i32, CanonicalError> {
+    let parsed = match value {
+        Value::String(s) => from_name(s),
+        Value::Number(n) => n.as_i64().and_then(|n| i32::try_from(n).ok()),
+        _ => None,
+    };
+    parsed.ok_or_else(|| invalid_argument_error(
+        format!("Invalid value {} for preference {:?}", value, key).as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use canonical_error::CanonicalErrorCode;
+    use crate::cedar::LatLong;
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let prefs = Preferences{
+            celestial_coord_format: Some(CelestialCoordFormat::HmsDms.into()),
+            eyepiece_fov: Some(0.5),
+            night_vision_theme: Some(true),
+            mount_type: Some(MountType::AltAz.into()),
+            celestial_coord_system: Some(CelestialCoordSystem::SystemGalactic.into()),
+            mirror_horizontal: Some(true),
+            min_slew_altitude: Some(15.0),
+            horizon_profile: vec![10.0, 25.5, 0.0],
+            ..Default::default()
+        };
+        let json = preferences_to_json(&prefs);
+        assert!(json.contains("\"mount_type\": \"ALT_AZ\""));
+        assert!(!json.contains("hide_app_bar"));
+        assert_eq!(preferences_from_json(&json).unwrap(), prefs);
+
+        assert_eq!(preferences_to_json(&Preferences::default()), "{}");
+    }
+
+    #[test]
+    fn test_observer_location_not_shared() {
+        let prefs = Preferences{
+            observer_location: Some(LatLong{latitude: 42.5, longitude: -71.25}),
+            ..Default::default()
+        };
+        assert_eq!(preferences_to_json(&prefs), "{}");
+        let imported = preferences_from_json(
+            r#"{"observer_location": {"latitude": 1.0, "longitude": 2.0}}"#).unwrap();
+        assert_eq!(imported.observer_location, None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let prefs = preferences_from_json(
+            r#"{"mount_type": 1, "show_perf_stats": false, "eyepiece_fov": null}"#).unwrap();
+        assert_eq!(prefs.mount_type, Some(MountType::Equatorial.into()));
+        assert_eq!(prefs.show_perf_stats, Some(false));
+        assert_eq!(prefs.eyepiece_fov, None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for json in ["{", "[]", r#"{"eyepiece_fov": "big"}"#,
+                     r#"{"mount_type": "DOBSONIAN"}"#, r#"{"hide_app_bar": 1}"#,
+                     r#"{"no_such_preference": true}"#] {
+            let err = preferences_from_json(json).unwrap_err();
+            assert_eq!(err.code, CanonicalErrorCode::InvalidArgument, "{}", json);
+        }
+    }
+
+}  // mod tests.
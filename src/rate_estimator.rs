@@ -159,6 +159,47 @@ impl RateEstimation {
     }
 }
 
+// Measures the rate (per second) at which events occur, e.g. frames being
+// captured, as the slope of the running event count over time.
+pub struct EventRate {
+    capacity: usize,
+    count: u64,
+    estimation: Option<RateEstimation>,
+}
+
+impl EventRate {
+    // `capacity` is as for RateEstimation::new().
+    pub fn new(capacity: usize) -> Self {
+        EventRate{capacity, count: 0, estimation: None}
+    }
+
+    // Successive calls must have increasing `time` arg values.
+    pub fn add_event(&mut self, time: SystemTime) {
+        self.count += 1;
+        match &mut self.estimation {
+            Some(estimation) => estimation.add(time, self.count as f64),
+            None => {
+                self.estimation =
+                    Some(RateEstimation::new(self.capacity, time, self.count as f64));
+            }
+        }
+    }
+
+    // Events per second since creation or the last reset(). None until there
+    // have been at least two events.
+    pub fn rate(&self) -> Option<f64> {
+        match &self.estimation {
+            Some(estimation) if estimation.count() > 1 => Some(estimation.slope()),
+            _ => None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.estimation = None;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate approx;
@@ -193,4 +234,27 @@ mod tests {
         assert!(re.fits_trend(time, 1.31, /*sigma=*/5.0));
     }
 
+    #[test]
+    fn test_event_rate() {
+        let mut time = SystemTime::now();
+        let mut rate = EventRate::new(5);
+        assert_eq!(rate.rate(), None);
+        rate.add_event(time);
+        assert_eq!(rate.rate(), None);
+        // 4 Hz, for longer than the capacity.
+        for _ in 0..20 {
+            time += Duration::from_millis(250);
+            rate.add_event(time);
+        }
+        assert_abs_diff_eq!(rate.rate().unwrap(), 4.0, epsilon = 0.001);
+
+        rate.reset();
+        assert_eq!(rate.rate(), None);
+        time += Duration::from_secs(1);
+        rate.add_event(time);
+        time += Duration::from_millis(500);
+        rate.add_event(time);
+        assert_abs_diff_eq!(rate.rate().unwrap(), 2.0, epsilon = 0.001);
+    }
+
 }  // mod tests.
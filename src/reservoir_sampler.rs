@@ -13,10 +13,17 @@ pub struct ReservoirSampler<T> {
 
 impl<T> ReservoirSampler<T> {
     pub fn new(capacity: usize) -> Self {
+        Self::with_seed(capacity, 42)
+    }
+
+    // As new(), with the given seed for the random choice of which items are
+    // kept. A given seed and sequence of add() calls always yields the same
+    // samples.
+    pub fn with_seed(capacity: usize, seed: u64) -> Self {
         ReservoirSampler {
             reservoir: Vec::with_capacity(capacity),
             capacity,
-            rng: SmallRng::seed_from_u64(42),
+            rng: SmallRng::seed_from_u64(seed),
             add_count: 0,
         }
     }
@@ -52,3 +59,58 @@ impl<T> ReservoirSampler<T> {
         self.add_count = 0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_and_replace() {
+        let mut sampler = ReservoirSampler::<i32>::with_seed(3, 1);
+        assert_eq!(sampler.add(0), (true, None));
+        assert_eq!(sampler.add(1), (true, None));
+        assert_eq!(sampler.add(2), (true, None));
+        assert_eq!(sampler.samples(), &vec![0, 1, 2]);
+        for i in 3..100 {
+            let (added, removed) = sampler.add(i);
+            // An item is removed exactly when one is added.
+            assert_eq!(added, removed.is_some());
+        }
+        assert_eq!(sampler.count(), 3);
+        sampler.clear();
+        assert_eq!(sampler.count(), 0);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let run = |seed| {
+            let mut sampler = ReservoirSampler::<i32>::with_seed(10, seed);
+            for i in 0..1000 {
+                sampler.add(i);
+            }
+            sampler.samples().clone()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_uniform() {
+        // Over many seeds, each tenth of the input is equally represented.
+        let mut counts = [0; 10];
+        for seed in 0..200 {
+            let mut sampler = ReservoirSampler::<usize>::with_seed(100, seed);
+            for i in 0..10000 {
+                sampler.add(i);
+            }
+            for i in sampler.samples() {
+                counts[i / 1000] += 1;
+            }
+        }
+        // 200 * 100 samples in total.
+        for count in counts {
+            assert!((1800..=2200).contains(&count), "{:?}", counts);
+        }
+    }
+
+}  // mod tests.
@@ -2,6 +2,11 @@
 // See LICENSE file in root directory for license terms.
 
 use image::GrayImage;
+use rayon::prelude::*;
+
+// Images are processed in parallel in chunks of at least this many pixels;
+// smaller chunks cost more in scheduling than they gain.
+const MIN_PARALLEL_CHUNK: usize = 64 * 1024;
 
 fn compute_lut(min_pixel_value: u8,
                peak_pixel_value: u8,
@@ -32,8 +37,16 @@ pub fn scale_image(
     }
     let lut = compute_lut(min_pixel_value, peak_pixel_value, gamma);
 
-    // Apply the lut
-    let out_vec: Vec<u8> = image.as_raw().iter().map(|x| lut[*x as usize]).collect();
+    // Apply the lut. Each pixel is mapped independently, so the result does
+    // not depend on how rayon divides the work.
+    let mut out_vec = vec![0_u8; image.as_raw().len()];
+    out_vec.par_chunks_mut(MIN_PARALLEL_CHUNK)
+        .zip(image.as_raw().par_chunks(MIN_PARALLEL_CHUNK))
+        .for_each(|(out_chunk, in_chunk)| {
+            for (out, x) in out_chunk.iter_mut().zip(in_chunk) {
+                *out = lut[*x as usize];
+            }
+        });
 
     let (width, height) = image.dimensions();
     GrayImage::from_raw(width, height, out_vec).unwrap()
@@ -47,7 +60,120 @@ pub fn scale_image_mut(
     }
     let lut = compute_lut(min_pixel_value, peak_pixel_value, gamma);
 
-    for pixel in image.pixels_mut() {
-        pixel[0] = lut[pixel[0] as usize];
+    image.par_chunks_mut(MIN_PARALLEL_CHUNK).for_each(|chunk| {
+        for pixel in chunk {
+            *pixel = lut[*pixel as usize];
+        }
+    });
+}
+
+// Counts the pixels of `image` into `bins` equal-width bins spanning 0..255.
+// `bins` must divide 256.
+pub fn coarse_histogram(image: &GrayImage, bins: usize) -> Vec<i32> {
+    assert_eq!(256 % bins, 0);
+    let mut counts = [0_i32; 256];
+    for pixel in image.as_raw() {
+        counts[*pixel as usize] += 1;
     }
+    counts.chunks(256 / bins).map(|c| c.iter().sum()).collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use super::*;
+    use image::Luma;
+
+    // Single threaded reference for scale_image().
+    fn scale_image_serial(
+        image: &GrayImage, mut min_pixel_value: u8, peak_pixel_value: u8, gamma: f32)
+        -> GrayImage {
+        if min_pixel_value > peak_pixel_value / 2 {
+            min_pixel_value = peak_pixel_value / 2;
+        }
+        let lut = compute_lut(min_pixel_value, peak_pixel_value, gamma);
+        let out_vec: Vec<u8> = image.as_raw().iter().map(|x| lut[*x as usize]).collect();
+        let (width, height) = image.dimensions();
+        GrayImage::from_raw(width, height, out_vec).unwrap()
+    }
+
+    // 12.3 megapixel, as the Raspberry Pi HQ camera.
+    fn hq_frame() -> GrayImage {
+        GrayImage::from_fn(4056, 3040, |x, y| Luma::<u8>([(x * 7 + y * 13) as u8]))
+    }
+
+    #[test]
+    fn test_scale_image() {
+        // Large enough to be split across threads.
+        let image = GrayImage::from_fn(1000, 300, |x, y| Luma::<u8>([(x ^ y) as u8]));
+        let lut = compute_lut(20, 200, 0.7);
+        let expected = GrayImage::from_fn(1000, 300, |x, y| {
+            Luma::<u8>([lut[image.get_pixel(x, y).0[0] as usize]])
+        });
+        assert_eq!(scale_image(&image, 20, 200, 0.7), expected);
+        let mut scaled = image.clone();
+        scale_image_mut(&mut scaled, 20, 200, 0.7);
+        assert_eq!(scaled, expected);
+
+        // Black level is limited to half of the peak.
+        let scaled = scale_image(&image, 150, 200, 0.7);
+        assert_eq!(scaled.get_pixel(100, 0).0[0], 0);
+        assert_eq!(scaled.get_pixel(101, 0).0[0], scale_image(&image, 100, 200, 0.7)
+                   .get_pixel(101, 0).0[0]);
+    }
+
+    #[test]
+    fn test_scale_image_matches_serial() {
+        let image = hq_frame();
+        for (min, peak, gamma) in [(0, 255, 1.0), (20, 200, 0.7), (150, 180, 0.5)] {
+            let serial = scale_image_serial(&image, min, peak, gamma);
+            assert_eq!(scale_image(&image, min, peak, gamma), serial);
+            let mut scaled = image.clone();
+            scale_image_mut(&mut scaled, min, peak, gamma);
+            assert_eq!(scaled, serial);
+        }
+    }
+
+    // Compares serial and parallel scaling of a full resolution HQ camera
+    // frame. Run with:
+    // cargo test --release test_scale_image_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn test_scale_image_benchmark() {
+        let image = hq_frame();
+        const ITERATIONS: u32 = 20;
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(scale_image_serial(&image, 20, 200, 0.7));
+        }
+        let serial = start.elapsed() / ITERATIONS;
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(scale_image(&image, 20, 200, 0.7));
+        }
+        let parallel = start.elapsed() / ITERATIONS;
+        let mut scaled = image.clone();
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            scale_image_mut(&mut scaled, 20, 200, 0.7);
+        }
+        let parallel_mut = start.elapsed() / ITERATIONS;
+        println!("{} threads: serial {:?}, parallel {:?}, parallel in place {:?}",
+                 rayon::current_num_threads(), serial, parallel, parallel_mut);
+    }
+
+    #[test]
+    fn test_coarse_histogram() {
+        let mut image = GrayImage::from_pixel(4, 2, Luma::<u8>([10]));
+        image.put_pixel(0, 0, Luma::<u8>([0]));
+        image.put_pixel(1, 0, Luma::<u8>([3]));
+        image.put_pixel(2, 0, Luma::<u8>([255]));
+        let histogram = coarse_histogram(&image, 64);
+        assert_eq!(histogram.len(), 64);
+        assert_eq!(histogram[0], 2);
+        assert_eq!(histogram[2], 5);
+        assert_eq!(histogram[63], 1);
+        assert_eq!(histogram.iter().sum::<i32>(), 8);
+    }
+
+}  // mod tests.
@@ -2,9 +2,14 @@
 // See LICENSE file in root directory for license terms.
 
 use crate::detect_engine::{DetectEngine, DetectResult};
+use crate::fits::{WcsSolution, integer_card, number_card, string_card, write_fits,
+                  write_wcs_file};
+use crate::frame_recorder::{FrameMetadata, FrameRecorder};
+use cedar_camera::abstract_camera::CapturedImage;
 
 use std::cmp::max;
 use std::ops::DerefMut;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime};
 
@@ -12,7 +17,7 @@ use canonical_error::{CanonicalError, failed_precondition_error, invalid_argumen
 use chrono::{DateTime, Local, Utc};
 use image::{GenericImageView, GrayImage};
 use imageproc::rect::Rect;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use tonic::transport::{Endpoint, Uri};
 use tokio::net::UnixStream;
 use tower::service_fn;
@@ -22,13 +27,27 @@ use crate::tetra3_server::{CelestialCoord, ImageCoord, SolveRequest,
                            SolveStatus};
 use crate::tetra3_server::tetra3_client::Tetra3Client;
 use crate::tetra3_subprocess::Tetra3Subprocess;
+use crate::rate_estimator::EventRate;
 use crate::value_stats::ValueStatsAccumulator;
 use crate::cedar;
 use cedar_detect::histogram_funcs::{average_top_values,
                                     get_level_for_fraction,
                                     remove_stars_from_histogram};
 use crate::scale_image::scale_image_mut;
-use crate::astro_util::{angular_separation, position_angle};
+use crate::astro_util::{angular_separation, position_angle,
+                        tangent_plane_from_equatorial};
+
+// See SolveState.exposure_rebase.
+const EXPOSURE_REBASE_SUCCESS_FRACTION: f64 = 0.5;
+const EXPOSURE_REBASE_INTERVAL: Duration = Duration::from_secs(30);
+
+// After this many consecutive failed solve cycles with a FOV estimate, a solve
+// is attempted without the estimate, in case the lens was changed without
+// recalibrating. Blind solves are slow, so they are attempted at most once per
+// BLIND_SOLVE_INTERVAL.
+const BLIND_SOLVE_FAILURE_THRESHOLD: i32 = 10;
+const BLIND_SOLVE_INTERVAL: Duration = Duration::from_secs(30);
+const BLIND_SOLVE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct SolveEngine {
     tetra3_subprocess: Arc<Mutex<Tetra3Subprocess>>,
@@ -59,6 +78,10 @@ struct SolveState {
     // Zero means go fast as star detections are computed.
     update_interval: Duration,
 
+    // Solve cycles are never started more often than this, regardless of
+    // `update_interval`. Does not apply to solve() calls (e.g. calibration).
+    min_solve_interval: Duration,
+
     // Required number of detected stars, below which we don't attempt a plate
     // solution.
     minimum_stars: i32,
@@ -73,14 +96,55 @@ struct SolveState {
     distortion: f32,
     return_matches: bool,
 
+    // If set (degrees), star centroids farther than this from the boresight
+    // (or image center, if no boresight is set) are moved to the end of the
+    // list passed to the solver. Tetra3 forms its match patterns from the
+    // leading (brightest) centroids, so this keeps distorted edge stars out
+    // of pattern matching while still using them for match verification.
+    // Only applied once the FOV is known (i.e. after calibration).
+    centroid_radius: Option<f32>,
+
     // Set if currently slewing to a target.
     slew_target: Option<CelestialCoord>,
 
+    // If true, the captured image is saved whenever a plate solve succeeds,
+    // at most once per `auto_save_interval`.
+    auto_save_images: bool,
+    auto_save_interval: Duration,
+    last_auto_save: Option<Instant>,
+
+    // If true, and the recent solve success fraction falls below
+    // EXPOSURE_REBASE_SUCCESS_FRACTION, the detect engine is asked to
+    // re-estimate its calibrated exposure duration (at most once per
+    // EXPOSURE_REBASE_INTERVAL). This adapts to sky brightness changes during
+    // a session (moonrise, twilight, thin clouds).
+    exposure_rebase: bool,
+
+    // If present, solve results (and their images) are recorded for offline
+    // debugging.
+    frame_recorder: Option<Arc<Mutex<FrameRecorder>>>,
+
     solve_interval_stats: ValueStatsAccumulator,
     solve_latency_stats: ValueStatsAccumulator,
     solve_attempt_stats: ValueStatsAccumulator,
     solve_success_stats: ValueStatsAccumulator,
 
+    // Rate of solve cycles.
+    solve_rate: EventRate,
+
+    // Number of solve cycles since the most recent successful solve.
+    frames_since_solve: i32,
+
+    // Set when a blind solve is first attempted (see
+    // BLIND_SOLVE_FAILURE_THRESHOLD); cleared by a successful solve or a new
+    // FOV estimate.
+    blind_solve_in_progress: bool,
+    last_blind_solve: Option<Instant>,
+
+    // The FOV found by a successful blind solve, until retrieved with
+    // take_blind_solve_fov().
+    blind_solve_fov: Option<f32>,
+
     // Estimated time at which `plate_solution` will next be updated.
     eta: Option<Instant>,
 
@@ -131,6 +195,8 @@ impl SolveEngine {
                      detect_engine: Arc<tokio::sync::Mutex<DetectEngine>>,
                      tetra3_server_address: String,
                      update_interval: Duration,
+                     min_solve_interval: Duration,
+                     auto_save_interval: Duration,
                      stats_capacity: usize,
                      solution_callback: Arc<dyn Fn(Option<DetectResult>,
                                                    Option<SolveResultProto>)
@@ -143,6 +209,7 @@ impl SolveEngine {
             state: Arc::new(Mutex::new(SolveState{
                 frame_id: None,
                 update_interval,
+                min_solve_interval,
                 minimum_stars: 4,
                 fov_estimate: None,
                 match_radius: 0.01,
@@ -151,11 +218,22 @@ impl SolveEngine {
                 boresight_pixel: None,
                 distortion: 0.0,
                 return_matches: true,
+                centroid_radius: None,
                 slew_target: None,
+                auto_save_images: false,
+                exposure_rebase: false,
+                auto_save_interval,
+                last_auto_save: None,
+                frame_recorder: None,
                 solve_interval_stats: ValueStatsAccumulator::new(stats_capacity),
                 solve_latency_stats: ValueStatsAccumulator::new(stats_capacity),
                 solve_attempt_stats: ValueStatsAccumulator::new(stats_capacity),
                 solve_success_stats: ValueStatsAccumulator::new(stats_capacity),
+                solve_rate: EventRate::new(stats_capacity),
+                frames_since_solve: 0,
+                blind_solve_in_progress: false,
+                last_blind_solve: None,
+                blind_solve_fov: None,
                 eta: None,
                 plate_solution: None,
                 stop_request: false,
@@ -188,6 +266,7 @@ impl SolveEngine {
                         fov_estimate.unwrap()).as_str()));
         }
         locked_state.fov_estimate = fov_estimate;
+        locked_state.blind_solve_in_progress = false;
         // Don't need to do anything, worker thread will pick up the change when
         // it finishes the current interval.
         Ok(())
@@ -243,6 +322,41 @@ impl SolveEngine {
         Ok(())
     }
 
+    pub fn set_centroid_radius(&mut self, centroid_radius: Option<f32>)
+                               -> Result<(), CanonicalError> {
+        if centroid_radius.is_some() && centroid_radius.unwrap() <= 0.0 {
+            return Err(invalid_argument_error(
+                format!("centroid_radius must be positive; got {}",
+                        centroid_radius.unwrap()).as_str()));
+        }
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.centroid_radius = centroid_radius;
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+        Ok(())
+    }
+
+    pub fn set_auto_save_images(&mut self, auto_save_images: bool) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.auto_save_images = auto_save_images;
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
+    pub fn set_exposure_rebase(&mut self, enabled: bool) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.exposure_rebase = enabled;
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
+    pub fn set_frame_recorder(&mut self, frame_recorder: Option<FrameRecorder>) {
+        let mut locked_state = self.state.lock().unwrap();
+        locked_state.frame_recorder = frame_recorder.map(|fr| Arc::new(Mutex::new(fr)));
+        // Don't need to do anything, worker thread will pick up the change when
+        // it finishes the current interval.
+    }
+
     // Note: we don't currently provide methods to change match_radius,
     // match_threshold, or return_matches. The defaults for these should be
     // fine.
@@ -287,20 +401,54 @@ impl SolveEngine {
         }
     }
 
+    // True while solves are failing and we are periodically attempting to
+    // solve without the FOV estimate; see BLIND_SOLVE_FAILURE_THRESHOLD.
+    pub fn blind_solve_in_progress(&self) -> bool {
+        self.state.lock().unwrap().blind_solve_in_progress
+    }
+
+    // If a blind solve has succeeded since the previous call, returns the
+    // horizontal FOV (degrees) that it found. This has already been adopted as
+    // our FOV estimate; the caller should update its calibration accordingly.
+    pub fn take_blind_solve_fov(&mut self) -> Option<f32> {
+        self.state.lock().unwrap().blind_solve_fov.take()
+    }
+
     pub fn reset_session_stats(&mut self) {
         let mut state = self.state.lock().unwrap();
         state.solve_interval_stats.reset_session();
         state.solve_latency_stats.reset_session();
         state.solve_attempt_stats.reset_session();
         state.solve_success_stats.reset_session();
+        state.solve_rate.reset();
+        state.frames_since_solve = 0;
     }
 
     // TODO: arg specifying directory to save to.
     pub async fn save_image(&self) -> Result<(), CanonicalError> {
         // Grab most recent image.
         let mut locked_detect_engine = self.detect_engine.lock().await;
-        let captured_image =
-            &locked_detect_engine.get_next_result(/*frame_id=*/None).await.captured_image;
+        let detect_result = locked_detect_engine.get_next_result(/*frame_id=*/None).await;
+        // Use its plate solution, if we have one.
+        let solve_result = match &self.state.lock().unwrap().plate_solution {
+            Some(ps) if ps.detect_result.frame_id == detect_result.frame_id =>
+                ps.tetra3_solve_result.clone(),
+            _ => None,
+        };
+        Self::write_image_file(&detect_result.captured_image, solve_result.as_ref())
+    }
+
+    // Writes `captured_image` to the current directory. If `solve_result` is
+    // a successful plate solution, the image center is incorporated into the
+    // file name and the world coordinate solution is written alongside, in a
+    // file with the same name but a .wcs extension.
+    fn write_image_file(captured_image: &CapturedImage,
+                        solve_result: Option<&SolveResultProto>)
+                        -> Result<(), CanonicalError> {
+        let (width, height) = captured_image.image.dimensions();
+        let wcs = solve_result.and_then(|sr| Self::wcs_solution(sr, width, height));
+        let solution = solve_result.and_then(|sr| sr.image_center_coords.as_ref())
+            .filter(|_| wcs.is_some());
         let image: &GrayImage = &captured_image.image;
         let readout_time: &SystemTime = &captured_image.readout_time;
         let exposure_duration_ms =
@@ -313,16 +461,74 @@ impl SolveEngine {
         let datetime_local: DateTime<Local> = DateTime::from(datetime_utc);
 
         // Generate file name.
-        let filename = format!("img_{}ms_{}.bmp",
-                               exposure_duration_ms, datetime_local.format("%Y%m%d_%H%M%S"));
+        let solution_str = match solution {
+            Some(coords) => format!("ra{:.3}_dec{:.3}_", coords.ra, coords.dec),
+            None => String::new(),
+        };
+        let basename = format!("img_{}ms_{}{}",
+                               exposure_duration_ms, solution_str,
+                               datetime_local.format("%Y%m%d_%H%M%S"));
+        let filename = format!("{}.bmp", basename);
         // Write to current directory.
-        match image.save(filename) {
-            Ok(()) => Ok(()),
-            Err(x) => {
+        if let Err(x) = image.save(&filename) {
             return Err(failed_precondition_error(
                 format!("Error saving file: {:?}", x).as_str()));
-            }
         }
+        info!("Saved image {}", filename);
+        if let Some(wcs) = wcs {
+            write_wcs_file(Path::new(&format!("{}.wcs", basename)), &wcs, width, height)?;
+        }
+        Ok(())
+    }
+
+    // Returns the world coordinate solution for an image of the given size
+    // from its plate solution, or None if `solve_result` is not a successful
+    // solve.
+    pub fn wcs_solution(solve_result: &SolveResultProto, width: u32, height: u32)
+                        -> Option<WcsSolution> {
+        if solve_result.status != Some(SolveStatus::MatchFound.into()) {
+            return None;
+        }
+        let center = solve_result.image_center_coords.as_ref()?;
+        let pixel_scale = solve_result.fov? as f64 / width as f64;
+        Some(WcsSolution::new(center.ra as f64, center.dec as f64,
+                              solve_result.roll.unwrap_or(0.0) as f64,
+                              pixel_scale, width, height))
+    }
+
+    // Writes the full resolution `captured_image` as a FITS file in the
+    // current directory. Capture parameters are recorded in the header, along
+    // with the WCS derived from `solve_result` if given and successful.
+    pub fn write_fits_file(captured_image: &CapturedImage,
+                           solve_result: Option<&SolveResultProto>)
+                           -> Result<(), CanonicalError> {
+        let image: &GrayImage = &captured_image.image;
+        let (width, height) = image.dimensions();
+        let params = &captured_image.capture_params;
+        let readout_time: DateTime<Utc> = DateTime::from(captured_image.readout_time);
+        let mut cards = vec![
+            string_card("DATE-OBS",
+                        &readout_time.format("%Y-%m-%dT%H:%M:%S%.3f").to_string(),
+                        "UTC readout time"),
+            number_card("EXPTIME", params.exposure_duration.as_secs_f64(),
+                        "Exposure (s)"),
+            integer_card("GAIN", params.gain.value() as i64, "Camera gain setting"),
+            integer_card("OFFSET", params.offset.value() as i64, "Camera offset setting"),
+            number_card("CCD-TEMP", captured_image.temperature.0 as f64,
+                        "Camera temperature (C)"),
+            string_card("CREATOR", "Cedar", ""),
+        ];
+        if let Some(wcs) = solve_result.and_then(|sr| Self::wcs_solution(sr, width, height)) {
+            cards.extend(wcs.cards());
+        }
+
+        let datetime_local: DateTime<Local> = DateTime::from(readout_time);
+        let filename = format!("img_{}ms_{}.fits",
+                               params.exposure_duration.as_millis(),
+                               datetime_local.format("%Y%m%d_%H%M%S"));
+        write_fits(Path::new(&filename), image, &cards)?;
+        info!("Saved FITS image {}", filename);
+        Ok(())
     }
 
     pub async fn solve(&self, solve_request: SolveRequest)
@@ -376,6 +582,127 @@ impl SolveEngine {
         }
     }
 
+    // Returns the fraction of the image area spanned by the bounding box of the
+    // plate solution's matched stars. A small value means the solution is
+    // based on only part of the field (e.g. partial obstruction or focus
+    // tilt). None if there are fewer than two matched stars.
+    fn coverage_fraction(tsr: &SolveResultProto, width: u32, height: u32) -> Option<f32> {
+        if tsr.matched_stars.len() < 2 {
+            return None;
+        }
+        let mut min_x = f32::MAX;
+        let mut max_x = f32::MIN;
+        let mut min_y = f32::MAX;
+        let mut max_y = f32::MIN;
+        for matched_star in &tsr.matched_stars {
+            let image_coord = matched_star.image_coord.as_ref().unwrap();
+            min_x = f32::min(min_x, image_coord.x);
+            max_x = f32::max(max_x, image_coord.x);
+            min_y = f32::min(min_y, image_coord.y);
+            max_y = f32::max(max_y, image_coord.y);
+        }
+        Some((max_x - min_x) * (max_y - min_y) / (width as f32 * height as f32))
+    }
+
+    // Returns the angular residuals (arcseconds) of the plate solution's
+    // matched stars: the distance between each star's catalog position and its
+    // image position mapped onto the sky. The mapping uses the solution's FOV
+    // and distortion (per tetra3's radial model) and a best-fit rotation.
+    // None if fewer than three stars matched.
+    fn match_residuals(tsr: &SolveResultProto, width: u32, height: u32)
+                       -> Option<Vec<f32>> {
+        if tsr.matched_stars.len() < 3 {
+            return None;
+        }
+        let center = tsr.image_center_coords.as_ref()?;
+        let ra0 = (center.ra as f64).to_radians();
+        let dec0 = (center.dec as f64).to_radians();
+        let k = tsr.distortion.unwrap_or(0.0) as f64;
+        let half_width = width as f64 / 2.0;
+        let half_height = height as f64 / 2.0;
+        // Tangent plane units per (undistorted) pixel.
+        let scale = ((tsr.fov? as f64).to_radians() / 2.0).tan() / half_width;
+
+        // (image, sky) tangent plane positions of each matched star. The image
+        // positions are oriented with east left and north up, prior to the
+        // rotation that we fit below.
+        let mut pairs = Vec::<((f64, f64), (f64, f64))>::with_capacity(
+            tsr.matched_stars.len());
+        for matched_star in &tsr.matched_stars {
+            let image_coord = matched_star.image_coord.as_ref()?;
+            let celestial_coord = matched_star.celestial_coord.as_ref()?;
+            let Some(sky) = tangent_plane_from_equatorial(
+                (celestial_coord.ra as f64).to_radians(),
+                (celestial_coord.dec as f64).to_radians(), ra0, dec0) else {
+                continue;
+            };
+            let dx = image_coord.x as f64 - half_width;
+            let dy = image_coord.y as f64 - half_height;
+            let r = (dx * dx + dy * dy).sqrt() / half_width;
+            let undistort = (1.0 - k * r * r) / (1.0 - k) * scale;
+            pairs.push(((-dx * undistort, -dy * undistort), sky));
+        }
+
+        // Least squares rotation from image to sky.
+        let mut sum_cos = 0.0;
+        let mut sum_sin = 0.0;
+        for ((u, v), (xi, eta)) in &pairs {
+            sum_cos += u * xi + v * eta;
+            sum_sin += u * eta - v * xi;
+        }
+        let norm = sum_cos.hypot(sum_sin);
+        if norm == 0.0 {
+            return None;
+        }
+        let (cos_t, sin_t) = (sum_cos / norm, sum_sin / norm);
+        Some(pairs.iter().map(|((u, v), (xi, eta))| {
+            let x = u * cos_t - v * sin_t;
+            let y = u * sin_t + v * cos_t;
+            ((x - xi).hypot(y - eta).to_degrees() * 3600.0) as f32
+        }).collect())
+    }
+
+    // Returns why a solve did not succeed, or None if it did. `tsr` is
+    // omitted if the solve was not attempted.
+    fn solve_failure_reason(num_detected: usize, tsr: Option<&SolveResultProto>)
+                            -> Option<cedar::SolveFailureReason> {
+        use cedar::SolveFailureReason as Reason;
+        if num_detected == 0 {
+            return Some(Reason::SolveFailureNoStars);
+        }
+        let Some(tsr) = tsr else {
+            return Some(Reason::SolveFailureTooFewStars);
+        };
+        match SolveStatus::try_from(tsr.status.unwrap_or(0)) {
+            Ok(SolveStatus::MatchFound) => None,
+            Ok(SolveStatus::TooFew) => Some(Reason::SolveFailureTooFewStars),
+            Ok(SolveStatus::Timeout) => Some(Reason::SolveFailureTimeout),
+            Ok(SolveStatus::Cancelled) => Some(Reason::SolveFailureCancelled),
+            Ok(SolveStatus::NoMatch) => Some(Reason::SolveFailureNoMatch),
+            Ok(SolveStatus::Unspecified) | Err(_) => Some(Reason::SolveFailureUnspecified),
+        }
+    }
+
+    fn solve_diagnostics(num_detected: usize, tsr: &SolveResultProto,
+                         width: u32, height: u32) -> cedar::SolveDiagnostics {
+        let mut diagnostics = cedar::SolveDiagnostics{
+            num_detected: Some(num_detected as i32),
+            ..Default::default()
+        };
+        if tsr.status != Some(SolveStatus::MatchFound.into()) {
+            return diagnostics;
+        }
+        diagnostics.num_matched = tsr.matches;
+        if let Some(mut residuals) = Self::match_residuals(tsr, width, height) {
+            residuals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let n = residuals.len();
+            diagnostics.residual_median = Some(residuals[n / 2]);
+            diagnostics.residual_p90 = Some(residuals[(n * 9 / 10).min(n - 1)]);
+            diagnostics.residual_max = Some(residuals[n - 1]);
+        }
+        diagnostics
+    }
+
     async fn worker(
         client: Arc<tokio::sync::Mutex<Tetra3Client<tonic::transport::Channel>>>,
         state: Arc<Mutex<SolveState>>,
@@ -386,11 +713,15 @@ impl SolveEngine {
         debug!("Starting solve engine");
         // Keep track of when we started the solve cycle.
         let mut last_result_time: Option<Instant> = None;
+        // Don't rebase exposure until the solve success statistics reflect
+        // the current session.
+        let mut last_exposure_rebase = Instant::now();
         loop {
             let update_interval: Duration;
             {
                 let mut locked_state = state.lock().unwrap();
-                update_interval = locked_state.update_interval;
+                update_interval = max(locked_state.update_interval,
+                                      locked_state.min_solve_interval);
                 if locked_state.stop_request {
                     debug!("Stopping solve engine");
                     locked_state.stop_request = false;
@@ -417,22 +748,37 @@ impl SolveEngine {
                 let mut locked_state = state.lock().unwrap();
                 locked_state.solve_interval_stats.add_value(elapsed.as_secs_f64());
             }
+            state.lock().unwrap().solve_rate.add_event(SystemTime::now());
             last_result_time = Some(now);
 
             let detect_result: DetectResult;
             let mut solve_request = SolveRequest::default();
             let minimum_stars;
             let frame_id;
+            let centroid_radius;
             let mut slew_request = None;
             let mut boresight_image: Option<GrayImage> = None;
             let mut boresight_image_region: Option<Rect> = None;
+            let blind_solve;
+            // FOV estimate in effect when a blind solve was started. The state
+            // lock is released during the solve, and the estimate may be
+            // reset or recalibrated meanwhile.
+            let blind_solve_prior_fov;
             {
                 let locked_state = state.lock().unwrap();
                 minimum_stars = locked_state.minimum_stars;
+                blind_solve = locked_state.fov_estimate.is_some() &&
+                    locked_state.frames_since_solve >= BLIND_SOLVE_FAILURE_THRESHOLD &&
+                    locked_state.last_blind_solve.is_none_or(
+                        |t| t.elapsed() >= BLIND_SOLVE_INTERVAL);
+                blind_solve_prior_fov =
+                    if blind_solve { locked_state.fov_estimate } else { None };
+                let fov_estimate =
+                    if blind_solve { None } else { locked_state.fov_estimate };
 
                 // Set up SolveRequest.
-                solve_request.fov_estimate = locked_state.fov_estimate;
-                match locked_state.fov_estimate {
+                solve_request.fov_estimate = fov_estimate;
+                match fov_estimate {
                     Some(fov) => {
                         solve_request.fov_max_error = Some(fov / 10.0);
                         solve_request.match_max_error = None;
@@ -446,7 +792,11 @@ impl SolveEngine {
                 solve_request.match_radius = Some(locked_state.match_radius);
                 solve_request.match_threshold = Some(locked_state.match_threshold);
 
-                let solve_timeout = locked_state.solve_timeout.as_secs_f64();
+                let solve_timeout = if blind_solve {
+                    BLIND_SOLVE_TIMEOUT.as_secs_f64()
+                } else {
+                    locked_state.solve_timeout.as_secs_f64()
+                };
                 let solve_timeout_int = solve_timeout as i64;
                 let solve_timeout_frac = solve_timeout - solve_timeout_int as f64;
                 solve_request.solve_timeout = Some(prost_types::Duration {
@@ -465,6 +815,10 @@ impl SolveEngine {
                 solve_request.distortion = Some(locked_state.distortion);
                 solve_request.return_matches = locked_state.return_matches;
                 frame_id = locked_state.frame_id;
+                centroid_radius = match fov_estimate {
+                    Some(fov) => locked_state.centroid_radius.map(|r| (r, fov)),
+                    None => None,
+                };
             }
             // Get the most recent star detection result.
             if let Some(delay_est) = detect_engine.lock().await.estimate_delay(frame_id) {
@@ -479,15 +833,37 @@ impl SolveEngine {
             // Plate-solve using the recently detected stars.
             let process_start_time = Instant::now();
 
+            // Star candidates are in order of decreasing brightness; keep that
+            // order within the near and far groups.
+            let mut far_centroids = Vec::<ImageCoord>::new();
+            let mut radius_pixels_sq = None;
+            let mut center = (width as f32 / 2.0, height as f32 / 2.0);
+            if let Some((radius, fov)) = centroid_radius {
+                let radius_pixels = radius / fov * width as f32;
+                radius_pixels_sq = Some(radius_pixels * radius_pixels);
+                if let Some(bp) = solve_request.target_pixels.first() {
+                    center = (bp.x, bp.y);
+                }
+            }
             for sc in &detect_result.star_candidates {
-                solve_request.star_centroids.push(ImageCoord{x: sc.centroid_x,
-                                                             y: sc.centroid_y});
+                let centroid = ImageCoord{x: sc.centroid_x, y: sc.centroid_y};
+                if let Some(r_sq) = radius_pixels_sq {
+                    let dx = sc.centroid_x - center.0;
+                    let dy = sc.centroid_y - center.1;
+                    if dx * dx + dy * dy > r_sq {
+                        far_centroids.push(centroid);
+                        continue;
+                    }
+                }
+                solve_request.star_centroids.push(centroid);
             }
+            solve_request.star_centroids.extend(far_centroids);
             solve_request.image_width = width as i32;
             solve_request.image_height = height as i32;
 
             let mut tetra3_solve_result: Option<SolveResultProto> = None;
             let mut solve_finish_time: Option<SystemTime> = None;
+            let mut auto_save = false;
             if detect_result.star_candidates.len() >= minimum_stars as usize {
                 {
                     let mut locked_state = state.lock().unwrap();
@@ -497,6 +873,12 @@ impl SolveEngine {
                         let solve_duration = Duration::from_secs_f64(recent_stats.min);
                         locked_state.eta = Some(Instant::now() + solve_duration);
                     }
+                    if blind_solve {
+                        info!("{} consecutive solve failures; trying without FOV estimate",
+                              locked_state.frames_since_solve);
+                        locked_state.blind_solve_in_progress = true;
+                        locked_state.last_blind_solve = Some(Instant::now());
+                    }
                 }
                 match Self::solve_with_client(client.clone(), solve_request).await {
                     Err(e) => {
@@ -512,6 +894,35 @@ impl SolveEngine {
 
             let elapsed = process_start_time.elapsed();
             let mut locked_state = state.lock().unwrap();
+            let solved = tetra3_solve_result.as_ref().is_some_and(
+                |tsr| tsr.status == Some(SolveStatus::MatchFound.into()));
+            let mut solve_coverage_fraction = None;
+            let solve_diagnostics = tetra3_solve_result.as_ref().map(|tsr| {
+                Self::solve_diagnostics(detect_result.star_candidates.len(), tsr,
+                                        width, height)
+            });
+            if solved {
+                if let Some(prior_fov) = blind_solve_prior_fov {
+                    if let Some(fov) = tetra3_solve_result.as_ref().unwrap().fov {
+                        if locked_state.fov_estimate == Some(prior_fov) {
+                            info!("Blind solve succeeded; FOV is {:.2} degrees (was {:.2})",
+                                  fov, prior_fov);
+                            locked_state.fov_estimate = Some(fov);
+                            locked_state.blind_solve_fov = Some(fov);
+                        } else {
+                            info!("Blind solve succeeded; FOV is {:.2} degrees, but the \
+                                   FOV estimate changed during the solve; keeping it",
+                                  fov);
+                        }
+                    }
+                }
+                locked_state.frames_since_solve = 0;
+                locked_state.blind_solve_in_progress = false;
+                solve_coverage_fraction = Self::coverage_fraction(
+                    tetra3_solve_result.as_ref().unwrap(), width, height);
+            } else {
+                locked_state.frames_since_solve += 1;
+            }
             if tetra3_solve_result.is_none() {
                 locked_state.solve_attempt_stats.add_value(0.0);
                 solution_callback(Some(detect_result.clone()), None);
@@ -526,6 +937,15 @@ impl SolveEngine {
                     locked_state.slew_target =
                         solution_callback(Some(detect_result.clone()), Some(tsr.clone()));
 
+                    if locked_state.auto_save_images &&
+                        (locked_state.last_auto_save.is_none() ||
+                         locked_state.last_auto_save.unwrap().elapsed() >=
+                         locked_state.auto_save_interval)
+                    {
+                        locked_state.last_auto_save = Some(Instant::now());
+                        auto_save = true;
+                    }
+
                     if let Some(ref mut slew_req) = slew_request {
                         let coords;
                         if tsr.target_coords.len() > 0 {
@@ -623,6 +1043,8 @@ impl SolveEngine {
                 locked_state.solve_latency_stats.add_value(elapsed.as_secs_f64());
             }
             // Post the result.
+            let solve_failure_reason = Self::solve_failure_reason(
+                detect_result.star_candidates.len(), tetra3_solve_result.as_ref());
             locked_state.plate_solution = Some(PlateSolution{
                 detect_result,
                 tetra3_solve_result,
@@ -635,7 +1057,59 @@ impl SolveEngine {
                 solve_latency_stats: locked_state.solve_latency_stats.value_stats.clone(),
                 solve_attempt_stats: locked_state.solve_attempt_stats.value_stats.clone(),
                 solve_success_stats: locked_state.solve_success_stats.value_stats.clone(),
+                solve_rate: locked_state.solve_rate.rate(),
+                frames_since_solve: locked_state.frames_since_solve,
+                solve_coverage_fraction,
+                solve_diagnostics,
+                solve_failure_reason,
             });
+
+            let rebase_exposure = locked_state.exposure_rebase &&
+                last_exposure_rebase.elapsed() >= EXPOSURE_REBASE_INTERVAL &&
+                locked_state.solve_success_stats.value_stats.recent.as_ref().is_some_and(
+                    |r| r.mean < EXPOSURE_REBASE_SUCCESS_FRACTION);
+
+            // Save/record the image (if called for) without holding our state
+            // lock.
+            let frame_recorder = locked_state.frame_recorder.clone();
+            let fov_estimate = locked_state.fov_estimate;
+            let distortion = locked_state.distortion;
+            let plate_solution =
+                if auto_save || frame_recorder.is_some() {
+                    locked_state.plate_solution.clone()
+                } else {
+                    None
+                };
+            drop(locked_state);
+            if auto_save {
+                let ps = plate_solution.as_ref().unwrap();
+                if let Err(e) = Self::write_image_file(&ps.detect_result.captured_image,
+                                                       ps.tetra3_solve_result.as_ref()) {
+                    // Most likely the disk is full. Don't keep trying.
+                    warn!("Disabling image auto-save: {:?}", e);
+                    state.lock().unwrap().auto_save_images = false;
+                }
+            }
+            if let Some(frame_recorder) = frame_recorder {
+                let ps = plate_solution.as_ref().unwrap();
+                let metadata = FrameMetadata{
+                    star_count: ps.detect_result.star_candidates.len(),
+                    noise_estimate: ps.detect_result.noise_estimate,
+                    fov_estimate,
+                    distortion,
+                    solve_result: ps.tetra3_solve_result.as_ref(),
+                };
+                if let Err(e) = frame_recorder.lock().unwrap().record(
+                    &ps.detect_result.captured_image, &metadata)
+                {
+                    warn!("Disabling frame recording: {:?}", e);
+                    state.lock().unwrap().frame_recorder = None;
+                }
+            }
+            if rebase_exposure {
+                last_exposure_rebase = Instant::now();
+                detect_engine.lock().await.rebase_calibrated_exposure_duration().await;
+            }
         }  // loop.
     }
 }
@@ -682,4 +1156,23 @@ pub struct PlateSolution {
 
     // Fraction of attempted plate solves succeeded.
     pub solve_success_stats: cedar::ValueStats,
+
+    // Solve cycles per second, since the session stats were last reset. None
+    // until there have been two cycles.
+    pub solve_rate: Option<f64>,
+
+    // Number of solve cycles since the most recent successful solve; zero if
+    // `tetra3_solve_result` is a successful solve.
+    pub frames_since_solve: i32,
+
+    // See the corresponding field in FrameResult. Omitted if
+    // `tetra3_solve_result` is not a successful solve.
+    pub solve_coverage_fraction: Option<f32>,
+
+    // See the corresponding field in FrameResult. Omitted if a solve was not
+    // attempted.
+    pub solve_diagnostics: Option<cedar::SolveDiagnostics>,
+
+    // Omitted if `tetra3_solve_result` is a successful solve.
+    pub solve_failure_reason: Option<cedar::SolveFailureReason>,
 }
@@ -0,0 +1,170 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use std::time::SystemTime;
+
+use crate::cedar::{SpiralSearchAdvice, SpiralSearchWaypoint};
+use crate::tetra3_server::CelestialCoord;
+
+// Adjacent search positions are spaced by this fraction of the field of view,
+// so that successive fields overlap.
+const STEP_FOV_FRACTION: f32 = 0.75;
+
+// Helps a manual-mount user who has lost the sky (e.g. bumped the telescope
+// or slewed far during a cloud outage). After several consecutive failed
+// plate solves, suggests a square spiral of positions to visit, centered on
+// the last successfully solved position.
+pub struct SpiralSearchAdvisor {
+    // Number of consecutive failed solves before advice is given.
+    failure_threshold: usize,
+
+    // Number of positions in the suggested spiral.
+    num_waypoints: usize,
+
+    consecutive_failures: usize,
+
+    // Most recent successful solve.
+    last_known_position: Option<(CelestialCoord, SystemTime)>,
+}
+
+impl SpiralSearchAdvisor {
+    pub fn new(failure_threshold: usize, num_waypoints: usize) -> Self {
+        SpiralSearchAdvisor{failure_threshold, num_waypoints,
+                            consecutive_failures: 0,
+                            last_known_position: None}
+    }
+
+    // Forget everything, e.g. when solving is stopped for SETUP mode.
+    pub fn reset(&mut self) {
+        self.consecutive_failures = 0;
+        self.last_known_position = None;
+    }
+
+    // Called for each successful solve.
+    pub fn solved(&mut self, boresight: &CelestialCoord, readout_time: SystemTime) {
+        self.consecutive_failures = 0;
+        self.last_known_position = Some((boresight.clone(), readout_time));
+    }
+
+    // Called for each failed solve.
+    pub fn failed(&mut self) {
+        self.consecutive_failures += 1;
+    }
+
+    // Returns the position (and its readout time) around which the spiral is
+    // centered, if advice is currently warranted.
+    pub fn search_center(&self) -> Option<(CelestialCoord, SystemTime)> {
+        if self.consecutive_failures < self.failure_threshold {
+            return None;
+        }
+        self.last_known_position.clone()
+    }
+
+    // Returns the spiral search guidance, if advice is currently warranted.
+    // `fov` is the horizontal field of view in degrees. `center_tilt` is the
+    // search center's position on the mount's tilt axis (declination for an
+    // equatorial mount; altitude for alt/az), in degrees; it is used to scale
+    // rotation axis offsets.
+    pub fn get_advice(&self, fov: f32, center_tilt: f32) -> Option<SpiralSearchAdvice> {
+        let (center, _time) = self.search_center()?;
+        let step_size = fov * STEP_FOV_FRACTION;
+        // Near the pole of the rotation axis a given sky distance needs a large
+        // rotation; cap the scaling.
+        let rotation_scale = 1.0 / center_tilt.to_radians().cos().abs().max(0.1);
+        let waypoints = spiral_offsets(self.num_waypoints).into_iter().map(
+            |(x, y)| SpiralSearchWaypoint{
+                offset_rotation_axis: x as f32 * step_size * rotation_scale,
+                offset_tilt_axis: (y as f32 * step_size).clamp(
+                    -90.0 - center_tilt, 90.0 - center_tilt),
+            }).collect();
+        Some(SpiralSearchAdvice{
+            last_known_position: Some(center),
+            failed_solve_count: self.consecutive_failures as i32,
+            step_size,
+            waypoints,
+        })
+    }
+}
+
+// Returns the first `count` positions, in units of the step size, of a square
+// spiral starting adjacent to (0, 0): (1, 0), (1, 1), (0, 1), (-1, 1), ...
+fn spiral_offsets(count: usize) -> Vec<(i32, i32)> {
+    let mut offsets = Vec::with_capacity(count);
+    let (mut x, mut y) = (0, 0);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg_length = 1;
+    let mut direction = 0;
+    while offsets.len() < count {
+        // Each leg length is used for two consecutive legs.
+        for _ in 0..2 {
+            let (dx, dy) = directions[direction];
+            for _ in 0..leg_length {
+                if offsets.len() == count {
+                    return offsets;
+                }
+                x += dx;
+                y += dy;
+                offsets.push((x, y));
+            }
+            direction = (direction + 1) % 4;
+        }
+        leg_length += 1;
+    }
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate approx;
+    use approx::assert_abs_diff_eq;
+    use super::*;
+
+    #[test]
+    fn test_spiral_offsets() {
+        assert_eq!(spiral_offsets(0), Vec::<(i32, i32)>::new());
+        assert_eq!(spiral_offsets(8),
+                   vec![(1, 0), (1, 1), (0, 1), (-1, 1),
+                        (-1, 0), (-1, -1), (0, -1), (1, -1)]);
+        assert_eq!(spiral_offsets(10)[9], (2, -1));
+    }
+
+    #[test]
+    fn test_advisor() {
+        let mut advisor = SpiralSearchAdvisor::new(3, 8);
+        let now = SystemTime::now();
+        // No advice without a known position.
+        for _ in 0..5 {
+            advisor.failed();
+        }
+        assert!(advisor.get_advice(10.0, 0.0).is_none());
+
+        advisor.solved(&CelestialCoord{ra: 100.0, dec: 60.0}, now);
+        advisor.failed();
+        advisor.failed();
+        assert!(advisor.get_advice(10.0, 60.0).is_none());
+        advisor.failed();
+        let advice = advisor.get_advice(10.0, 60.0).unwrap();
+        assert_eq!(advice.failed_solve_count, 3);
+        assert_abs_diff_eq!(advice.step_size, 7.5, epsilon = 0.001);
+        assert_eq!(advice.waypoints.len(), 8);
+        // Rotation offsets are scaled by 1/cos(tilt).
+        assert_abs_diff_eq!(advice.waypoints[0].offset_rotation_axis, 15.0,
+                            epsilon = 0.001);
+        assert_abs_diff_eq!(advice.waypoints[0].offset_tilt_axis, 0.0,
+                            epsilon = 0.001);
+        assert_abs_diff_eq!(advice.waypoints[2].offset_tilt_axis, 7.5,
+                            epsilon = 0.001);
+
+        // A success ends the advice.
+        advisor.solved(&CelestialCoord{ra: 101.0, dec: 60.0}, now);
+        assert!(advisor.get_advice(10.0, 60.0).is_none());
+
+        advisor.failed();
+        advisor.failed();
+        advisor.failed();
+        assert!(advisor.get_advice(10.0, 60.0).is_some());
+        advisor.reset();
+        assert!(advisor.get_advice(10.0, 60.0).is_none());
+    }
+
+}  // mod tests.
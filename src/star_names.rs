@@ -0,0 +1,138 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Common names of bright stars. The solver's catalog identifies stars only by
+// catalog number, so names are found by position instead.
+
+use crate::astro_util::angular_separation;
+
+// A catalog star within this many degrees of a table entry is taken to be
+// that star. Generous enough to absorb proper motion since J2000, and well
+// under the separation of any two stars in the table.
+const MATCH_TOLERANCE: f64 = 0.1;
+
+// (name, J2000 RA degrees, J2000 Dec degrees). Includes the named stars
+// brighter than about magnitude 2.5.
+const STAR_NAMES: &[(&str, f64, f64)] = &[
+    ("Sirius", 101.287, -16.716),
+    ("Canopus", 95.988, -52.696),
+    ("Arcturus", 213.915, 19.182),
+    ("Rigil Kentaurus", 219.902, -60.834),
+    ("Vega", 279.235, 38.784),
+    ("Capella", 79.172, 45.998),
+    ("Rigel", 78.634, -8.202),
+    ("Procyon", 114.825, 5.225),
+    ("Achernar", 24.429, -57.237),
+    ("Betelgeuse", 88.793, 7.407),
+    ("Hadar", 210.956, -60.373),
+    ("Altair", 297.696, 8.868),
+    ("Acrux", 186.650, -63.099),
+    ("Aldebaran", 68.980, 16.509),
+    ("Antares", 247.352, -26.432),
+    ("Spica", 201.298, -11.161),
+    ("Pollux", 116.329, 28.026),
+    ("Fomalhaut", 344.413, -29.622),
+    ("Deneb", 310.358, 45.280),
+    ("Mimosa", 191.930, -59.689),
+    ("Regulus", 152.093, 11.967),
+    ("Adhara", 104.656, -28.972),
+    ("Castor", 113.650, 31.888),
+    ("Gacrux", 187.791, -57.113),
+    ("Shaula", 263.402, -37.104),
+    ("Bellatrix", 81.283, 6.350),
+    ("Elnath", 81.573, 28.608),
+    ("Miaplacidus", 138.300, -69.717),
+    ("Alnilam", 84.053, -1.202),
+    ("Alnair", 332.058, -46.961),
+    ("Alnitak", 85.190, -1.943),
+    ("Alioth", 193.507, 55.960),
+    ("Dubhe", 165.932, 61.751),
+    ("Mirfak", 51.081, 49.861),
+    ("Wezen", 107.098, -26.393),
+    ("Sargas", 264.330, -42.998),
+    ("Kaus Australis", 276.043, -34.385),
+    ("Avior", 125.628, -59.509),
+    ("Alkaid", 206.885, 49.313),
+    ("Menkalinan", 89.882, 44.948),
+    ("Atria", 252.166, -69.028),
+    ("Alhena", 99.428, 16.399),
+    ("Peacock", 306.412, -56.735),
+    ("Polaris", 37.955, 89.264),
+    ("Mirzam", 95.675, -17.956),
+    ("Alphard", 141.897, -8.659),
+    ("Hamal", 31.793, 23.463),
+    ("Algieba", 154.993, 19.841),
+    ("Diphda", 10.897, -17.987),
+    ("Nunki", 283.816, -26.297),
+    ("Menkent", 211.671, -36.370),
+    ("Mirach", 17.433, 35.621),
+    ("Alpheratz", 2.097, 29.091),
+    ("Rasalhague", 263.734, 12.560),
+    ("Kochab", 222.676, 74.156),
+    ("Saiph", 86.939, -9.670),
+    ("Denebola", 177.265, 14.572),
+    ("Algol", 47.042, 40.956),
+    ("Almach", 30.975, 42.330),
+    ("Scheat", 345.944, 28.083),
+    ("Markab", 346.190, 15.205),
+    ("Enif", 326.047, 9.875),
+    ("Eltanin", 269.152, 51.489),
+    ("Schedar", 10.127, 56.537),
+    ("Caph", 2.295, 59.150),
+    ("Merak", 165.460, 56.383),
+    ("Mizar", 200.981, 54.925),
+    ("Phecda", 178.458, 53.695),
+    ("Alderamin", 319.645, 62.586),
+    ("Unukalhai", 236.067, 6.426),
+    ("Zubeneschamali", 229.252, -9.383),
+    ("Sadr", 305.557, 40.257),
+    ("Dschubba", 240.083, -22.622),
+    ("Ankaa", 6.571, -42.306),
+    ("Suhail", 136.999, -43.433),
+    ("Aspidiske", 139.273, -59.275),
+    ("Naos", 120.896, -40.003),
+    ("Mintaka", 83.002, -0.299),
+    ("Arneb", 83.183, -17.822),
+    ("Izar", 221.247, 27.074),
+    ("Alphecca", 233.672, 26.715),
+    ("Sabik", 257.595, -15.725),
+    ("Menkar", 45.570, 4.090),
+    ("Algenib", 3.309, 15.184),
+];
+
+/// Returns the common name of the star at the given position (degrees), if it
+/// is one of the named bright stars.
+pub fn star_name(ra: f64, dec: f64) -> Option<&'static str> {
+    STAR_NAMES.iter().find(|(_name, star_ra, star_dec)| {
+        angular_separation(ra.to_radians(), dec.to_radians(),
+                           star_ra.to_radians(), star_dec.to_radians())
+            .to_degrees() < MATCH_TOLERANCE
+    }).map(|(name, _, _)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_star_name() {
+        assert_eq!(star_name(279.234, 38.783), Some("Vega"));
+        assert_eq!(star_name(37.95, 89.26), Some("Polaris"));
+        // Alcor, 12 arcmin from Mizar.
+        assert_eq!(star_name(201.306, 54.988), None);
+        assert_eq!(star_name(0.0, 0.0), None);
+    }
+
+    #[test]
+    fn test_entries_distinct() {
+        for (i, (name, ra, dec)) in STAR_NAMES.iter().enumerate() {
+            for (other, other_ra, other_dec) in &STAR_NAMES[i + 1..] {
+                let separation = angular_separation(
+                    ra.to_radians(), dec.to_radians(),
+                    other_ra.to_radians(), other_dec.to_radians()).to_degrees();
+                assert!(separation > 2.0 * MATCH_TOLERANCE, "{} {}", name, other);
+            }
+        }
+    }
+
+}  // mod tests.
@@ -0,0 +1,174 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Implements the server side of Stellarium's telescope control protocol, so
+// that Stellarium (and other planetarium apps that speak it) can display
+// Cedar's plate-solved position and request gotos.
+//
+// The protocol is binary, little-endian, over TCP. The server periodically
+// sends MessageCurrentPosition:
+//   LENGTH (u16): 24
+//   TYPE   (u16): 0
+//   TIME   (i64): microseconds since the Unix epoch
+//   RA     (u32): 0x100000000 corresponds to 24h
+//   DEC    (i32): 0x40000000 corresponds to +90 degrees
+//   STATUS (i32): 0 means OK
+// The client sends MessageGoto:
+//   LENGTH (u16): 20
+//   TYPE   (u16): 0
+//   TIME   (i64)
+//   RA     (u32)
+//   DEC    (i32)
+
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use log::{debug, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::position_reporter::TelescopePosition;
+
+const CURRENT_POSITION_LENGTH: usize = 24;
+const GOTO_LENGTH: usize = 20;
+
+// How often the current position is sent to each client.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Accepts Stellarium clients on `bind_addr`:`port`, serving each one
+// concurrently. Runs until an error occurs in accepting connections.
+pub async fn run_stellarium_server(bind_addr: IpAddr, port: u16,
+                                   telescope_position: Arc<Mutex<TelescopePosition>>)
+                                   -> Result<(), io::Error> {
+    let listener = TcpListener::bind((bind_addr, port)).await?;
+    info!("Stellarium telescope server listening on {}:{}", bind_addr, port);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("Stellarium client connected from {:?}", addr);
+        let telescope_position = telescope_position.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(stream, telescope_position).await {
+                debug!("Stellarium client {:?} disconnected: {:?}", addr, e);
+            }
+        });
+    }
+}
+
+async fn serve_client(stream: TcpStream,
+                      telescope_position: Arc<Mutex<TelescopePosition>>)
+                      -> Result<(), io::Error> {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+    let mut goto_buf = [0u8; GOTO_LENGTH];
+    // Number of bytes of `goto_buf` received so far. We use read() rather than
+    // read_exact() because the latter is not cancellation safe within
+    // select!.
+    let mut filled = 0;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let message = {
+                    let locked_position = telescope_position.lock().unwrap();
+                    encode_current_position(
+                        locked_position.boresight_ra, locked_position.boresight_dec,
+                        SystemTime::now(),
+                        if locked_position.boresight_valid { 0 } else { -1 })
+                };
+                writer.write_all(&message).await?;
+            }
+            result = reader.read(&mut goto_buf[filled..]) => {
+                let count = result?;
+                if count == 0 {
+                    return Ok(());  // Client closed the connection.
+                }
+                filled += count;
+                if filled < GOTO_LENGTH {
+                    continue;
+                }
+                filled = 0;
+                match decode_goto(&goto_buf) {
+                    Some((ra, dec)) => {
+                        info!("Stellarium goto ra {:.4} dec {:.4}", ra, dec);
+                        telescope_position.lock().unwrap().start_slew(ra, dec);
+                    },
+                    None => {
+                        warn!("Ignoring unrecognized Stellarium message {:?}", goto_buf);
+                    },
+                }
+            }
+        }
+    }
+}
+
+// `ra` and `dec` are in degrees. `status` is zero if the position is current.
+pub fn encode_current_position(ra: f64, dec: f64, time: SystemTime, status: i32)
+                               -> [u8; CURRENT_POSITION_LENGTH] {
+    let time_micros = time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO).as_micros() as i64;
+    let ra_fraction = (ra / 360.0).rem_euclid(1.0);
+    let ra_int = (ra_fraction * 4294967296.0) as u64 as u32;
+    let dec_int = (dec / 90.0 * 1073741824.0) as i32;
+    let mut message = [0u8; CURRENT_POSITION_LENGTH];
+    message[0..2].copy_from_slice(&(CURRENT_POSITION_LENGTH as u16).to_le_bytes());
+    message[2..4].copy_from_slice(&0u16.to_le_bytes());
+    message[4..12].copy_from_slice(&time_micros.to_le_bytes());
+    message[12..16].copy_from_slice(&ra_int.to_le_bytes());
+    message[16..20].copy_from_slice(&dec_int.to_le_bytes());
+    message[20..24].copy_from_slice(&status.to_le_bytes());
+    message
+}
+
+// Returns the (ra, dec) in degrees of a MessageGoto, or None if `message` is
+// not one.
+pub fn decode_goto(message: &[u8; GOTO_LENGTH]) -> Option<(f64, f64)> {
+    let length = u16::from_le_bytes([message[0], message[1]]);
+    let msg_type = u16::from_le_bytes([message[2], message[3]]);
+    if length as usize != GOTO_LENGTH || msg_type != 0 {
+        return None;
+    }
+    let ra_int = u32::from_le_bytes(message[12..16].try_into().unwrap());
+    let dec_int = i32::from_le_bytes(message[16..20].try_into().unwrap());
+    let ra = ra_int as f64 / 4294967296.0 * 360.0;
+    let dec = dec_int as f64 / 1073741824.0 * 90.0;
+    Some((ra, dec))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate approx;
+    use approx::assert_abs_diff_eq;
+    use super::*;
+
+    #[test]
+    fn test_encode_current_position() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_micros(1234567);
+        let message = encode_current_position(270.0, -45.0, time, 0);
+        assert_eq!(u16::from_le_bytes([message[0], message[1]]), 24);
+        assert_eq!(i64::from_le_bytes(message[4..12].try_into().unwrap()), 1234567);
+        assert_eq!(u32::from_le_bytes(message[12..16].try_into().unwrap()), 0xC0000000);
+        assert_eq!(i32::from_le_bytes(message[16..20].try_into().unwrap()), -0x20000000);
+        assert_eq!(i32::from_le_bytes(message[20..24].try_into().unwrap()), 0);
+
+        // RA of 360 wraps to 0.
+        let message = encode_current_position(360.0, 0.0, time, 0);
+        assert_eq!(u32::from_le_bytes(message[12..16].try_into().unwrap()), 0);
+    }
+
+    #[test]
+    fn test_decode_goto() {
+        let mut message = [0u8; GOTO_LENGTH];
+        message[0..2].copy_from_slice(&20u16.to_le_bytes());
+        message[12..16].copy_from_slice(&0x40000000u32.to_le_bytes());
+        message[16..20].copy_from_slice(&0x20000000i32.to_le_bytes());
+        let (ra, dec) = decode_goto(&message).unwrap();
+        assert_abs_diff_eq!(ra, 90.0, epsilon = 1.0e-6);
+        assert_abs_diff_eq!(dec, 45.0, epsilon = 1.0e-6);
+
+        // Wrong type.
+        message[2..4].copy_from_slice(&1u16.to_le_bytes());
+        assert!(decode_goto(&message).is_none());
+    }
+
+}  // mod tests.
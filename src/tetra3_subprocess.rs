@@ -5,7 +5,6 @@ use std::ffi::{OsStr, OsString};
 use std::io::{BufRead, BufReader};
 use std::process::{Command, Child, Stdio, ChildStdout, ChildStderr};
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
@@ -19,14 +18,18 @@ use canonical_error::{CanonicalError, failed_precondition_error};
 //   to info!/warn! logs.
 // * If the subprocess unexpectedly exits, log to error! and re-start
 //   the subprocess.
-// * We install a ^C handler, and ensure that the subprocess is killed
-//   before we exit.
+// * The subprocess is killed when we are stopped or dropped. The server's
+//   signal handling calls stop() before exiting, as process exit does not
+//   run destructors.
 
 pub struct Tetra3Subprocess {
     tetra3_script_path: OsString,
     tetra3_database: OsString,
     pid: Arc<Mutex<u32>>,
     stopping: Arc<Mutex<bool>>,
+    // Invoked (from the wait worker thread) after the subprocess has been
+    // re-spawned following an unexpected exit.
+    restart_callback: Arc<Mutex<Option<Box<dyn Fn() + Send>>>>,
 }
 
 impl Drop for Tetra3Subprocess {
@@ -98,41 +101,19 @@ impl Tetra3Subprocess {
     }
 
     fn make_wait_worker(&mut self, mut child: Child) {
-        let got_signal = Arc::new(AtomicBool::new(false));
-        let got_signal2 = got_signal.clone();
-        ctrlc::set_handler(move || {
-            info!("Got control-c");
-            got_signal2.store(true, Ordering::Relaxed);
-        }).unwrap();
-
         let tetra3_script_path = self.tetra3_script_path.clone();
         let tetra3_database = self.tetra3_database.clone();
         let pid = self.pid.clone();
         let stopping = self.stopping.clone();
+        let restart_callback = self.restart_callback.clone();
         thread::spawn(move || {
             loop {
                 let stdout_worker = Self::make_stdout_worker(child.stdout.take().unwrap());
                 let stderr_worker = Self::make_stderr_worker(child.stderr.take().unwrap());
-                let child_status;
-                loop {
-                    if got_signal.load(Ordering::Relaxed) {
-                        info!("Killing {:?}", tetra3_script_path);
-                        child.kill().unwrap();
-                        info!("Exiting");
-                        std::process::exit(-1);
-                    }
-                    match child.try_wait() {
-                        Ok(Some(status)) => {
-                            child_status = status;
-                            break;
-                        },
-                        Ok(None) => {
-                            thread::sleep(Duration::from_millis(10));
-                            continue;  // Wait again for signal or child exit.
-                        },
-                        Err(e) => panic!("Unexpected child.wait() error {:?}", e),
-                    }
-                }
+                let child_status = match child.wait() {
+                    Ok(status) => status,
+                    Err(e) => panic!("Unexpected child.wait() error {:?}", e),
+                };
                 stdout_worker.join().unwrap();
                 stderr_worker.join().unwrap();
                 if *stopping.lock().unwrap() {
@@ -144,6 +125,9 @@ impl Tetra3Subprocess {
                 // Re-spawn subprocess.
                 child = Self::make_child(&tetra3_script_path, &tetra3_database).unwrap();
                 *pid.lock().unwrap() = child.id();
+                if let Some(callback) = restart_callback.lock().unwrap().as_ref() {
+                    callback();
+                }
             }
         });
     }
@@ -159,12 +143,19 @@ impl Tetra3Subprocess {
             tetra3_script_path, tetra3_database,
             pid: Arc::new(Mutex::new(pid)),
             stopping: Arc::new(Mutex::new(false)),
+            restart_callback: Arc::new(Mutex::new(None)),
         };
         t3_subprocess.make_wait_worker(child);
         thread::sleep(Duration::from_secs(2));
         Ok(t3_subprocess)
     }
 
+    // Registers a function to be called whenever the subprocess is re-spawned
+    // after exiting unexpectedly.
+    pub fn set_restart_callback(&mut self, callback: Box<dyn Fn() + Send>) {
+        *self.restart_callback.lock().unwrap() = Some(callback);
+    }
+
     // tetra3_server.py traps SIGINT and uses this to cancel the in-progress solve.
     pub fn send_interrupt_signal(&mut self) {
         self.send_signal("INT");
@@ -0,0 +1,58 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+// Server side of the ThroughputTest RPC. The server only sinks and sources
+// bytes; throughput and round trip latency are measured by the client, which
+// is the only party that sees the whole transfer.
+
+use canonical_error::{CanonicalError, invalid_argument_error};
+
+use crate::cedar::{ThroughputTestRequest, ThroughputTestResult};
+
+// Returns the response to `req`, or an error if either direction exceeds
+// `max_bytes`.
+pub fn throughput_test_response(req: &ThroughputTestRequest, max_bytes: usize)
+                                -> Result<ThroughputTestResult, CanonicalError> {
+    if req.payload.len() > max_bytes {
+        return Err(invalid_argument_error(
+            format!("payload size {} exceeds maximum {}.",
+                    req.payload.len(), max_bytes).as_str()));
+    }
+    if req.response_size < 0 || req.response_size as usize > max_bytes {
+        return Err(invalid_argument_error(
+            format!("response_size must be in [0, {}]; got {}.",
+                    max_bytes, req.response_size).as_str()));
+    }
+    Ok(ThroughputTestResult{
+        received_size: req.payload.len() as i32,
+        payload: vec![0_u8; req.response_size as usize],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(payload_size: usize, response_size: i32) -> ThroughputTestRequest {
+        ThroughputTestRequest{payload: vec![7_u8; payload_size], response_size}
+    }
+
+    #[test]
+    fn test_throughput_test_response() {
+        let result = throughput_test_response(&request(100, 200), 1000).unwrap();
+        assert_eq!(result.received_size, 100);
+        assert_eq!(result.payload.len(), 200);
+
+        // Latency probe.
+        let result = throughput_test_response(&request(0, 0), 1000).unwrap();
+        assert_eq!(result.received_size, 0);
+        assert!(result.payload.is_empty());
+
+        // Limits are inclusive.
+        assert!(throughput_test_response(&request(1000, 1000), 1000).is_ok());
+        assert!(throughput_test_response(&request(1001, 0), 1000).is_err());
+        assert!(throughput_test_response(&request(0, 1001), 1000).is_err());
+        assert!(throughput_test_response(&request(0, -1), 1000).is_err());
+    }
+
+}  // mod tests.
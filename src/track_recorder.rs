@@ -0,0 +1,113 @@
+// Copyright (c) 2024 Steven Rosenthal smr@dt3.org
+// See LICENSE file in root directory for license terms.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use canonical_error::{CanonicalError, failed_precondition_error};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+
+use crate::tetra3_server::CelestialCoord;
+
+const HEADER: &str = "time,ra,dec,roll,rmse,alt,az";
+
+// Records the sequence of plate-solved positions to a CSV file, for
+// post-session analysis of tracking and drift. Each recording goes to a new
+// file in `dir`.
+pub struct TrackRecorder {
+    dir: PathBuf,
+
+    // Present while recording.
+    file: Option<(File, PathBuf)>,
+}
+
+impl TrackRecorder {
+    pub fn new(dir: &Path) -> Self {
+        TrackRecorder{dir: dir.to_path_buf(), file: None}
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.file.is_some()
+    }
+
+    // Starts a new recording file. If already recording, the current file is
+    // first closed.
+    pub fn start(&mut self) -> Result<(), CanonicalError> {
+        self.stop();
+        let now: DateTime<Utc> = SystemTime::now().into();
+        let path = self.dir.join(
+            format!("cedar_track_{}.csv", now.format("%Y%m%d_%H%M%S")));
+        let mut file = match fs::File::create(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(failed_precondition_error(
+                    format!("Could not create {:?}: {:?}", path, e).as_str()));
+            }
+        };
+        if let Err(e) = writeln!(file, "{}", HEADER) {
+            return Err(failed_precondition_error(
+                format!("Could not write {:?}: {:?}", path, e).as_str()));
+        }
+        info!("Recording plate solve track to {:?}", path);
+        self.file = Some((file, path));
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((file, path)) = self.file.take() {
+            if let Err(e) = file.sync_all() {
+                warn!("Error closing {:?}: {:?}", path, e);
+            }
+            info!("Finished recording plate solve track to {:?}", path);
+        }
+    }
+
+    // Appends a solved position, if recording. `alt_az` is in degrees.
+    pub fn record(&mut self, time: SystemTime, coords: &CelestialCoord,
+                  roll: Option<f32>, rmse: Option<f32>, alt_az: Option<(f64, f64)>) {
+        let Some((file, path)) = &mut self.file else {
+            return;
+        };
+        let line = format_track_line(time, coords, roll, rmse, alt_az);
+        if let Err(e) = writeln!(file, "{}", line) {
+            warn!("Error writing {:?}, stopping track recording: {:?}", path, e);
+            self.file = None;
+        }
+    }
+}
+
+fn format_track_line(time: SystemTime, coords: &CelestialCoord,
+                     roll: Option<f32>, rmse: Option<f32>,
+                     alt_az: Option<(f64, f64)>) -> String {
+    let datetime: DateTime<Utc> = time.into();
+    let optional = |v: Option<f64>| v.map_or(String::new(), |v| format!("{:.4}", v));
+    format!("{},{:.6},{:.6},{},{},{},{}",
+            datetime.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            coords.ra, coords.dec,
+            optional(roll.map(|r| r as f64)),
+            optional(rmse.map(|r| r as f64)),
+            optional(alt_az.map(|(alt, _)| alt)),
+            optional(alt_az.map(|(_, az)| az)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_track_line() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let coords = CelestialCoord{ra: 83.5, dec: -5.25};
+        assert_eq!(format_track_line(time, &coords, Some(10.0), Some(1.5),
+                                     Some((45.0, 180.5))),
+                   "2023-11-14T22:13:20.123Z,83.500000,-5.250000,\
+                    10.0000,1.5000,45.0000,180.5000");
+        assert_eq!(format_track_line(time, &coords, None, None, None),
+                   "2023-11-14T22:13:20.123Z,83.500000,-5.250000,,,,");
+    }
+
+}  // mod tests.